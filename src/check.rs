@@ -0,0 +1,131 @@
+//! `--check`: probe each configured source once and print a pass/fail table,
+//! without starting the server
+
+use anyhow::Result;
+use dart::config::{Config, ConfigSource, SourceConfig};
+use dart::sources;
+use std::io::Write;
+use std::time::Duration;
+
+/// Probe every source in the config from `source`, waiting up to
+/// `timeout` for each. Returns whether none failed.
+pub fn run<W: Write>(source: &ConfigSource, timeout: Duration, out: &mut W) -> Result<bool> {
+    let config = Config::load_from(source)?;
+    check(
+        &config.sources,
+        |source| sources::probe(source, timeout),
+        out,
+    )
+}
+
+/// Print one row per source with the result of `probe`: `None` means the
+/// source type can't be probed, which is reported but doesn't fail the check
+fn check<W, P>(sources: &[SourceConfig], probe: P, out: &mut W) -> Result<bool>
+where
+    W: Write,
+    P: Fn(&SourceConfig) -> Option<bool>,
+{
+    let width = sources
+        .iter()
+        .map(|source| source.name.len())
+        .chain(std::iter::once("SOURCE".len()))
+        .max()
+        .unwrap_or_default();
+    writeln!(out, "{:<width$}  {:<6}  RESULT", "SOURCE", "TYPE")?;
+
+    let mut failed = 0;
+    for source in sources {
+        let result = match probe(source) {
+            Some(true) => "ok",
+            Some(false) => {
+                failed += 1;
+                "FAIL"
+            }
+            None => "skipped (no probe for this type)",
+        };
+        let kind = format!("{:?}", source.source_type).to_lowercase();
+        writeln!(out, "{:<width$}  {:<6}  {}", source.name, kind, result)?;
+    }
+
+    writeln!(out)?;
+    if failed == 0 {
+        writeln!(out, "All {} source(s) passed", sources.len())?;
+    } else {
+        writeln!(out, "{} of {} source(s) failed", failed, sources.len())?;
+    }
+    Ok(failed == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dart::config::SourceType;
+
+    fn sources() -> Vec<SourceConfig> {
+        let config: Config = toml::from_str(
+            r#"
+            [server]
+
+            [[sources]]
+            name = "lobby"
+            type = "rtsp"
+            url = "rtsp://lobby.local/stream"
+
+            [[sources]]
+            name = "loading-dock"
+            type = "rtsp"
+            url = "rtsp://dock.local/stream"
+
+            [[sources]]
+            name = "feed"
+            type = "srt"
+            url = "srt://ingest.local:9000"
+
+            [[sources]]
+            name = "hdmi"
+            type = "v4l2"
+            device = "/dev/video0"
+            "#,
+        )
+        .unwrap();
+        config.sources
+    }
+
+    /// Stands in for the network: only the lobby camera and the capture card answer
+    fn mock_probe(source: &SourceConfig) -> Option<bool> {
+        match source.name.as_str() {
+            "feed" => None,
+            name => Some(name == "lobby" || name == "hdmi"),
+        }
+    }
+
+    #[test]
+    fn test_check_reports_each_source() {
+        let mut out = Vec::new();
+        let passed = check(&sources(), mock_probe, &mut out).unwrap();
+        assert!(!passed);
+
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines[0], "SOURCE        TYPE    RESULT");
+        assert_eq!(lines[1], "lobby         rtsp    ok");
+        assert_eq!(lines[2], "loading-dock  rtsp    FAIL");
+        assert_eq!(
+            lines[3],
+            "feed          srt     skipped (no probe for this type)"
+        );
+        assert_eq!(lines[4], "hdmi          v4l2    ok");
+        assert_eq!(lines[6], "1 of 4 source(s) failed");
+    }
+
+    #[test]
+    fn test_check_passes_when_nothing_fails() {
+        let mut out = Vec::new();
+        let reachable =
+            |source: &SourceConfig| (source.source_type != SourceType::Srt).then_some(true);
+        assert!(check(&sources(), reachable, &mut out).unwrap());
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .ends_with("All 4 source(s) passed\n"));
+    }
+}
@@ -0,0 +1,152 @@
+//! H.264 compatibility mount - serves an H.265 source again as H.264 at
+//! `/{name}/h264`, for players that can't decode H.265
+//!
+//! Pipeline: appsrc -> h265parse -> avdec_h265 -> x264enc -> h264parse -> appsink
+//!
+//! The source's `profile`/`level` apply here, since this is the H.264 output.
+//!
+//! The transcoder is fed from the source's frames, so the camera is still
+//! opened once. Frames are only decoded while the mount has viewers.
+
+use crate::config::EncodeConfig;
+use crate::rtsp::{FrameBus, FrameData, StreamKind};
+use crate::sources;
+use anyhow::{Context, Result};
+use gstreamer::prelude::*;
+use gstreamer_app::{AppSink, AppSrc};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// How often a waiting transcoder re-asks the source for a keyframe
+const KEYFRAME_POLL: Duration = Duration::from_millis(100);
+
+/// Start transcoding `source`'s H.265 frames into `output`, the compat mount's bus
+pub fn start(
+    name: &str,
+    encode: &EncodeConfig,
+    source: &Arc<FrameBus>,
+    output: Arc<FrameBus>,
+) -> Result<()> {
+    let pipeline_str = pipeline_string(encode);
+    debug!("H.264 compat pipeline for '{}': {}", name, pipeline_str);
+
+    let pipeline = gstreamer::parse::launch(&pipeline_str)
+        .context("Failed to create H.264 compat pipeline")?
+        .downcast::<gstreamer::Pipeline>()
+        .map_err(|_| anyhow::anyhow!("Failed to downcast to Pipeline"))?;
+    let appsrc = pipeline
+        .by_name("src")
+        .ok_or_else(|| anyhow::anyhow!("Missing src element"))?
+        .dynamic_cast::<AppSrc>()
+        .map_err(|_| anyhow::anyhow!("Failed to cast to AppSrc"))?;
+    let appsink = pipeline
+        .by_name("sink")
+        .ok_or_else(|| anyhow::anyhow!("Missing sink element"))?
+        .dynamic_cast::<AppSink>()
+        .map_err(|_| anyhow::anyhow!("Failed to cast to AppSink"))?;
+
+    let sink_output = Arc::clone(&output);
+    appsink.set_callbacks(
+        gstreamer_app::AppSinkCallbacks::builder()
+            .new_sample(move |sink| {
+                // A client just started playing: cut the GOP short for it
+                if sink_output.take_keyframe_request() && !sources::force_keyframe(sink) {
+                    debug!("Encoder did not accept a keyframe request");
+                }
+
+                let sample = sink.pull_sample().map_err(|_| gstreamer::FlowError::Eos)?;
+                let buffer = sample.buffer_owned().ok_or(gstreamer::FlowError::Error)?;
+                let is_keyframe = !buffer.flags().contains(gstreamer::BufferFlags::DELTA_UNIT);
+                sink_output.send(FrameData::new(buffer, is_keyframe, StreamKind::Video));
+                Ok(gstreamer::FlowSuccess::Ok)
+            })
+            .build(),
+    );
+
+    pipeline
+        .set_state(gstreamer::State::Playing)
+        .map_err(|e| anyhow::anyhow!("Failed to start H.264 compat pipeline: {:?}", e))?;
+
+    info!("Started H.264 compat transcode for '{}'", name);
+
+    // Weak, so the source's bus can still close and end this thread
+    let mut frames = source.subscribe();
+    let source = Arc::downgrade(source);
+    let name = name.to_string();
+    std::thread::spawn(move || {
+        loop {
+            // Nothing to decode while nobody watches; once someone does, the
+            // decoder can't start mid-GOP
+            if output.viewers() == 0 {
+                frames.resync();
+            } else if !frames.is_synced() {
+                if let Some(source) = source.upgrade() {
+                    source.request_keyframe();
+                }
+            }
+
+            let frame = match frames.recv_from_keyframe_timeout(KEYFRAME_POLL) {
+                Ok(frame) => frame,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            if frame.kind != StreamKind::Video || output.viewers() == 0 {
+                continue;
+            }
+
+            if let Err(e) = appsrc.push_buffer(frame.output_buffer()) {
+                warn!("H.264 compat transcode for '{}' stopped: {:?}", name, e);
+                break;
+            }
+        }
+
+        pipeline.set_state(gstreamer::State::Null).ok();
+        debug!("H.264 compat transcode for '{}' ended", name);
+    });
+
+    Ok(())
+}
+
+/// Build the H.265 to H.264 transcode launch string
+fn pipeline_string(encode: &EncodeConfig) -> String {
+    format!(
+        "appsrc name=src is-live=true format=time do-timestamp=true caps={h265} \
+         ! h265parse \
+         ! avdec_h265 \
+         ! {encoder} \
+         ! {encoder_caps} \
+         ! h264parse \
+         ! {h264} \
+         ! {appsink}",
+        h265 = sources::h265_caps(),
+        encoder = sources::build_encoder_string(encode),
+        encoder_caps = sources::h264_encoder_caps(encode),
+        h264 = sources::h264_caps(),
+        appsink = sources::appsink_config(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline_string() {
+        let encode = EncodeConfig {
+            bitrate: 1500,
+            ..EncodeConfig::default()
+        };
+        let pipeline = pipeline_string(&encode);
+        assert!(pipeline.starts_with(&format!(
+            "appsrc name=src is-live=true format=time do-timestamp=true caps={} ! h265parse ! avdec_h265 ! videoconvert ! x264enc bitrate=1500 ",
+            sources::h265_caps()
+        )));
+        assert!(pipeline.ends_with(&format!(
+            "! h264parse ! {} ! {}",
+            sources::h264_caps(),
+            sources::appsink_config()
+        )));
+    }
+}
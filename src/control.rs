@@ -0,0 +1,318 @@
+//! HTTP control API for starting and stopping sources at runtime
+//!
+//! `GET /sources` lists every source with its state; `POST /sources/{name}/start`,
+//! `/stop` and `/restart` act on one source and return its new state, and
+//! `POST /sources/{name}/bitrate/{kbps}` retunes its encoder live. When
+//! `[server] control_token` is set, requests must carry
+//! `Authorization: Bearer <token>`.
+
+use crate::http::{Request, Response};
+use crate::sources::{Source, SourceState};
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tracing::info;
+
+/// Path prefix routed to this module
+pub const PREFIX: &str = "/sources";
+
+/// Whether a request path belongs to this module
+pub fn handles(path: &str) -> bool {
+    path.strip_prefix(PREFIX)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// The parts of a source the control API drives
+pub trait Controllable {
+    fn state(&self) -> SourceState;
+    fn is_running(&self) -> bool;
+    fn start(self: Arc<Self>) -> Result<()>;
+    fn stop(&self);
+    fn set_bitrate(&self, kbps: u32) -> Result<()>;
+}
+
+impl Controllable for Source {
+    fn state(&self) -> SourceState {
+        Source::state(self)
+    }
+
+    fn is_running(&self) -> bool {
+        Source::is_running(self)
+    }
+
+    fn start(self: Arc<Self>) -> Result<()> {
+        Source::start(self)
+    }
+
+    fn stop(&self) {
+        Source::stop(self)
+    }
+
+    fn set_bitrate(&self, kbps: u32) -> Result<()> {
+        Source::set_bitrate(self, kbps)
+    }
+}
+
+/// Action requested on one source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Start,
+    Stop,
+    Restart,
+    /// New bitrate in kbps
+    Bitrate(u32),
+}
+
+impl Action {
+    fn parse(s: &str) -> Option<Self> {
+        if let Some(kbps) = s.strip_prefix("bitrate/") {
+            return kbps.parse().ok().map(Self::Bitrate);
+        }
+        match s {
+            "start" => Some(Self::Start),
+            "stop" => Some(Self::Stop),
+            "restart" => Some(Self::Restart),
+            _ => None,
+        }
+    }
+}
+
+/// One entry of the listing
+#[derive(Debug, Serialize)]
+struct SourceEntry<'a> {
+    name: &'a str,
+    state: SourceState,
+}
+
+/// Handle a request under `/sources`
+pub fn handle_request<S: Controllable>(
+    sources: &BTreeMap<String, Arc<S>>,
+    token: Option<&str>,
+    request: &Request,
+) -> Response {
+    if !authorized(token, request) {
+        return Response::text(401, "Unauthorized");
+    }
+
+    let Some(rest) = request.path.strip_prefix(PREFIX) else {
+        return Response::not_found();
+    };
+
+    if rest.is_empty() || rest == "/" {
+        if request.method != "GET" {
+            return Response::text(405, "Method Not Allowed");
+        }
+        let entries: Vec<_> = sources
+            .iter()
+            .map(|(name, source)| SourceEntry {
+                name,
+                state: source.state(),
+            })
+            .collect();
+        return json(&entries);
+    }
+
+    let Some((name, action)) = rest.strip_prefix('/').and_then(|r| r.split_once('/')) else {
+        return Response::not_found();
+    };
+    let Some(action) = Action::parse(action) else {
+        return Response::not_found();
+    };
+    if request.method != "POST" {
+        return Response::text(405, "Method Not Allowed");
+    }
+    let Some(source) = sources.get(name) else {
+        return Response::text(404, "Unknown source");
+    };
+
+    info!("Control API: {:?} source '{}'", action, name);
+    if let Err(e) = apply(source, action) {
+        return Response::text(409, &e.to_string());
+    }
+
+    json(&SourceEntry {
+        name,
+        state: source.state(),
+    })
+}
+
+/// Run an action against a source
+fn apply<S: Controllable>(source: &Arc<S>, action: Action) -> Result<()> {
+    match action {
+        Action::Start => Arc::clone(source).start(),
+        Action::Stop => {
+            if !source.is_running() {
+                anyhow::bail!("Source is not running");
+            }
+            source.stop();
+            Ok(())
+        }
+        Action::Restart => {
+            source.stop();
+            Arc::clone(source).start()
+        }
+        Action::Bitrate(kbps) => source.set_bitrate(kbps),
+    }
+}
+
+/// Check the bearer token, if one is configured
+fn authorized(token: Option<&str>, request: &Request) -> bool {
+    let Some(token) = token else {
+        return true;
+    };
+    request
+        .header("authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|given| given.trim() == token)
+}
+
+fn json<T: Serialize>(value: &T) -> Response {
+    Response::ok(
+        "application/json",
+        serde_json::to_vec(value).unwrap_or_default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+    /// Records start/stop calls instead of running a pipeline
+    #[derive(Default)]
+    struct MockSource {
+        running: AtomicBool,
+        starts: AtomicUsize,
+        bitrate: AtomicU32,
+    }
+
+    impl Controllable for MockSource {
+        fn state(&self) -> SourceState {
+            if self.is_running() {
+                SourceState::Live
+            } else {
+                SourceState::Stopped
+            }
+        }
+
+        fn is_running(&self) -> bool {
+            self.running.load(Ordering::SeqCst)
+        }
+
+        fn start(self: Arc<Self>) -> Result<()> {
+            if self.running.swap(true, Ordering::SeqCst) {
+                anyhow::bail!("Source is already running");
+            }
+            self.starts.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn stop(&self) {
+            self.running.store(false, Ordering::SeqCst);
+        }
+
+        fn set_bitrate(&self, kbps: u32) -> Result<()> {
+            if kbps < 100 {
+                anyhow::bail!("Bitrate too low");
+            }
+            self.bitrate.store(kbps, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    fn registry() -> BTreeMap<String, Arc<MockSource>> {
+        let cam1 = Arc::new(MockSource::default());
+        Arc::clone(&cam1).start().unwrap();
+        BTreeMap::from([
+            ("cam1".to_string(), cam1),
+            ("cam2".to_string(), Arc::new(MockSource::default())),
+        ])
+    }
+
+    fn request(method: &str, path: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_routing() {
+        let sources = registry();
+
+        let response = handle_request(&sources, None, &request("GET", "/sources"));
+        assert_eq!(response.status, 200);
+        let json: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(json[0]["name"], "cam1");
+        assert_eq!(json[0]["state"], "live");
+        assert_eq!(json[1]["state"], "stopped");
+
+        let status = |method, path| handle_request(&sources, None, &request(method, path)).status;
+        assert_eq!(status("POST", "/sources"), 405);
+        assert_eq!(status("GET", "/sources/cam1/stop"), 405);
+        assert_eq!(status("POST", "/sources/cam1/pause"), 404);
+        assert_eq!(status("POST", "/sources/cam9/stop"), 404);
+        assert_eq!(status("POST", "/sources/cam1"), 404);
+
+        // Only /sources and below; a source named "sources2" keeps its snapshots
+        assert!(handles("/sources") && handles("/sources/cam1/stop"));
+        assert!(!handles("/sources2/snapshot.jpg"));
+    }
+
+    #[test]
+    fn test_actions() {
+        let sources = registry();
+        let post = |path| handle_request(&sources, None, &request("POST", path));
+
+        // Stop and start again
+        let response = post("/sources/cam1/stop");
+        assert_eq!(response.status, 200);
+        assert!(!sources["cam1"].is_running());
+        assert_eq!(post("/sources/cam1/stop").status, 409);
+
+        let response = post("/sources/cam1/start");
+        assert_eq!(response.status, 200);
+        let json: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(json["state"], "live");
+        assert_eq!(post("/sources/cam1/start").status, 409);
+
+        // Restart works whether or not the source was running
+        assert_eq!(post("/sources/cam1/restart").status, 200);
+        assert_eq!(post("/sources/cam2/restart").status, 200);
+        assert_eq!(sources["cam1"].starts.load(Ordering::SeqCst), 3);
+        assert_eq!(sources["cam2"].starts.load(Ordering::SeqCst), 1);
+        assert!(sources["cam2"].is_running());
+    }
+
+    #[test]
+    fn test_bitrate() {
+        let sources = registry();
+        let status = |method, path| handle_request(&sources, None, &request(method, path)).status;
+
+        assert_eq!(status("POST", "/sources/cam1/bitrate/2500"), 200);
+        assert_eq!(sources["cam1"].bitrate.load(Ordering::SeqCst), 2500);
+
+        // Rejected values leave the bitrate alone
+        assert_eq!(status("POST", "/sources/cam1/bitrate/50"), 409);
+        assert_eq!(status("POST", "/sources/cam1/bitrate/fast"), 404);
+        assert_eq!(status("GET", "/sources/cam1/bitrate/3000"), 405);
+        assert_eq!(sources["cam1"].bitrate.load(Ordering::SeqCst), 2500);
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let sources = registry();
+        let mut req = request("GET", "/sources");
+
+        assert_eq!(handle_request(&sources, Some("s3cret"), &req).status, 401);
+
+        req.headers = vec![("authorization".to_string(), "Bearer wrong".to_string())];
+        assert_eq!(handle_request(&sources, Some("s3cret"), &req).status, 401);
+
+        req.headers = vec![("authorization".to_string(), "Bearer s3cret".to_string())];
+        assert_eq!(handle_request(&sources, Some("s3cret"), &req).status, 200);
+    }
+}
@@ -0,0 +1,86 @@
+//! `--dry-run`: load and validate the config, then print each source's mount
+//! and launch string without binding ports or opening devices
+
+use crate::resolve_backend;
+use anyhow::{Context, Result};
+use dart::config::{Config, ConfigSource};
+use dart::logging;
+use dart::sources::{self, VaapiElement};
+use std::io::Write;
+
+/// Print the planned pipelines for the config from `source` to `out`.
+/// Fails on the first invalid setting.
+pub fn run<W: Write>(
+    source: &ConfigSource,
+    mpp: bool,
+    vaapi: Option<VaapiElement>,
+    out: &mut W,
+) -> Result<()> {
+    let config = Config::load_from(source)?;
+    writeln!(
+        out,
+        "Config {} is valid: {} source(s)",
+        source,
+        config.sources.len()
+    )?;
+
+    for source in &config.sources {
+        let backend = resolve_backend(source, mpp, vaapi)?;
+        let pipeline = sources::pipeline_string(source, backend)
+            .with_context(|| format!("Source '{}'", source.name))?;
+
+        writeln!(out)?;
+        writeln!(
+            out,
+            "[{}] {:?} source, {:?} encoder",
+            source.name, source.source_type, backend
+        )?;
+        writeln!(
+            out,
+            "  mount:    {}",
+            config.server.rtsp_url(&source.mount_path())
+        )?;
+        writeln!(out, "  pipeline: {}", logging::redact_pipeline(&pipeline))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_dry_run_sample_config() {
+        let config = Path::new(env!("CARGO_MANIFEST_DIR")).join("config.test.toml");
+        let mut out = Vec::new();
+        run(&ConfigSource::Path(config), false, None, &mut out).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("is valid: 1 source(s)"));
+        assert!(out.contains("[bars] Test source, X264 encoder"));
+        assert!(out.contains("mount:    rtsp://0.0.0.0:8554/bars/stream"));
+        assert!(out.contains("pipeline: videotestsrc is-live=true pattern=smpte"));
+        assert!(out.contains("x264enc bitrate=2000"));
+    }
+
+    #[test]
+    fn test_dry_run_rejects_invalid_config() {
+        let config = std::env::temp_dir().join(format!("dart-dry-run-{}.toml", std::process::id()));
+        std::fs::write(
+            &config,
+            "[server]\n[[sources]]\nname = \"cam 1\"\ntype = \"test\"\n",
+        )
+        .unwrap();
+
+        let result = run(
+            &ConfigSource::Path(config.clone()),
+            false,
+            None,
+            &mut Vec::new(),
+        );
+        std::fs::remove_file(&config).ok();
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,230 @@
+//! Typed errors for source pipelines
+//!
+//! Pipeline failures are classified into the cases an operator can act on
+//! (missing device, bad credentials, no signal, ...), each keeping the
+//! GStreamer message it came from. Code embedding the library can match on
+//! the variant; the `dart` binary sees them as `anyhow` errors from the
+//! source loop, where `{:#}` prints the hint followed by that message.
+
+use crate::config::{SourceConfig, SourceType};
+use std::fmt;
+
+/// An error from a source's pipeline
+#[derive(Debug, thiserror::Error)]
+pub enum DartError {
+    /// The V4L2 device path doesn't exist
+    #[error("{device} does not exist — check the device path")]
+    DeviceNotFound {
+        device: String,
+        source: PipelineError,
+    },
+
+    /// Another program holds the V4L2 device
+    #[error("{device} is in use by another program")]
+    DeviceBusy {
+        device: String,
+        source: PipelineError,
+    },
+
+    /// The V4L2 device can't be opened by this user
+    #[error("Permission denied opening {device} — add the dart user to the video group")]
+    PermissionDenied {
+        device: String,
+        source: PipelineError,
+    },
+
+    /// The camera rejected the configured credentials
+    #[error("The camera rejected the credentials — check username and password")]
+    AuthFailed { source: PipelineError },
+
+    /// The source couldn't be reached
+    #[error("Could not connect to the source — check the url and that it is reachable")]
+    ConnectFailed { source: PipelineError },
+
+    /// Caps negotiation failed: no signal on a capture device (`device`),
+    /// or a stream whose format doesn't match the pipeline
+    #[error("{}", caps_hint(.device.as_deref()))]
+    CapsNegotiation {
+        device: Option<String>,
+        source: PipelineError,
+    },
+
+    /// The pipeline stopped delivering frames
+    #[error("No frames for {secs}s, source appears frozen")]
+    Timeout { secs: u64 },
+
+    /// A pipeline error that isn't one of the above
+    #[error(transparent)]
+    Pipeline(#[from] PipelineError),
+
+    /// Anything else, e.g. an invalid launch string
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// An error message posted on a pipeline's bus
+#[derive(Debug)]
+pub struct PipelineError {
+    pub message: String,
+    pub debug: Option<String>,
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Pipeline error: {} ({:?})", self.message, self.debug)
+    }
+}
+
+impl std::error::Error for PipelineError {}
+
+fn caps_hint(device: Option<&str>) -> String {
+    match device {
+        Some(device) => format!(
+            "No signal detected on {} — check HDMI input and colorimetry",
+            device
+        ),
+        None => "The stream's format doesn't match the pipeline — check source_codec against what the camera sends".to_string(),
+    }
+}
+
+impl DartError {
+    /// Classify a GStreamer error (message and debug text) from `config`'s
+    /// pipeline, for the error classes that come up in practice
+    pub fn from_bus_error(config: &SourceConfig, message: &str, debug: Option<&str>) -> Self {
+        let text = format!("{} {}", message, debug.unwrap_or_default()).to_lowercase();
+        let has = |needle: &str| text.contains(needle);
+        let v4l2 = config.source_type == SourceType::V4l2;
+        let device = config.device.as_deref().unwrap_or("the device").to_string();
+        let source = PipelineError {
+            message: message.to_string(),
+            debug: debug.map(str::to_string),
+        };
+
+        if has("not-negotiated") || has("not negotiated") {
+            DartError::CapsNegotiation {
+                device: v4l2.then_some(device),
+                source,
+            }
+        } else if v4l2 && has("busy") {
+            DartError::DeviceBusy { device, source }
+        } else if v4l2 && has("permission denied") {
+            DartError::PermissionDenied { device, source }
+        } else if v4l2 && (has("cannot identify device") || has("no such file or directory")) {
+            DartError::DeviceNotFound { device, source }
+        } else if has("unauthorized") {
+            // Not "401": that also matches line numbers in the debug text
+            DartError::AuthFailed { source }
+        } else if has("could not connect")
+            || has("connection refused")
+            || has("could not open resource for reading")
+        {
+            DartError::ConnectFailed { source }
+        } else {
+            DartError::Pipeline(source)
+        }
+    }
+
+    /// The user-facing hint, if the error was recognised
+    pub fn hint(&self) -> Option<String> {
+        match self {
+            DartError::Pipeline(_) | DartError::Other(_) => None,
+            _ => Some(self.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v4l2() -> SourceConfig {
+        toml::from_str("name = \"hdmi\"\ntype = \"v4l2\"\ndevice = \"/dev/video0\"").unwrap()
+    }
+
+    fn rtsp() -> SourceConfig {
+        toml::from_str("name = \"cam\"\ntype = \"rtsp\"\nurl = \"rtsp://cam/stream\"").unwrap()
+    }
+
+    #[test]
+    fn test_simulated_failures() {
+        let not_found = DartError::from_bus_error(
+            &v4l2(),
+            "Cannot identify device '/dev/video9'.",
+            Some("system error: No such file or directory"),
+        );
+        assert!(
+            matches!(&not_found, DartError::DeviceNotFound { device, .. } if device == "/dev/video0")
+        );
+
+        let auth = DartError::from_bus_error(
+            &rtsp(),
+            "Unauthorized",
+            Some("../gst/rtsp/gstrtspsrc.c(6401): gst_rtspsrc_send (): Unauthorized (401)"),
+        );
+        assert!(matches!(auth, DartError::AuthFailed { .. }));
+
+        let caps = DartError::from_bus_error(
+            &v4l2(),
+            "Internal data stream error.",
+            Some("streaming stopped, reason not-negotiated (-4)"),
+        );
+        assert!(
+            matches!(&caps, DartError::CapsNegotiation { device: Some(device), .. } if device == "/dev/video0")
+        );
+        assert_eq!(
+            caps.hint().as_deref(),
+            Some("No signal detected on /dev/video0 — check HDMI input and colorimetry")
+        );
+        assert!(matches!(
+            DartError::from_bus_error(
+                &rtsp(),
+                "Internal data stream error.",
+                Some("reason not-negotiated (-4)")
+            ),
+            DartError::CapsNegotiation { device: None, .. }
+        ));
+
+        let busy = DartError::from_bus_error(
+            &v4l2(),
+            "Could not open device '/dev/video0' for reading and writing.",
+            Some("system error: Device or resource busy"),
+        );
+        assert!(matches!(busy, DartError::DeviceBusy { .. }));
+
+        // Device errors only mean something for a local device
+        let refused = DartError::from_bus_error(
+            &rtsp(),
+            "Could not open resource for reading and writing.",
+            Some("Failed to connect. (Generic error)"),
+        );
+        assert!(matches!(refused, DartError::ConnectFailed { .. }));
+
+        let unknown = DartError::from_bus_error(
+            &rtsp(),
+            "Internal data stream error.",
+            Some("../gst/rtsp/gstrtspsrc.c(6401): reason error (-5)"),
+        );
+        assert!(matches!(unknown, DartError::Pipeline(_)));
+        assert!(unknown.hint().is_none());
+    }
+
+    #[test]
+    fn test_error_keeps_gstreamer_message() {
+        let error = DartError::from_bus_error(&rtsp(), "Unauthorized", Some("Unauthorized (401)"));
+        assert_eq!(
+            error.to_string(),
+            "The camera rejected the credentials — check username and password"
+        );
+
+        // At the binary boundary the chain carries both
+        let error = anyhow::Error::from(error);
+        assert_eq!(format!("{:#}", error), "The camera rejected the credentials — check username and password: Pipeline error: Unauthorized (Some(\"Unauthorized (401)\"))");
+        assert!(error.downcast_ref::<DartError>().is_some());
+
+        let frozen = DartError::Timeout { secs: 10 };
+        assert_eq!(
+            frozen.to_string(),
+            "No frames for 10s, source appears frozen"
+        );
+    }
+}
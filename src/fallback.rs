@@ -1,24 +1,176 @@
 //! Fallback image encoding and management
 //!
-//! Encodes a static image to H.264/H.265 at startup for use when sources disconnect.
+//! Encodes a static image, an animated GIF, or a short looping clip, to
+//! H.264/H.265 at startup for use when sources disconnect. Without a file, a
+//! `color:` or `text:` spec renders a plain frame instead. The codec and
+//! resolution must match the mount the frames are pushed into.
 
+use crate::config::{FallbackAspect, OutputCodec, SourceConfig};
+use crate::sources::EncoderBackend;
 use anyhow::{Context, Result};
 use gstreamer::prelude::*;
 use gstreamer_app::AppSink;
 use std::path::Path;
 use std::sync::Arc;
-use tracing::{debug, info};
+use std::time::Duration;
+use tracing::{debug, info, warn};
 
-/// Pre-encoded fallback frame data
+/// Fallback resolution when the source's size is not configured
+const DEFAULT_WIDTH: u32 = 640;
+const DEFAULT_HEIGHT: u32 = 480;
+
+/// Resolution to encode a source's fallback at: its configured size, or 640x480
+pub fn size_for(config: &SourceConfig) -> (u32, u32) {
+    match (config.width, config.height) {
+        (Some(w), Some(h)) => (w, h),
+        _ => (DEFAULT_WIDTH, DEFAULT_HEIGHT),
+    }
+}
+
+/// Longest clip kept in memory (about a minute at 25 fps); longer clips are cut
+const MAX_CLIP_FRAMES: usize = 1500;
+
+/// Frame rate assumed when a clip's caps don't carry one
+const DEFAULT_CLIP_FPS: u32 = 25;
+
+/// File extensions treated as video clips rather than still images
+const CLIP_EXTENSIONS: &[&str] = &["mp4", "m4v", "mov", "mkv", "webm", "avi", "ts"];
+
+/// GIF frame delay used when a frame carries no timing, as browsers do
+const DEFAULT_GIF_DELAY: Duration = Duration::from_millis(100);
+
+/// Named colors accepted by `color:` specs, as 0xRRGGBB
+const NAMED_COLORS: &[(&str, u32)] = &[
+    ("black", 0x000000),
+    ("white", 0xFFFFFF),
+    ("gray", 0x808080),
+    ("grey", 0x808080),
+    ("red", 0xFF0000),
+    ("green", 0x00FF00),
+    ("blue", 0x0000FF),
+    ("yellow", 0xFFFF00),
+];
+
+/// A fallback rendered at startup instead of loaded from a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FallbackSpec {
+    /// `color:black` or `color:#RRGGBB`: a solid frame (0xRRGGBB)
+    Color(u32),
+    /// `text:NO SIGNAL`: white text centred on black
+    Text(String),
+}
+
+impl FallbackSpec {
+    /// Parse a `fallback` value. Returns None for a file path, and an error
+    /// for a `scheme:` prefix other than `color:` or `text:`.
+    pub fn parse(value: &str) -> Result<Option<Self>> {
+        // A one-letter prefix is more likely a drive letter than a scheme
+        let Some((scheme, rest)) = value
+            .split_once(':')
+            .filter(|(s, _)| s.len() > 1 && s.chars().all(|c| c.is_ascii_alphanumeric()))
+        else {
+            return Ok(None);
+        };
+
+        match scheme {
+            "color" => parse_color(rest).map(|rgb| Some(Self::Color(rgb))),
+            "text" => {
+                if rest.trim().is_empty() {
+                    anyhow::bail!("text: fallback needs some text");
+                }
+                Ok(Some(Self::Text(rest.to_string())))
+            }
+            _ => anyhow::bail!(
+                "unknown fallback scheme '{}:' (expected a file path, color: or text:)",
+                scheme
+            ),
+        }
+    }
+}
+
+/// A color name from [`NAMED_COLORS`] or `#RRGGBB`
+fn parse_color(value: &str) -> Result<u32> {
+    let value = value.trim().to_ascii_lowercase();
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() == 6 {
+            if let Ok(rgb) = u32::from_str_radix(hex, 16) {
+                return Ok(rgb);
+            }
+        }
+        anyhow::bail!("invalid color '#{}' (expected #RRGGBB)", hex);
+    }
+    NAMED_COLORS
+        .iter()
+        .find(|(name, _)| *name == value)
+        .map(|(_, rgb)| *rgb)
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "unknown color '{}' (use a name like black or #RRGGBB)",
+                value
+            )
+        })
+}
+
+/// One pre-encoded fallback frame
 #[derive(Clone)]
 pub struct FallbackFrame {
-    /// Encoded keyframe data (H.264 or H.265 depending on MPP availability)
-    pub data: Arc<Vec<u8>>,
+    /// Encoded frame (H.264 or H.265, matching the mount codec)
+    pub buffer: gstreamer::Buffer,
+    pub is_keyframe: bool,
+}
+
+/// Pre-encoded fallback content pushed while a source is down
+#[derive(Clone)]
+pub enum FallbackSource {
+    /// Still image: a single keyframe, resent at the source's `fallback_fps`
+    Image(FallbackFrame),
+    /// Looping clip, starting on a keyframe so every loop decodes cleanly
+    Clip {
+        frames: Arc<Vec<FallbackFrame>>,
+        frame_interval: Duration,
+    },
+    /// Animated GIF, each frame shown for its own delay
+    Animation {
+        frames: Arc<Vec<FallbackFrame>>,
+        delays: Arc<Vec<Duration>>,
+    },
 }
 
-impl FallbackFrame {
-    /// Encode an image file to a fallback frame (H.265 if MPP available, H.264 otherwise)
-    pub fn from_image<P: AsRef<Path>>(path: P, mpp: bool) -> Result<Self> {
+impl FallbackSource {
+    /// Load an image or video clip (chosen by file extension), or render a
+    /// `color:`/`text:` spec
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        codec: OutputCodec,
+        backend: EncoderBackend,
+        size: (u32, u32),
+        aspect: FallbackAspect,
+    ) -> Result<Self> {
+        if let Some(spec) = path
+            .as_ref()
+            .to_str()
+            .map(FallbackSpec::parse)
+            .transpose()?
+            .flatten()
+        {
+            return Self::from_spec(&spec, codec, backend, size);
+        }
+        if is_clip(path.as_ref()) {
+            Self::from_clip(path, codec, backend, size, aspect)
+        } else {
+            Self::from_image(path, codec, backend, size, aspect)
+        }
+    }
+
+    /// Encode an image file to a fallback frame matching the mount's output codec and size.
+    /// An animated GIF becomes a loop of all its frames.
+    pub fn from_image<P: AsRef<Path>>(
+        path: P,
+        codec: OutputCodec,
+        backend: EncoderBackend,
+        (width, height): (u32, u32),
+        aspect: FallbackAspect,
+    ) -> Result<Self> {
         let path = path.as_ref();
         let path_str = path
             .to_str()
@@ -26,114 +178,803 @@ impl FallbackFrame {
 
         info!("Encoding fallback image: {}", path.display());
 
-        // Initialize GStreamer if not already done
-        gstreamer::init().ok();
+        if is_gif(path) {
+            return Self::from_gif(path_str, codec, backend, (width, height), aspect);
+        }
 
-        let pipeline_str = if mpp {
-            // MPP path: encode fallback image to H.265
-            format!(
-                "filesrc location=\"{path}\" \
-                 ! decodebin ! videoconvert ! videoscale \
-                 ! video/x-raw,width=640,height=480 \
-                 ! mpph265enc gop=1 \
-                 ! video/x-h265,stream-format=byte-stream,alignment=au \
-                 ! h265parse \
-                 ! appsink name=sink emit-signals=false sync=false",
-                path = path_str
-            )
-        } else {
-            // x264 path (existing behavior)
-            format!(
-                "filesrc location=\"{path}\" \
-                 ! decodebin \
-                 ! videoconvert \
-                 ! videoscale \
-                 ! video/x-raw,width=640,height=480 \
-                 ! x264enc tune=stillimage key-int-max=1 \
-                 ! video/x-h264,stream-format=byte-stream,alignment=au \
-                 ! h264parse \
-                 ! appsink name=sink emit-signals=false sync=false",
-                path = path_str
-            )
-        };
+        let pipeline_str = pipeline_string(path_str, codec, backend, width, height, aspect);
 
-        debug!("Fallback pipeline: {}", pipeline_str);
-
-        let pipeline = gstreamer::parse::launch(&pipeline_str)
-            .context("Failed to create fallback encoding pipeline")?
-            .downcast::<gstreamer::Pipeline>()
-            .map_err(|_| anyhow::anyhow!("Failed to downcast to Pipeline"))?;
-
-        let sink = pipeline
-            .by_name("sink")
-            .ok_or_else(|| anyhow::anyhow!("Missing sink element"))?
-            .dynamic_cast::<AppSink>()
-            .map_err(|_| anyhow::anyhow!("Failed to cast to AppSink"))?;
-
-        // Start pipeline
-        pipeline
-            .set_state(gstreamer::State::Playing)
-            .map_err(|e| anyhow::anyhow!("Failed to start pipeline: {:?}", e))?;
-
-        // Pull the encoded frame(s) - we just need one keyframe
-        let mut frame_data = Vec::new();
-
-        // Wait for up to 5 seconds for the frame
-        let timeout = std::time::Duration::from_secs(5);
-        let start = std::time::Instant::now();
-
-        while start.elapsed() < timeout {
-            match sink.try_pull_sample(gstreamer::ClockTime::from_mseconds(100)) {
-                Some(sample) => {
-                    if let Some(buffer) = sample.buffer() {
-                        if let Ok(map) = buffer.map_readable() {
-                            frame_data.extend_from_slice(map.as_slice());
-                            // For a still image, one frame is enough
-                            break;
-                        }
-                    }
+        // For a still image, one frame is enough
+        let (mut frames, _) = encode(&pipeline_str, 1, Duration::from_secs(5))?;
+        let frame = frames.remove(0);
+
+        info!("Fallback image encoded: {} bytes", frame.buffer.size());
+
+        Ok(Self::Image(FallbackFrame {
+            is_keyframe: true,
+            ..frame
+        }))
+    }
+
+    /// Render and encode a `color:`/`text:` spec as a still image
+    pub fn from_spec(
+        spec: &FallbackSpec,
+        codec: OutputCodec,
+        backend: EncoderBackend,
+        (width, height): (u32, u32),
+    ) -> Result<Self> {
+        info!("Rendering fallback: {:?}", spec);
+
+        let pipeline_str = spec_pipeline_string(spec, codec, backend, width, height);
+        let (mut frames, _) = encode(&pipeline_str, 1, Duration::from_secs(5))?;
+        let frame = frames.remove(0);
+
+        info!("Fallback frame encoded: {} bytes", frame.buffer.size());
+
+        Ok(Self::Image(FallbackFrame {
+            is_keyframe: true,
+            ..frame
+        }))
+    }
+
+    /// Encode every frame of a GIF, keeping the GIF's per-frame delays. A
+    /// single-frame GIF is a still image.
+    fn from_gif(
+        path: &str,
+        codec: OutputCodec,
+        backend: EncoderBackend,
+        (width, height): (u32, u32),
+        aspect: FallbackAspect,
+    ) -> Result<Self> {
+        // Same decode-and-encode as a clip: decodebin yields each GIF frame
+        // timestamped by its delay, and the first frame is a keyframe
+        let pipeline_str = clip_pipeline_string(path, codec, backend, width, height, aspect);
+        let (frames, _) = encode(&pipeline_str, MAX_CLIP_FRAMES, Duration::from_secs(60))?;
+
+        if frames.len() == 1 {
+            let frame = frames.into_iter().next().unwrap();
+            info!("Fallback image encoded: {} bytes", frame.buffer.size());
+            return Ok(Self::Image(FallbackFrame {
+                is_keyframe: true,
+                ..frame
+            }));
+        }
+
+        if !frames[0].is_keyframe {
+            anyhow::bail!("Fallback GIF did not start with a keyframe");
+        }
+
+        let delays = gif_delays(&frames);
+        info!(
+            "Fallback GIF encoded: {} frames, {} ms per loop",
+            frames.len(),
+            delays.iter().sum::<Duration>().as_millis()
+        );
+
+        Ok(Self::Animation {
+            frames: Arc::new(frames),
+            delays: Arc::new(delays),
+        })
+    }
+
+    /// Encode a video clip to a loopable frame sequence at the clip's frame rate
+    pub fn from_clip<P: AsRef<Path>>(
+        path: P,
+        codec: OutputCodec,
+        backend: EncoderBackend,
+        (width, height): (u32, u32),
+        aspect: FallbackAspect,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let path_str = path
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid path"))?;
+
+        info!("Encoding fallback clip: {}", path.display());
+
+        let pipeline_str = clip_pipeline_string(path_str, codec, backend, width, height, aspect);
+        let (mut frames, fps) = encode(&pipeline_str, MAX_CLIP_FRAMES, Duration::from_secs(60))?;
+
+        if frames.len() == MAX_CLIP_FRAMES {
+            warn!(
+                "Fallback clip {} is longer than {} frames, looping the first {}",
+                path.display(),
+                MAX_CLIP_FRAMES,
+                MAX_CLIP_FRAMES
+            );
+        }
+
+        // The encoder opens with a keyframe; drop anything before it so the
+        // loop point is always decodable
+        let first_key = frames
+            .iter()
+            .position(|f| f.is_keyframe)
+            .ok_or_else(|| anyhow::anyhow!("Fallback clip produced no keyframe"))?;
+        frames.drain(..first_key);
+
+        let (num, den) = fps
+            .filter(|(n, d)| *n > 0 && *d > 0)
+            .unwrap_or((DEFAULT_CLIP_FPS as i32, 1));
+        let frame_interval = Duration::from_secs_f64(den as f64 / num as f64);
+
+        info!(
+            "Fallback clip encoded: {} frames at {}/{} fps",
+            frames.len(),
+            num,
+            den
+        );
+
+        Ok(Self::Clip {
+            frames: Arc::new(frames),
+            frame_interval,
+        })
+    }
+
+    /// Frames to cycle through, in order
+    pub fn frames(&self) -> &[FallbackFrame] {
+        match self {
+            Self::Image(frame) => std::slice::from_ref(frame),
+            Self::Clip { frames, .. } | Self::Animation { frames, .. } => frames,
+        }
+    }
+
+    /// How long to show frame `index` before sending the next. A still image
+    /// is resent every `still_interval`.
+    pub fn frame_delay(&self, index: usize, still_interval: Duration) -> Duration {
+        match self {
+            Self::Image(_) => still_interval,
+            Self::Clip { frame_interval, .. } => *frame_interval,
+            Self::Animation { delays, .. } => delays[index],
+        }
+    }
+}
+
+/// Whether a fallback path is a GIF, which may be animated
+fn is_gif(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("gif"))
+}
+
+/// Per-frame delays of an encoded GIF, from the gaps between timestamps. The
+/// last frame uses its own duration, and untimed frames fall back to 100 ms.
+fn gif_delays(frames: &[FallbackFrame]) -> Vec<Duration> {
+    let nonzero = |d: Option<gstreamer::ClockTime>| {
+        d.map(|d| Duration::from_nanos(d.nseconds()))
+            .filter(|d| !d.is_zero())
+            .unwrap_or(DEFAULT_GIF_DELAY)
+    };
+
+    frames
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            let next = frames.get(i + 1).and_then(|next| next.buffer.pts());
+            match (frame.buffer.pts(), next) {
+                (Some(pts), Some(next)) => nonzero(next.checked_sub(pts)),
+                _ => nonzero(frame.buffer.duration()),
+            }
+        })
+        .collect()
+}
+
+/// Whether a fallback path looks like a video clip
+fn is_clip(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| CLIP_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+/// Frame rate as (numerator, denominator)
+type Framerate = (i32, i32);
+
+/// Run an encoding pipeline to EOS (or `max_frames`), collecting its output.
+/// Also returns the framerate from the output caps, if present.
+fn encode(
+    pipeline_str: &str,
+    max_frames: usize,
+    timeout: Duration,
+) -> Result<(Vec<FallbackFrame>, Option<Framerate>)> {
+    // Initialize GStreamer if not already done
+    gstreamer::init().ok();
+
+    debug!(
+        "Fallback pipeline: {}",
+        crate::logging::redact_pipeline(pipeline_str)
+    );
+
+    let pipeline = gstreamer::parse::launch(pipeline_str)
+        .context("Failed to create fallback encoding pipeline")?
+        .downcast::<gstreamer::Pipeline>()
+        .map_err(|_| anyhow::anyhow!("Failed to downcast to Pipeline"))?;
+
+    let sink = pipeline
+        .by_name("sink")
+        .ok_or_else(|| anyhow::anyhow!("Missing sink element"))?
+        .dynamic_cast::<AppSink>()
+        .map_err(|_| anyhow::anyhow!("Failed to cast to AppSink"))?;
+
+    // Start pipeline
+    pipeline
+        .set_state(gstreamer::State::Playing)
+        .map_err(|e| anyhow::anyhow!("Failed to start pipeline: {:?}", e))?;
+
+    let mut frames = Vec::new();
+    let mut framerate = None;
+    let start = std::time::Instant::now();
+
+    while frames.len() < max_frames && start.elapsed() < timeout {
+        match sink.try_pull_sample(gstreamer::ClockTime::from_mseconds(100)) {
+            Some(sample) => {
+                if framerate.is_none() {
+                    framerate = sample
+                        .caps()
+                        .and_then(|caps| caps.structure(0))
+                        .and_then(|s| s.get::<gstreamer::Fraction>("framerate").ok())
+                        .map(|f| (f.numer(), f.denom()));
+                }
+                if let Some(buffer) = sample.buffer_owned() {
+                    frames.push(FallbackFrame {
+                        is_keyframe: !buffer.flags().contains(gstreamer::BufferFlags::DELTA_UNIT),
+                        buffer,
+                    });
                 }
-                None => {
-                    // Check if we hit EOS
-                    if let Some(bus) = pipeline.bus() {
-                        if bus.have_pending() {
-                            for msg in bus.iter() {
-                                if let gstreamer::MessageView::Eos(_) = msg.view() {
-                                    break;
-                                }
-                                if let gstreamer::MessageView::Error(err) = msg.view() {
-                                    pipeline.set_state(gstreamer::State::Null).ok();
-                                    return Err(anyhow::anyhow!(
-                                        "Fallback encoding error: {}",
-                                        err.error()
-                                    ));
-                                }
-                            }
+            }
+            None => {
+                if sink.is_eos() {
+                    break;
+                }
+                if let Some(bus) = pipeline.bus() {
+                    if let Some(msg) = bus.pop_filtered(&[gstreamer::MessageType::Error]) {
+                        if let gstreamer::MessageView::Error(err) = msg.view() {
+                            pipeline.set_state(gstreamer::State::Null).ok();
+                            return Err(anyhow::anyhow!(
+                                "Fallback encoding error: {}",
+                                err.error()
+                            ));
                         }
                     }
                 }
             }
         }
+    }
+
+    // Stop pipeline
+    pipeline.set_state(gstreamer::State::Null).ok();
 
-        // Stop pipeline
-        pipeline.set_state(gstreamer::State::Null).ok();
+    if frames.is_empty() {
+        anyhow::bail!("Failed to encode fallback - no data produced");
+    }
+
+    Ok((frames, framerate))
+}
 
-        if frame_data.is_empty() {
-            anyhow::bail!("Failed to encode fallback image - no data produced");
+/// Scale to the output size, letterboxing or stretching
+fn scale_string(width: u32, height: u32, aspect: FallbackAspect) -> String {
+    // Square output pixels make videoscale pad (letterbox) instead of stretching
+    let add_borders = aspect == FallbackAspect::Letterbox;
+    format!(
+        "videoscale add-borders={} ! video/x-raw,width={},height={},pixel-aspect-ratio=1/1",
+        add_borders, width, height
+    )
+}
+
+/// Build the clip encoding pipeline. Keyframes every 30 frames keep late
+/// joiners' wait short; the first frame is always a keyframe.
+fn clip_pipeline_string(
+    path: &str,
+    codec: OutputCodec,
+    backend: EncoderBackend,
+    width: u32,
+    height: u32,
+    aspect: FallbackAspect,
+) -> String {
+    let (encoder, caps, parse) = match codec {
+        OutputCodec::H265 if backend == EncoderBackend::Mpp => (
+            "mpph265enc gop=30",
+            "video/x-h265,stream-format=byte-stream,alignment=au",
+            "h265parse",
+        ),
+        // H.265 passthrough on a host without MPP
+        OutputCodec::H265 => (
+            "x265enc tune=zerolatency speed-preset=veryfast key-int-max=30",
+            "video/x-h265,stream-format=byte-stream,alignment=au",
+            "h265parse",
+        ),
+        OutputCodec::H264 => (
+            "x264enc tune=zerolatency speed-preset=veryfast key-int-max=30",
+            "video/x-h264,stream-format=byte-stream,alignment=au",
+            "h264parse",
+        ),
+    };
+
+    // sync=false drains the file as fast as it decodes
+    format!(
+        "filesrc location=\"{path}\" \
+         ! decodebin \
+         ! videoconvert \
+         ! {scale} \
+         ! {encoder} \
+         ! {caps} \
+         ! {parse} \
+         ! appsink name=sink emit-signals=false sync=false",
+        path = path,
+        scale = scale_string(width, height, aspect),
+        encoder = encoder,
+        caps = caps,
+        parse = parse,
+    )
+}
+
+/// Build the still-image encoding pipeline for the given output codec and size
+fn pipeline_string(
+    path: &str,
+    codec: OutputCodec,
+    backend: EncoderBackend,
+    width: u32,
+    height: u32,
+    aspect: FallbackAspect,
+) -> String {
+    format!(
+        "filesrc location=\"{path}\" \
+         ! decodebin \
+         ! videoconvert \
+         ! {scale} \
+         ! {encode}",
+        path = path,
+        scale = scale_string(width, height, aspect),
+        encode = still_encode_string(codec, backend),
+    )
+}
+
+/// Build the pipeline that renders a spec to one frame at the output size
+fn spec_pipeline_string(
+    spec: &FallbackSpec,
+    codec: OutputCodec,
+    backend: EncoderBackend,
+    width: u32,
+    height: u32,
+) -> String {
+    let (rgb, overlay) = match spec {
+        FallbackSpec::Color(rgb) => (*rgb, String::new()),
+        FallbackSpec::Text(text) => (
+            0x000000,
+            format!(
+                "textoverlay text=\"{}\" valignment=center halignment=center \
+                 font-desc=\"Sans {}\" ! ",
+                text.replace('"', "\\\""),
+                (height / 20).max(8)
+            ),
+        ),
+    };
+
+    format!(
+        "videotestsrc num-buffers=1 pattern=solid-color foreground-color=0xFF{rgb:06X} \
+         ! video/x-raw,width={width},height={height} \
+         ! {overlay}videoconvert \
+         ! {encode}",
+        rgb = rgb,
+        width = width,
+        height = height,
+        overlay = overlay,
+        encode = still_encode_string(codec, backend),
+    )
+}
+
+/// Encoder tail of the still pipelines: every frame a keyframe
+fn still_encode_string(codec: OutputCodec, backend: EncoderBackend) -> &'static str {
+    match codec {
+        // MPP path: encode fallback image to H.265
+        OutputCodec::H265 if backend == EncoderBackend::Mpp => {
+            "mpph265enc gop=1 \
+             ! video/x-h265,stream-format=byte-stream,alignment=au \
+             ! h265parse \
+             ! appsink name=sink emit-signals=false sync=false"
+        }
+        // H.265 passthrough on a host without MPP
+        OutputCodec::H265 => {
+            "x265enc tune=zerolatency key-int-max=1 \
+             ! video/x-h265,stream-format=byte-stream,alignment=au \
+             ! h265parse \
+             ! appsink name=sink emit-signals=false sync=false"
         }
+        OutputCodec::H264 => {
+            "x264enc tune=stillimage key-int-max=1 \
+             ! video/x-h264,stream-format=byte-stream,alignment=au \
+             ! h264parse \
+             ! appsink name=sink emit-signals=false sync=false"
+        }
+    }
+}
 
-        info!(
-            "Fallback image encoded: {} bytes",
-            frame_data.len()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::VaapiElement;
+
+    #[test]
+    fn test_h264_fallback_pipeline() {
+        let pipeline = pipeline_string(
+            "/tmp/offline.png",
+            OutputCodec::H264,
+            EncoderBackend::X264,
+            640,
+            480,
+            FallbackAspect::Letterbox,
+        );
+        assert!(pipeline.contains("x264enc"));
+        assert!(pipeline.contains("video/x-h264"));
+        assert!(!pipeline.contains("h265"));
+    }
+
+    #[test]
+    fn test_h265_fallback_pipeline() {
+        let pipeline = pipeline_string(
+            "/tmp/offline.png",
+            OutputCodec::H265,
+            EncoderBackend::Mpp,
+            640,
+            480,
+            FallbackAspect::Letterbox,
         );
+        assert!(pipeline.contains("mpph265enc"));
+        assert!(pipeline.contains("video/x-h265"));
+        assert!(!pipeline.contains("h264"));
+    }
 
-        Ok(Self {
-            data: Arc::new(frame_data),
-        })
+    #[test]
+    fn test_h265_fallback_pipeline_without_mpp() {
+        for backend in [
+            EncoderBackend::X264,
+            EncoderBackend::Vaapi(VaapiElement::Va),
+        ] {
+            let still = pipeline_string(
+                "/tmp/offline.png",
+                OutputCodec::H265,
+                backend,
+                640,
+                480,
+                FallbackAspect::Letterbox,
+            );
+            assert!(still.contains("x265enc tune=zerolatency key-int-max=1"));
+            assert!(still.contains("video/x-h265"));
+            assert!(!still.contains("mpph265enc"));
+
+            let clip = clip_pipeline_string(
+                "/tmp/offline.mp4",
+                OutputCodec::H265,
+                backend,
+                640,
+                480,
+                FallbackAspect::Letterbox,
+            );
+            assert!(clip.contains("x265enc tune=zerolatency speed-preset=veryfast key-int-max=30"));
+            assert!(!clip.contains("mpph265enc"));
+        }
+    }
+
+    #[test]
+    fn test_fallback_matches_source_size() {
+        let pipeline = pipeline_string(
+            "/tmp/offline.png",
+            OutputCodec::H264,
+            EncoderBackend::X264,
+            1920,
+            1080,
+            FallbackAspect::Letterbox,
+        );
+        assert!(pipeline.contains("videoscale add-borders=true ! video/x-raw,width=1920,height=1080,pixel-aspect-ratio=1/1"));
+
+        let stretched = pipeline_string(
+            "/tmp/offline.png",
+            OutputCodec::H265,
+            EncoderBackend::Mpp,
+            1280,
+            720,
+            FallbackAspect::Stretch,
+        );
+        assert!(
+            stretched.contains("videoscale add-borders=false ! video/x-raw,width=1280,height=720,")
+        );
+    }
+
+    #[test]
+    fn test_parse_spec() {
+        let parse = |s| FallbackSpec::parse(s).unwrap();
+        assert_eq!(parse("color:black"), Some(FallbackSpec::Color(0x000000)));
+        assert_eq!(parse("color:Blue"), Some(FallbackSpec::Color(0x0000FF)));
+        assert_eq!(parse("color:#1e90ff"), Some(FallbackSpec::Color(0x1E90FF)));
+        assert_eq!(
+            parse("text:NO SIGNAL"),
+            Some(FallbackSpec::Text("NO SIGNAL".to_string()))
+        );
+        assert_eq!(
+            parse("text:Back at 10:30"),
+            Some(FallbackSpec::Text("Back at 10:30".to_string()))
+        );
+
+        // File paths are not specs
+        assert_eq!(parse("/srv/offline.png"), None);
+        assert_eq!(parse("offline.png"), None);
+        assert_eq!(parse("/srv/cam:1/offline.png"), None);
+
+        assert!(FallbackSpec::parse("colour:black").is_err());
+        assert!(FallbackSpec::parse("http://example.com/offline.png").is_err());
+        assert!(FallbackSpec::parse("color:teal-ish").is_err());
+        assert!(FallbackSpec::parse("color:#12345").is_err());
+        assert!(FallbackSpec::parse("text:  ").is_err());
     }
 
-    /// Get the frame data
-    pub fn data(&self) -> &[u8] {
-        &self.data
+    #[test]
+    fn test_spec_pipeline() {
+        let color = spec_pipeline_string(
+            &FallbackSpec::Color(0x1E90FF),
+            OutputCodec::H264,
+            EncoderBackend::X264,
+            1280,
+            720,
+        );
+        assert!(color.starts_with(
+            "videotestsrc num-buffers=1 pattern=solid-color foreground-color=0xFF1E90FF"
+        ));
+        assert!(color.contains(
+            "video/x-raw,width=1280,height=720 ! videoconvert ! x264enc tune=stillimage"
+        ));
+        assert!(!color.contains("textoverlay"));
+
+        let text = spec_pipeline_string(
+            &FallbackSpec::Text("Say \"hi\"".to_string()),
+            OutputCodec::H265,
+            EncoderBackend::Mpp,
+            1280,
+            720,
+        );
+        assert!(text.contains("foreground-color=0xFF000000"));
+        assert!(text.contains("textoverlay text=\"Say \\\"hi\\\"\" valignment=center halignment=center font-desc=\"Sans 36\" ! videoconvert ! mpph265enc"));
+    }
+
+    #[test]
+    #[ignore = "needs a GStreamer runtime with videotestsrc, textoverlay and x264enc"]
+    fn test_spec_produces_encoded_frame() {
+        for spec in ["color:red", "text:NO SIGNAL"] {
+            let fallback = FallbackSource::load(
+                spec,
+                OutputCodec::H264,
+                EncoderBackend::X264,
+                (320, 240),
+                FallbackAspect::Letterbox,
+            )
+            .unwrap();
+            let frames = fallback.frames();
+            assert_eq!(frames.len(), 1);
+            assert!(frames[0].is_keyframe);
+            assert!(frames[0].buffer.size() > 0);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a GStreamer runtime with pngenc, pngdec and x264enc"]
+    fn test_png_fallback_encodes_annex_b() {
+        gstreamer::init().unwrap();
+
+        let png = std::env::temp_dir().join(format!("dart-fallback-{}.png", std::process::id()));
+        let pipeline = gstreamer::parse::launch(&format!(
+            "videotestsrc num-buffers=1 ! video/x-raw,width=320,height=240 ! pngenc ! filesink location=\"{}\"",
+            png.display()
+        ))
+        .unwrap();
+        pipeline.set_state(gstreamer::State::Playing).unwrap();
+        pipeline.bus().unwrap().timed_pop_filtered(
+            gstreamer::ClockTime::from_seconds(5),
+            &[gstreamer::MessageType::Eos],
+        );
+        pipeline.set_state(gstreamer::State::Null).unwrap();
+
+        let fallback = FallbackSource::load(
+            &png,
+            OutputCodec::H264,
+            EncoderBackend::X264,
+            (320, 240),
+            FallbackAspect::Letterbox,
+        )
+        .unwrap();
+        std::fs::remove_file(&png).ok();
+
+        let frame = &fallback.frames()[0];
+        assert!(frame.is_keyframe);
+        let data = frame.buffer.map_readable().unwrap();
+        assert!(
+            data.starts_with(&[0, 0, 0, 1]) || data.starts_with(&[0, 0, 1]),
+            "no Annex-B start code"
+        );
+    }
+
+    #[test]
+    fn test_size_for_defaults_to_640x480() {
+        let config: SourceConfig = toml::from_str(
+            r#"
+            name = "cam1"
+            type = "rtsp"
+            url = "rtsp://camera.local/stream"
+        "#,
+        )
+        .unwrap();
+        assert_eq!(size_for(&config), (640, 480));
+    }
+
+    #[test]
+    fn test_still_image_resent_at_fallback_fps() {
+        gstreamer::init().unwrap();
+        let config: SourceConfig = toml::from_str(
+            r#"
+            name = "cam1"
+            type = "test"
+            fallback = "/srv/offline.png"
+            fallback_fps = 10
+        "#,
+        )
+        .unwrap();
+        let image = FallbackSource::Image(FallbackFrame {
+            buffer: gstreamer::Buffer::new(),
+            is_keyframe: true,
+        });
+
+        for index in 0..3 {
+            assert_eq!(
+                image.frame_delay(index, config.fallback_interval()),
+                Duration::from_millis(100)
+            );
+        }
+    }
+
+    #[test]
+    fn test_clip_detection() {
+        assert!(is_clip(Path::new("/srv/reconnecting.mp4")));
+        assert!(is_clip(Path::new("/srv/reconnecting.MOV")));
+        assert!(!is_clip(Path::new("/srv/offline.png")));
+        assert!(!is_clip(Path::new("/srv/offline")));
+    }
+
+    #[test]
+    fn test_clip_pipeline() {
+        let pipeline = clip_pipeline_string(
+            "/srv/reconnecting.mp4",
+            OutputCodec::H264,
+            EncoderBackend::X264,
+            1280,
+            720,
+            FallbackAspect::Letterbox,
+        );
+        assert!(pipeline.contains("width=1280,height=720"));
+        assert!(pipeline.contains("x264enc tune=zerolatency speed-preset=veryfast key-int-max=30"));
+        assert!(!pipeline.contains("stillimage"));
+    }
+
+    #[test]
+    #[ignore = "needs a GStreamer runtime with x264enc, mp4mux and avdec_h264"]
+    fn test_clip_produces_looping_frames() {
+        gstreamer::init().unwrap();
+
+        // Two seconds of a moving pattern at 25 fps
+        let clip = std::env::temp_dir().join(format!("dart-fallback-{}.mp4", std::process::id()));
+        let pipeline = gstreamer::parse::launch(&format!(
+            "videotestsrc num-buffers=50 pattern=ball ! video/x-raw,width=320,height=240,framerate=25/1 \
+             ! x264enc ! mp4mux ! filesink location=\"{}\"",
+            clip.display()
+        ))
+        .unwrap();
+        pipeline.set_state(gstreamer::State::Playing).unwrap();
+        pipeline.bus().unwrap().timed_pop_filtered(
+            gstreamer::ClockTime::from_seconds(10),
+            &[gstreamer::MessageType::Eos],
+        );
+        pipeline.set_state(gstreamer::State::Null).unwrap();
+
+        let fallback = FallbackSource::load(
+            &clip,
+            OutputCodec::H264,
+            EncoderBackend::X264,
+            (320, 240),
+            FallbackAspect::Letterbox,
+        )
+        .unwrap();
+        std::fs::remove_file(&clip).ok();
+
+        let frames = fallback.frames();
+        assert!(frames.len() > 1);
+        assert!(frames[0].is_keyframe);
+        assert_ne!(
+            frames[0].buffer.map_readable().unwrap().as_slice(),
+            frames[1].buffer.map_readable().unwrap().as_slice()
+        );
+        assert_eq!(
+            fallback.frame_delay(0, Duration::from_secs(1)),
+            Duration::from_millis(40)
+        );
+    }
+
+    /// A 1x1 GIF with one frame per palette index, each shown for `delay_cs`
+    /// hundredths of a second
+    fn animated_gif(colors: &[u8], delay_cs: u16) -> Vec<u8> {
+        let mut gif = b"GIF89a".to_vec();
+        // 1x1 screen with a 4-entry global palette: black, red, green, blue
+        gif.extend_from_slice(&[1, 0, 1, 0, 0x81, 0, 0]);
+        gif.extend_from_slice(&[0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 255]);
+        for &color in colors {
+            let [lo, hi] = delay_cs.to_le_bytes();
+            gif.extend_from_slice(&[0x21, 0xF9, 4, 0, lo, hi, 0, 0]);
+            gif.extend_from_slice(&[0x2C, 0, 0, 0, 0, 1, 0, 1, 0, 0]);
+            // LZW, 3-bit codes: clear (4), the pixel, end (5)
+            gif.extend_from_slice(&[2, 2, 4 | (color << 3) | (1 << 6), 1, 0]);
+        }
+        gif.push(0x3B);
+        gif
+    }
+
+    #[test]
+    fn test_gif_detection() {
+        assert!(is_gif(Path::new("/srv/offline.gif")));
+        assert!(is_gif(Path::new("/srv/offline.GIF")));
+        assert!(!is_gif(Path::new("/srv/offline.png")));
+        assert!(!is_clip(Path::new("/srv/offline.gif")));
+    }
+
+    #[test]
+    fn test_gif_delays() {
+        gstreamer::init().unwrap();
+        let frame = |pts_ms: Option<u64>, duration_ms: Option<u64>| {
+            let mut buffer = gstreamer::Buffer::new();
+            let b = buffer.get_mut().unwrap();
+            b.set_pts(pts_ms.map(gstreamer::ClockTime::from_mseconds));
+            b.set_duration(duration_ms.map(gstreamer::ClockTime::from_mseconds));
+            FallbackFrame {
+                buffer,
+                is_keyframe: false,
+            }
+        };
+
+        let frames = [
+            frame(Some(0), None),
+            frame(Some(200), None),
+            frame(Some(250), Some(500)),
+        ];
+        let ms = Duration::from_millis;
+        assert_eq!(gif_delays(&frames), [ms(200), ms(50), ms(500)]);
+
+        // No timing at all: every frame gets the default
+        let untimed = [frame(None, None), frame(None, None)];
+        assert_eq!(gif_delays(&untimed), [DEFAULT_GIF_DELAY, DEFAULT_GIF_DELAY]);
+    }
+
+    #[test]
+    #[ignore = "needs a GStreamer runtime with x264enc and a GIF decoder"]
+    fn test_animated_gif_loops_every_frame() {
+        let gif = std::env::temp_dir().join(format!("dart-fallback-{}.gif", std::process::id()));
+        std::fs::write(&gif, animated_gif(&[1, 2, 3], 20)).unwrap();
+
+        let fallback = FallbackSource::from_image(
+            &gif,
+            OutputCodec::H264,
+            EncoderBackend::X264,
+            (320, 240),
+            FallbackAspect::Letterbox,
+        )
+        .unwrap();
+        std::fs::remove_file(&gif).ok();
+
+        let frames = fallback.frames();
+        assert_eq!(frames.len(), 3);
+        assert!(frames[0].is_keyframe);
+        let data: Vec<_> = frames
+            .iter()
+            .map(|f| f.buffer.map_readable().unwrap().to_vec())
+            .collect();
+        assert_ne!(data[0], data[1]);
+        assert_ne!(data[1], data[2]);
+        assert_ne!(data[0], data[2]);
+        assert_eq!(
+            fallback.frame_delay(1, Duration::from_secs(1)),
+            Duration::from_millis(200)
+        );
     }
 }
@@ -0,0 +1,237 @@
+//! HLS output - segments a source's encoded frames to disk for HTTP clients
+//!
+//! Pipeline: appsrc -> h26Xparse -> hlssink2
+//!
+//! Segments are written to `{hls_directory}/{name}/` and served over HTTP at
+//! `/{name}/index.m3u8` by the file handler in this module, behind the
+//! source's `[sources.auth]` like its other outputs.
+
+use crate::config::OutputCodec;
+use crate::http::{source_authorized, Request, Response};
+use crate::rtsp::{FrameReceiver, StreamKind};
+use crate::sources::Source;
+use anyhow::{Context, Result};
+use gstreamer::prelude::*;
+use gstreamer_app::AppSrc;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+
+/// Playlist file name written by hlssink2
+pub const PLAYLIST_NAME: &str = "index.m3u8";
+
+/// Start an HLS output for a source, fed from `frames`
+pub fn start(
+    name: &str,
+    codec: OutputCodec,
+    directory: &Path,
+    mut frames: FrameReceiver,
+) -> Result<()> {
+    let segment_dir = directory.join(name);
+    std::fs::create_dir_all(&segment_dir)
+        .with_context(|| format!("Failed to create HLS directory: {}", segment_dir.display()))?;
+
+    let pipeline_str = pipeline_string(codec, &segment_dir);
+    debug!(
+        "HLS pipeline for '{}': {}",
+        name,
+        crate::logging::redact_pipeline(&pipeline_str)
+    );
+
+    let pipeline = gstreamer::parse::launch(&pipeline_str)
+        .context("Failed to create HLS pipeline")?
+        .downcast::<gstreamer::Pipeline>()
+        .map_err(|_| anyhow::anyhow!("Failed to downcast to Pipeline"))?;
+
+    let appsrc = pipeline
+        .by_name("videosrc")
+        .ok_or_else(|| anyhow::anyhow!("HLS pipeline missing 'videosrc' element"))?
+        .dynamic_cast::<AppSrc>()
+        .map_err(|_| anyhow::anyhow!("Failed to cast to AppSrc"))?;
+
+    pipeline
+        .set_state(gstreamer::State::Playing)
+        .map_err(|e| anyhow::anyhow!("Failed to start HLS pipeline: {:?}", e))?;
+
+    info!(
+        "Started HLS output for '{}': {}",
+        name,
+        segment_dir.display()
+    );
+
+    let name = name.to_string();
+    std::thread::spawn(move || {
+        // Segments must start on a keyframe
+        while let Ok(frame) = frames.recv_from_keyframe() {
+            // Video only; forwarded audio is not muxed into the segments
+            if frame.kind != StreamKind::Video {
+                continue;
+            }
+
+            if let Err(e) = appsrc.push_buffer(frame.output_buffer()) {
+                warn!("HLS output for '{}' stopped: {:?}", name, e);
+                break;
+            }
+        }
+
+        pipeline.set_state(gstreamer::State::Null).ok();
+        debug!("HLS output for '{}' ended", name);
+    });
+
+    Ok(())
+}
+
+/// Build the HLS segmenting launch string
+fn pipeline_string(codec: OutputCodec, segment_dir: &Path) -> String {
+    let (caps, parse) = match codec {
+        OutputCodec::H264 => (crate::sources::h264_caps(), "h264parse"),
+        OutputCodec::H265 => (crate::sources::h265_caps(), "h265parse"),
+    };
+
+    format!(
+        "appsrc name=videosrc is-live=true format=time do-timestamp=true caps={caps} \
+         ! {parse} \
+         ! hlssink2 location=\"{dir}/segment%05d.ts\" \
+           playlist-location=\"{dir}/{playlist}\" \
+           target-duration=2 playlist-length=5 max-files=10",
+        caps = caps,
+        parse = parse,
+        dir = segment_dir.display(),
+        playlist = PLAYLIST_NAME,
+    )
+}
+
+/// Serve playlists and segments from the HLS directory, for sources with
+/// HLS enabled
+pub fn handle_request(directory: &Path, sources: &[Arc<Source>], request: &Request) -> Response {
+    if request.method != "GET" {
+        return Response::text(405, "Method Not Allowed");
+    }
+
+    let Some((name, path)) = resolve_path(directory, &request.path) else {
+        return Response::not_found();
+    };
+    let Some(source) = sources.iter().find(|s| s.name() == name && s.config().hls) else {
+        return Response::not_found();
+    };
+    if !source_authorized(source.config().auth.as_ref(), request) {
+        return Response::unauthorized();
+    }
+
+    match std::fs::read(&path) {
+        Ok(body) => Response::ok(content_type(&path), body),
+        Err(_) => Response::not_found(),
+    }
+}
+
+/// Map `/{name}/{file}` to the source name and a file in the HLS directory,
+/// rejecting traversal
+fn resolve_path<'a>(directory: &Path, request_path: &'a str) -> Option<(&'a str, PathBuf)> {
+    let mut parts = request_path.trim_start_matches('/').split('/');
+    let name = parts.next()?;
+    let file = parts.next()?;
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let safe = |s: &str| !s.is_empty() && !s.starts_with('.') && !s.contains('\\');
+    if !safe(name) || !safe(file) {
+        return None;
+    }
+
+    Some((name, directory.join(name).join(file)))
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("m3u8") => "application/vnd.apple.mpegurl",
+        Some("ts") => "video/mp2t",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline_string_matches_codec() {
+        let h264 = pipeline_string(OutputCodec::H264, Path::new("/tmp/hls/cam1"));
+        assert!(h264.contains("caps=video/x-h264"));
+        assert!(h264.contains("! h264parse ! hlssink2"));
+        assert!(h264.contains("playlist-location=\"/tmp/hls/cam1/index.m3u8\""));
+
+        let h265 = pipeline_string(OutputCodec::H265, Path::new("/tmp/hls/cam1"));
+        assert!(h265.contains("caps=video/x-h265"));
+        assert!(h265.contains("! h265parse ! hlssink2"));
+    }
+
+    #[test]
+    fn test_serves_hls_sources_behind_their_auth() {
+        let dir = std::env::temp_dir().join(format!("dart-hls-test-{}", std::process::id()));
+        for name in ["cam1", "cam2"] {
+            std::fs::create_dir_all(dir.join(name)).unwrap();
+            std::fs::write(dir.join(name).join(PLAYLIST_NAME), "#EXTM3U\n").unwrap();
+        }
+
+        let source = |toml: &str| {
+            let config = toml::from_str(toml).unwrap();
+            Arc::new(
+                Source::new(
+                    config,
+                    Arc::new(crate::rtsp::FrameBus::default()),
+                    None,
+                    crate::sources::EncoderBackend::X264,
+                )
+                .unwrap(),
+            )
+        };
+        let sources = [
+            source(
+                r#"
+                name = "cam1"
+                type = "rtsp"
+                url = "rtsp://camera.local/stream"
+                hls = true
+                [auth]
+                enabled = true
+                username = "admin"
+                password = "secret"
+            "#,
+            ),
+            // Files left behind by a source that no longer has HLS
+            source("name = \"cam2\"\ntype = \"rtsp\"\nurl = \"rtsp://camera.local/stream\""),
+        ];
+        let get = |path: &str, authorization: Option<&str>| {
+            let request = Request {
+                method: "GET".to_string(),
+                path: path.to_string(),
+                headers: authorization
+                    .map(|value| ("authorization".to_string(), value.to_string()))
+                    .into_iter()
+                    .collect(),
+                body: Vec::new(),
+            };
+            handle_request(&dir, &sources, &request).status
+        };
+
+        assert_eq!(get("/cam1/index.m3u8", None), 401);
+        // admin:secret
+        assert_eq!(get("/cam1/index.m3u8", Some("Basic YWRtaW46c2VjcmV0")), 200);
+        assert_eq!(get("/cam2/index.m3u8", None), 404);
+        assert_eq!(get("/cam3/index.m3u8", None), 404);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_path() {
+        let dir = Path::new("/tmp/hls");
+        assert_eq!(
+            resolve_path(dir, "/cam1/index.m3u8"),
+            Some(("cam1", PathBuf::from("/tmp/hls/cam1/index.m3u8")))
+        );
+        assert_eq!(resolve_path(dir, "/cam1/../../etc/passwd"), None);
+        assert_eq!(resolve_path(dir, "/../index.m3u8"), None);
+        assert_eq!(resolve_path(dir, "/cam1"), None);
+    }
+}
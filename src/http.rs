@@ -0,0 +1,488 @@
+//! Minimal HTTP/1.1 server for the auxiliary endpoints (HLS, status, ...)
+//!
+//! One thread per connection, one request per connection. Good enough for
+//! dashboards and supervisors polling a handful of URLs; not a general web server.
+//! Listens on TCP, or on a UNIX socket for a reverse proxy on the same host.
+
+use crate::config::AuthConfig;
+use anyhow::{Context, Result};
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info};
+
+/// Prefix selecting a UNIX socket in an address setting
+pub const UNIX_PREFIX: &str = "unix:";
+
+/// Largest request body accepted (an SDP offer is a few KiB)
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// How long a write may block on a client that stopped reading before the
+/// connection is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where an HTTP server listens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Bind {
+    Tcp {
+        address: String,
+        port: u16,
+    },
+    /// UNIX socket at this path
+    Unix(PathBuf),
+}
+
+impl Bind {
+    /// A `unix:/path` address as a socket path; None for anything else
+    pub fn unix_path(address: &str) -> Option<PathBuf> {
+        address.strip_prefix(UNIX_PREFIX).map(PathBuf::from)
+    }
+}
+
+impl fmt::Display for Bind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp { address, port } => write!(f, "{}:{}", address, port),
+            Self::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+/// Parsed HTTP request
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// Header names are lowercased
+    pub headers: Vec<(String, String)>,
+    /// Empty unless the request carried a Content-Length
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Value of a header, by case-insensitive name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// HTTP response
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: String,
+    /// Extra headers besides Content-Type and Content-Length
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Written after the headers in place of `body`, for responses of unknown
+    /// length such as MJPEG
+    pub stream: Option<StreamBody>,
+}
+
+/// Writes a streamed response body until the client goes away or the stream
+/// ends; the connection closes when it returns
+pub type StreamWriter = Box<dyn FnOnce(&mut dyn Write) -> std::io::Result<()> + Send>;
+
+/// A streamed body, wrapped so `Response` stays `Debug`
+pub struct StreamBody(StreamWriter);
+
+impl fmt::Debug for StreamBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StreamBody")
+    }
+}
+
+impl Response {
+    /// 200 response with the given content type
+    pub fn ok(content_type: &str, body: Vec<u8>) -> Self {
+        Self {
+            status: 200,
+            content_type: content_type.to_string(),
+            headers: Vec::new(),
+            body,
+            stream: None,
+        }
+    }
+
+    /// 200 response whose body is written by `write` as it is produced
+    pub fn stream<F>(content_type: &str, write: F) -> Self
+    where
+        F: FnOnce(&mut dyn Write) -> std::io::Result<()> + Send + 'static,
+    {
+        Self {
+            stream: Some(StreamBody(Box::new(write))),
+            ..Self::ok(content_type, Vec::new())
+        }
+    }
+
+    /// Plain-text response with an arbitrary status
+    pub fn text(status: u16, body: &str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain".to_string(),
+            headers: Vec::new(),
+            body: body.as_bytes().to_vec(),
+            stream: None,
+        }
+    }
+
+    /// 404 response
+    pub fn not_found() -> Self {
+        Self::text(404, "Not Found")
+    }
+
+    /// 401 response asking for Basic credentials
+    pub fn unauthorized() -> Self {
+        Self::text(401, "Unauthorized").with_header("WWW-Authenticate", "Basic realm=\"dart\"")
+    }
+
+    /// Add a header to the response
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// Whether the request carries a source's `[sources.auth]` credentials as
+/// HTTP Basic auth, for endpoints that serve the source's video outside RTSP.
+/// Always true when the source has no auth enabled.
+pub fn source_authorized(auth: Option<&AuthConfig>, request: &Request) -> bool {
+    let Some(auth) = auth.filter(|a| a.enabled) else {
+        return true;
+    };
+    let Some(encoded) = request
+        .header("authorization")
+        .and_then(|value| value.strip_prefix("Basic "))
+    else {
+        return false;
+    };
+    let decoded = glib::base64_decode(encoded.trim());
+    let Some((username, password)) = std::str::from_utf8(&decoded)
+        .ok()
+        .and_then(|credentials| credentials.split_once(':'))
+    else {
+        return false;
+    };
+    auth.username.as_deref() == Some(username) && auth.password.as_deref() == Some(password)
+}
+
+/// Request handler shared between connection threads
+pub type Handler = Arc<dyn Fn(&Request) -> Response + Send + Sync>;
+
+/// Bind an HTTP server and serve requests in a background thread
+pub fn serve(bind: &Bind, handler: Handler) -> Result<()> {
+    match bind {
+        Bind::Tcp { address, port } => {
+            let listener = TcpListener::bind((address.as_str(), *port))
+                .with_context(|| format!("Failed to bind HTTP server on {}", bind))?;
+            info!("HTTP server listening on {}", bind);
+            std::thread::spawn(move || {
+                let incoming = listener.incoming().map(|stream| {
+                    stream.and_then(|s| s.set_write_timeout(Some(WRITE_TIMEOUT)).map(|()| s))
+                });
+                accept(incoming, handler)
+            });
+        }
+        Bind::Unix(path) => {
+            let listener = bind_unix(path)
+                .with_context(|| format!("Failed to bind HTTP server on {}", bind))?;
+            info!("HTTP server listening on {}", bind);
+            std::thread::spawn(move || {
+                let incoming = listener.incoming().map(|stream| {
+                    stream.and_then(|s| s.set_write_timeout(Some(WRITE_TIMEOUT)).map(|()| s))
+                });
+                accept(incoming, handler)
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Bind a UNIX socket, replacing a stale socket left by an earlier run.
+/// Anything else already at the path is left alone and reported.
+fn bind_unix(path: &Path) -> Result<UnixListener> {
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            anyhow::bail!("{} exists and is not a socket", path.display());
+        }
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+    }
+    Ok(UnixListener::bind(path)?)
+}
+
+/// Serve each accepted connection on its own thread
+fn accept<S, I>(incoming: I, handler: Handler)
+where
+    S: Read + Write + Send + 'static,
+    I: Iterator<Item = std::io::Result<S>>,
+{
+    for stream in incoming {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let handler = Arc::clone(&handler);
+        std::thread::spawn(move || {
+            if let Err(e) = handle_connection(stream, &handler) {
+                debug!("HTTP connection error: {}", e);
+            }
+        });
+    }
+}
+
+/// Read one request, dispatch it and write the response
+fn handle_connection<S: Read + Write>(mut stream: S, handler: &Handler) -> Result<()> {
+    let response = match parse_request(&mut BufReader::new(&mut stream)) {
+        Ok(request) => handler(&request),
+        Err(e) => {
+            debug!("Bad HTTP request: {}", e);
+            Response::text(400, "Bad Request")
+        }
+    };
+
+    write_response(&mut stream, response)?;
+    Ok(())
+}
+
+/// Parse the request line, headers and any Content-Length body
+fn parse_request<R: BufRead>(reader: &mut R) -> Result<Request> {
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Empty request line"))?
+        .to_string();
+    let target = parts
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Missing request target"))?;
+    // Query strings are not used by any endpoint
+    let path = target.split('?').next().unwrap_or(target).to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+
+    let length = headers
+        .iter()
+        .find(|(name, _)| name == "content-length")
+        .map(|(_, value)| value.parse::<usize>())
+        .transpose()
+        .context("Invalid Content-Length")?
+        .unwrap_or(0);
+    if length > MAX_BODY_BYTES {
+        anyhow::bail!("Request body of {} bytes is too large", length);
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Request {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
+
+/// Serialize a response and close the connection. A streamed body has no
+/// Content-Length; it ends when the connection closes.
+fn write_response<W: Write>(writer: &mut W, response: Response) -> std::io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\n",
+        response.status,
+        reason_phrase(response.status),
+        response.content_type,
+    )?;
+    if response.stream.is_none() {
+        write!(writer, "Content-Length: {}\r\n", response.body.len())?;
+    }
+    for (name, value) in &response.headers {
+        write!(writer, "{}: {}\r\n", name, value)?;
+    }
+    write!(writer, "Connection: close\r\n\r\n")?;
+
+    match response.stream {
+        Some(StreamBody(write)) => {
+            writer.flush()?;
+            write(writer)?;
+        }
+        None => writer.write_all(&response.body)?,
+    }
+    writer.flush()
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let raw = b"GET /cam1/index.m3u8?x=1 HTTP/1.1\r\nHost: dart\r\n\r\n";
+        let request = parse_request(&mut &raw[..]).unwrap();
+
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/cam1/index.m3u8");
+        assert_eq!(request.header("host"), Some("dart"));
+        assert_eq!(request.header("Authorization"), None);
+        assert!(request.body.is_empty());
+
+        let raw = b"POST /whep/cam1 HTTP/1.1\r\nContent-Length: 5\r\n\r\nv=0\r\n";
+        let request = parse_request(&mut &raw[..]).unwrap();
+        assert_eq!(request.body, b"v=0\r\n");
+
+        let raw = b"POST /whep/cam1 HTTP/1.1\r\nContent-Length: 1000000\r\n\r\n";
+        assert!(parse_request(&mut &raw[..]).is_err());
+    }
+
+    #[test]
+    fn test_source_authorized() {
+        let auth = AuthConfig {
+            enabled: true,
+            username: Some("admin".to_string()),
+            password: Some("pa:ss".to_string()),
+            method: Default::default(),
+        };
+        let request = |authorization: Option<&str>| Request {
+            method: "GET".to_string(),
+            path: "/cam1/mjpeg".to_string(),
+            headers: authorization
+                .map(|value| ("authorization".to_string(), value.to_string()))
+                .into_iter()
+                .collect(),
+            body: Vec::new(),
+        };
+
+        let allowed = |authorization| source_authorized(Some(&auth), &request(authorization));
+
+        // admin:pa:ss, the password may contain colons
+        assert!(allowed(Some("Basic YWRtaW46cGE6c3M=")));
+        // admin:wrong
+        assert!(!allowed(Some("Basic YWRtaW46d3Jvbmc=")));
+        assert!(!allowed(Some("Bearer pa:ss")));
+        assert!(!allowed(None));
+
+        // No auth, or auth turned off, lets everyone in
+        assert!(source_authorized(None, &request(None)));
+        let disabled = AuthConfig {
+            enabled: false,
+            ..auth
+        };
+        assert!(source_authorized(Some(&disabled), &request(None)));
+
+        let response = Response::unauthorized();
+        assert_eq!(response.status, 401);
+        assert_eq!(response.headers[0].0, "WWW-Authenticate");
+    }
+
+    #[test]
+    fn test_unix_socket() {
+        assert_eq!(
+            Bind::unix_path("unix:/run/dart/api.sock"),
+            Some(PathBuf::from("/run/dart/api.sock"))
+        );
+        assert_eq!(Bind::unix_path("127.0.0.1"), None);
+        assert_eq!(
+            Bind::Unix(PathBuf::from("/run/dart/api.sock")).to_string(),
+            "unix:/run/dart/api.sock"
+        );
+
+        let path = std::env::temp_dir().join(format!("dart-http-{}.sock", std::process::id()));
+        let bind = Bind::Unix(path.clone());
+        let get = || {
+            let mut stream = std::os::unix::net::UnixStream::connect(&path).unwrap();
+            stream.write_all(b"GET /status HTTP/1.1\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        serve(
+            &bind,
+            Arc::new(|request| Response::ok("text/plain", request.path.clone().into_bytes())),
+        )
+        .unwrap();
+        assert!(get().ends_with("\r\n\r\n/status"));
+
+        // A socket left behind by an earlier run is replaced
+        serve(&bind, Arc::new(|_| Response::text(503, "restarted"))).unwrap();
+        assert!(get().starts_with("HTTP/1.1 503"));
+        std::fs::remove_file(&path).unwrap();
+
+        // Other files are never clobbered, and missing directories are reported
+        std::fs::write(&path, "not a socket").unwrap();
+        let err = format!(
+            "{:#}",
+            serve(&bind, Arc::new(|_| Response::not_found())).unwrap_err()
+        );
+        assert!(err.contains("is not a socket"));
+        std::fs::remove_file(&path).unwrap();
+
+        let missing = Bind::Unix(PathBuf::from("/nonexistent/dart/api.sock"));
+        let err = format!(
+            "{:#}",
+            serve(&missing, Arc::new(|_| Response::not_found())).unwrap_err()
+        );
+        assert!(err.contains("Failed to bind HTTP server on unix:/nonexistent/dart/api.sock"));
+    }
+
+    #[test]
+    fn test_write_response() {
+        let mut out = Vec::new();
+        write_response(&mut out, Response::text(404, "Not Found")).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(out.contains("Content-Length: 9\r\n"));
+        assert!(out.ends_with("\r\n\r\nNot Found"));
+
+        let mut out = Vec::new();
+        let response = Response::text(201, "").with_header("Location", "/whep/cam1/1");
+        write_response(&mut out, response).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 201 Created\r\n"));
+        assert!(out.contains("\r\nLocation: /whep/cam1/1\r\n"));
+
+        // Streamed bodies go out without a length
+        let mut out = Vec::new();
+        let response = Response::stream("text/plain", |w| w.write_all(b"chunk"));
+        write_response(&mut out, response).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(!out.contains("Content-Length"));
+        assert!(out.ends_with("Connection: close\r\n\r\nchunk"));
+    }
+}
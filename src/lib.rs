@@ -0,0 +1,23 @@
+//! dart's sources, pipelines and servers, usable from other binaries
+//!
+//! The `dart` binary is a thin CLI over these modules. Embedders build a
+//! [`sources::Source`] from a [`config::SourceConfig`] and match source
+//! failures on [`error::DartError`].
+
+pub mod compat;
+pub mod config;
+pub mod control;
+pub mod error;
+pub mod fallback;
+pub mod hls;
+pub mod http;
+pub mod logging;
+pub mod mjpeg;
+pub mod record;
+pub mod rtmp;
+pub mod rtsp;
+pub mod snapshot;
+pub mod sources;
+pub mod srt;
+pub mod status;
+pub mod webrtc;
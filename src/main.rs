@@ -1,62 +1,160 @@
-mod config;
+mod check;
 mod config_wizard;
-mod fallback;
-mod rtsp;
-mod sources;
+mod dry_run;
 
-use anyhow::Result;
+use dart::{
+    compat, config, control, fallback, hls, http, logging, mjpeg, record, rtmp, rtsp, snapshot,
+    sources, srt, status, webrtc,
+};
+
+use anyhow::{Context, Result};
 use clap::Parser;
-use config::{OutputCodec, SourceType};
-use fallback::FallbackFrame;
-use std::path::PathBuf;
-use std::sync::Arc;
-use tracing::{error, info, warn};
+use fallback::FallbackSource;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use sources::{EncoderBackend, VaapiElement};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
 
 #[derive(Parser)]
 #[command(name = "dart")]
 #[command(about = "Universal RTSP restreamer - accepts V4L2 and RTSP inputs")]
 struct Args {
-    /// Path to configuration file
-    #[arg(short, long, default_value = "config.toml")]
-    config: PathBuf,
+    /// Path to configuration file, a directory of `*.toml` fragments, or `-`
+    /// to read TOML from stdin. Without it, inline TOML in `DART_CONFIG` is
+    /// used if set, otherwise `config.toml`
+    #[arg(short, long)]
+    config: Option<PathBuf>,
 
     /// Interactively create a new configuration file
     #[arg(long)]
     config_new: bool,
+
+    /// Validate the config and print each source's pipeline, then exit
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Probe each source once and print a pass/fail table, then exit
+    /// (non-zero if any source is unreachable)
+    #[arg(long)]
+    check: bool,
+
+    /// Seconds `--check` waits for each source to answer
+    #[arg(long, default_value_t = 5, value_name = "SECONDS")]
+    check_timeout: u64,
+
+    /// Write each source's pipeline graph (GraphViz .dot) to this directory
+    /// once it is playing, or when it fails
+    #[arg(long, value_name = "DIR")]
+    dump_dot: Option<PathBuf>,
+
+    /// Log line format: human-readable text or JSON for log shippers
+    #[arg(long, value_enum, default_value_t = logging::LogFormat::Text)]
+    log_format: logging::LogFormat,
+
+    /// Print the version, git commit, GStreamer runtime and detected
+    /// hardware encoders, then exit
+    #[arg(short = 'V', long)]
+    version: bool,
+}
+
+/// Running sources keyed by name, shared with the HTTP API
+type Registry = Arc<Mutex<BTreeMap<String, Arc<sources::Source>>>>;
+
+/// What the main thread is woken up for
+enum Signal {
+    Shutdown,
+    Reload,
 }
 
 fn main() -> Result<()> {
     // Parse CLI args
     let args = Args::parse();
 
+    if args.version {
+        gstreamer::init()?;
+        println!(
+            "{}",
+            version_string(sources::mpp_available(), sources::vaapi_available())
+        );
+        return Ok(());
+    }
+
     // Handle --config-new
     if args.config_new {
-        return config_wizard::run(&args.config);
+        let path = args
+            .config
+            .as_deref()
+            .unwrap_or(Path::new(config::DEFAULT_CONFIG_PATH));
+        if path == Path::new("-") {
+            anyhow::bail!("--config-new writes a file; pass --config <path>");
+        }
+        return config_wizard::run(path);
     }
 
     // Initialize logging
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env()
-                .add_directive("dart=info".parse().unwrap()),
-        )
-        .init();
+    let log_levels = logging::init(args.log_format)?;
+
+    // Sources write their graphs here themselves; GST_DEBUG_DUMP_DOT_DIR is
+    // left alone, since GStreamer's own dumps include element properties
+    if let Some(dir) = &args.dump_dot {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create dot dump directory: {}", dir.display()))?;
+        sources::set_dot_dump_dir(dir.clone());
+    }
 
     // Initialize GStreamer
     gstreamer::init()?;
     info!("GStreamer initialized");
 
-    // Detect MPP support once
+    // Detect encoder support once
     let mpp = sources::mpp_available();
-    if mpp {
-        info!("Rockchip MPP detected — using hardware H.265 encoding");
-    } else {
-        info!("MPP not available — using software x264 H.264 encoding");
+    let vaapi = sources::vaapi_available();
+    match EncoderBackend::select(mpp, vaapi) {
+        EncoderBackend::Mpp => {
+            info!("Rockchip MPP detected — using hardware H.265 encoding")
+        }
+        EncoderBackend::Vaapi(element) => {
+            info!(
+                "VAAPI detected ({:?}) — using hardware H.264 encoding",
+                element
+            )
+        }
+        EncoderBackend::X264 => {
+            info!("No hardware encoder available — using software x264 H.264 encoding")
+        }
+    }
+
+    let config_env = std::env::var(config::CONFIG_ENV).ok();
+    if args.config.is_some() && config_env.is_some() {
+        warn!(
+            "Both --config and {} are set; using --config",
+            config::CONFIG_ENV
+        );
+    }
+    let config_source = config::ConfigSource::resolve(args.config.as_deref(), config_env);
+
+    // Only plan the pipelines; nothing is bound or opened
+    if args.dry_run {
+        return dry_run::run(&config_source, mpp, vaapi, &mut std::io::stdout());
+    }
+
+    // Probe the sources without starting the server
+    if args.check {
+        let timeout = Duration::from_secs(args.check_timeout);
+        if !check::run(&config_source, timeout, &mut std::io::stdout())? {
+            std::process::exit(1);
+        }
+        return Ok(());
     }
 
     // Load configuration
-    let config = config::Config::load(&args.config)?;
-    info!("Loaded config from: {}", args.config.display());
+    let config = config::Config::load_from(&config_source)?;
+    log_levels.set_from_config(&config.sources);
+    info!("Loaded config from: {}", config_source);
     info!(
         "Server: {}:{}, {} source(s)",
         config.server.bind_address,
@@ -64,125 +162,203 @@ fn main() -> Result<()> {
         config.sources.len()
     );
 
+    // Resolve each source's encoder override against what was detected
+    let backends = config
+        .sources
+        .iter()
+        .map(|source| resolve_backend(source, mpp, vaapi))
+        .collect::<Result<Vec<_>>>()?;
+
     // Create RTSP server
     let rtsp_server = rtsp::RtspServer::new(config.server.rtsp_port, &config.server.bind_address)?;
+    if let Some((cert, key)) = config.server.tls() {
+        rtsp_server.set_tls_certificate(cert, key)?;
+    }
 
-    // Track active source names for display and RTSP sources that need the Source abstraction
-    let mut active_source_names: Vec<String> = Vec::new();
-    let mut active_sources: Vec<Arc<sources::Source>> = Vec::new();
-
-    for source_config in config.sources {
-        info!(
-            "Setting up source: {} ({:?})",
-            source_config.name, source_config.source_type
-        );
+    // Running sources by name; reloads add and remove entries
+    let registry: Registry = Arc::default();
 
-        match source_config.source_type {
-            SourceType::V4l2 => {
-                // V4L2 sources use direct factory launch — the RTSP server manages
-                // the full pipeline. No appsrc, no Source thread needed.
-                match rtsp_server.add_v4l2_mount(&source_config, mpp) {
-                    Ok(()) => {
-                        active_source_names.push(source_config.name.clone());
-                    }
-                    Err(e) => {
-                        error!("Failed to add V4L2 mount for '{}': {}", source_config.name, e);
-                    }
-                }
-            }
-            SourceType::Rtsp => {
-                // RTSP sources use appsrc pattern (rtspsrc has dynamic pads)
-                let codec = if source_config.transcode && mpp {
-                    OutputCodec::H265
-                } else {
-                    OutputCodec::H264
-                };
-
-                // Load fallback image if configured
-                let fallback = if let Some(fallback_path) = &source_config.fallback {
-                    match FallbackFrame::from_image(fallback_path, mpp) {
-                        Ok(f) => {
-                            info!(
-                                "Loaded fallback image for '{}': {}",
-                                source_config.name, fallback_path
-                            );
-                            Some(f)
-                        }
-                        Err(e) => {
-                            warn!(
-                                "Failed to load fallback image for '{}': {}",
-                                source_config.name, e
-                            );
-                            None
-                        }
-                    }
-                } else {
-                    None
-                };
-
-                let frame_tx = match rtsp_server.add_mount(&source_config, codec) {
-                    Ok(tx) => tx,
-                    Err(e) => {
-                        error!("Failed to add mount for '{}': {}", source_config.name, e);
-                        continue;
-                    }
-                };
-
-                let source_name = source_config.name.clone();
-
-                let source = match sources::Source::new(source_config, frame_tx, fallback, mpp) {
-                    Ok(s) => Arc::new(s),
-                    Err(e) => {
-                        error!("Failed to create source '{}': {}", source_name, e);
-                        rtsp_server.remove_mount(&source_name);
-                        continue;
-                    }
-                };
-
-                if let Err(e) = Arc::clone(&source).start() {
-                    error!("Failed to start source '{}': {}", source_name, e);
-                    rtsp_server.remove_mount(&source_name);
-                    continue;
-                }
+    // Serve HLS playlists if enabled
+    if let Some(hls_port) = config.server.hls_port {
+        let hls_directory = config.server.hls_directory.clone();
+        let hls_registry = Arc::clone(&registry);
+        let bind = http::Bind::Tcp {
+            address: config.server.bind_address.clone(),
+            port: hls_port,
+        };
+        http::serve(
+            &bind,
+            Arc::new(move |request| {
+                let sources: Vec<_> = hls_registry.lock().unwrap().values().cloned().collect();
+                hls::handle_request(&hls_directory, &sources, request)
+            }),
+        )?;
+    }
 
-                active_source_names.push(source_name);
-                active_sources.push(source);
+    for (source_config, backend) in config.sources.into_iter().zip(backends) {
+        let source_name = source_config.name.clone();
+        match start_source(&config.server, &rtsp_server, source_config, backend) {
+            Ok(source) => {
+                registry.lock().unwrap().insert(source_name, source);
             }
+            Err(e) => error!("Failed to set up source '{}': {:#}", source_name, e),
         }
     }
 
-    if active_source_names.is_empty() {
-        anyhow::bail!("No sources started successfully");
-    }
+    let active_sources: Vec<Arc<sources::Source>> =
+        registry.lock().unwrap().values().cloned().collect();
+    ensure_started(active_sources.len(), &config.server)?;
 
-    info!("{} source(s) active", active_source_names.len());
+    info!("{} source(s) active", active_sources.len());
 
-    // Start RTSP server
+    // Start RTSP server; with rtsp_port = 0 the OS picks the port
     rtsp_server.start()?;
+    let rtsp_port = rtsp_server.bound_port().unwrap_or(config.server.rtsp_port);
+
+    // Serve the HTTP API (status, snapshots, MJPEG, control, WHEP) if enabled
+    if let Some(bind) = config.server.http_bind() {
+        let api_registry = Arc::clone(&registry);
+        let unhealthy_after = Duration::from_secs(config.server.unhealthy_after);
+        let control_token = config.server.control_token.clone();
+        let webrtc_sessions = Arc::new(webrtc::Sessions::default());
+        http::serve(
+            &bind,
+            Arc::new(move |request| {
+                if control::handles(&request.path) {
+                    // A copy, so stopping or starting a source doesn't hold the
+                    // registry against reloads and other requests
+                    let sources = api_registry.lock().unwrap().clone();
+                    return control::handle_request(&sources, control_token.as_deref(), request);
+                }
+
+                let api_sources: Vec<_> = api_registry.lock().unwrap().values().cloned().collect();
+                if webrtc::handles(&request.path) {
+                    webrtc::handle_request(&webrtc_sessions, &api_sources, request)
+                } else if request.path.ends_with(mjpeg::SUFFIX) {
+                    mjpeg::handle_request(&api_sources, request)
+                } else if request.path.ends_with("/snapshot.jpg") {
+                    snapshot::handle_request(&api_sources, request)
+                } else {
+                    status::handle_request(&api_sources, rtsp_port, unhealthy_after, request)
+                }
+            }),
+        )?;
+    }
 
     // Print available streams
     println!("\nAvailable RTSP streams:");
-    for name in &active_source_names {
-        println!(
-            "  rtsp://{}:{}/{}/stream",
-            config.server.bind_address, config.server.rtsp_port, name
-        );
+    for source in &active_sources {
+        for mount_path in source.config().mount_paths() {
+            println!("  {}", config.server.rtsp_url_on(rtsp_port, &mount_path));
+        }
+    }
+    if let Some(hls_port) = config.server.hls_port {
+        let hls_sources: Vec<_> = active_sources.iter().filter(|s| s.config().hls).collect();
+        if !hls_sources.is_empty() {
+            println!("\nAvailable HLS streams:");
+            for source in hls_sources {
+                println!(
+                    "  http://{}:{}/{}/{}",
+                    config.server.url_host(),
+                    hls_port,
+                    source.name(),
+                    hls::PLAYLIST_NAME
+                );
+            }
+        }
+    }
+    let webrtc_sources: Vec<_> = active_sources
+        .iter()
+        .filter(|s| s.config().webrtc)
+        .collect();
+    if let Some(http::Bind::Tcp { address, port }) = config.server.http_bind() {
+        if !webrtc_sources.is_empty() {
+            println!("\nAvailable WebRTC (WHEP) endpoints:");
+            for source in webrtc_sources {
+                println!(
+                    "  http://{}:{}{}/{}",
+                    config::url_host(&address),
+                    port,
+                    webrtc::PREFIX,
+                    source.name()
+                );
+            }
+        }
+    }
+    let srt_outputs: Vec<_> = active_sources
+        .iter()
+        .filter_map(|s| srt::SrtTarget::from_config(s.config()).map(|t| (s.name(), t)))
+        .collect();
+    if !srt_outputs.is_empty() {
+        println!("\nAvailable SRT streams:");
+        for (name, target) in &srt_outputs {
+            println!(
+                "  {} ({})",
+                target.display_url(&config.server.url_host()),
+                name
+            );
+        }
+    }
+    let rtmp_outputs: Vec<_> = active_sources
+        .iter()
+        .filter_map(|s| s.config().rtmp.as_ref().map(|r| (s.name(), r)))
+        .collect();
+    if !rtmp_outputs.is_empty() {
+        println!("\nRTMP outputs:");
+        for (name, rtmp_config) in &rtmp_outputs {
+            println!("  {} ({})", rtmp::display_url(&rtmp_config.url), name);
+        }
+    }
+    match config.server.http_bind() {
+        Some(http::Bind::Unix(path)) => {
+            println!("\nStatus: GET /status on {}", path.display());
+        }
+        Some(http::Bind::Tcp { address, port }) => {
+            println!(
+                "\nStatus: http://{}:{}/status",
+                config::url_host(&address),
+                port
+            );
+        }
+        None => {}
     }
     println!();
+    drop(active_sources);
 
-    // Wait for Ctrl+C
-    info!("Press Ctrl+C to stop");
-    let (tx, rx) = std::sync::mpsc::channel();
-    ctrlc::set_handler(move || {
-        let _ = tx.send(());
-    })
-    .expect("Error setting Ctrl+C handler");
+    // Wait for Ctrl+C or SIGTERM, reloading the config on SIGHUP
+    info!(
+        "Press Ctrl+C to stop, send SIGHUP to reload {}",
+        config_source
+    );
+    let (tx, rx) = mpsc::channel();
+    let mut signals =
+        Signals::new([SIGHUP, SIGINT, SIGTERM]).context("Failed to register signal handlers")?;
+    std::thread::spawn(move || {
+        let mut shutting_down = false;
+        for signal in signals.forever() {
+            if !forward_signal(signal, &mut shutting_down, &tx) {
+                warn!("Second stop signal received, exiting immediately");
+                std::process::exit(128 + signal);
+            }
+        }
+    });
 
-    rx.recv().ok();
+    while let Ok(Signal::Reload) = rx.recv() {
+        reload(
+            &config_source,
+            &config.server,
+            &rtsp_server,
+            &registry,
+            &log_levels,
+            mpp,
+            vaapi,
+        );
+    }
 
     // Shutdown
     info!("Shutting down...");
-    for source in &active_sources {
+    for source in registry.lock().unwrap().values() {
+        debug!("Stopping source '{}' ({:?})", source.name(), source.state());
         source.stop();
     }
     rtsp_server.stop();
@@ -190,3 +366,356 @@ fn main() -> Result<()> {
     info!("Goodbye!");
     Ok(())
 }
+
+/// Pass a signal on to the main thread. Returns false for a second SIGINT or
+/// SIGTERM, when shutdown is already under way and the process should exit now.
+fn forward_signal(signal: i32, shutting_down: &mut bool, tx: &mpsc::Sender<Signal>) -> bool {
+    if signal == SIGHUP {
+        let _ = tx.send(Signal::Reload);
+        return true;
+    }
+    if std::mem::replace(shutting_down, true) {
+        return false;
+    }
+    let _ = tx.send(Signal::Shutdown);
+    true
+}
+
+/// Resolve a source's encoder override against the detected hardware
+fn resolve_backend(
+    source: &config::SourceConfig,
+    mpp: bool,
+    vaapi: Option<VaapiElement>,
+) -> Result<EncoderBackend> {
+    let choice = source.encode_config().encoder;
+    let backend = EncoderBackend::resolve(choice, mpp, vaapi)
+        .with_context(|| format!("Source '{}'", source.name))?;
+    if choice != config::EncoderChoice::Auto {
+        info!("Source '{}' encoder forced to {:?}", source.name, backend);
+    }
+    Ok(backend)
+}
+
+/// Mount a source with its outputs and start it
+fn start_source(
+    server: &config::ServerConfig,
+    rtsp_server: &rtsp::RtspServer,
+    source_config: config::SourceConfig,
+    backend: EncoderBackend,
+) -> Result<Arc<sources::Source>> {
+    info!(
+        "Setting up source: {} ({:?})",
+        source_config.name, source_config.source_type
+    );
+
+    // Every source feeds an appsrc mount so fallback frames can be injected
+    // while it reconnects.
+    let codec = source_config.output_codec(backend);
+
+    // The compat mount's x264 is the only H.264 encoder left to constrain
+    let encode = source_config.encode_config();
+    if source_config.encodes()
+        && codec == config::OutputCodec::H265
+        && !source_config.compat_h264
+        && (encode.profile.is_some() || encode.level.is_some())
+    {
+        warn!(
+            "Source '{}' is encoded as H.265; profile and level only apply to H.264",
+            source_config.name
+        );
+    }
+
+    // Load fallback image or clip if configured, encoded to match the mount codec and size
+    let fallback = if let Some(fallback_path) = &source_config.fallback {
+        match FallbackSource::load(
+            fallback_path,
+            codec,
+            backend,
+            fallback::size_for(&source_config),
+            source_config.fallback_aspect,
+        ) {
+            Ok(f) => {
+                info!(
+                    "Loaded fallback for '{}': {}",
+                    source_config.name, fallback_path
+                );
+                Some(f)
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to load fallback image for '{}': {}",
+                    source_config.name, e
+                );
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let frame_tx = rtsp_server
+        .add_mount(&source_config, codec)
+        .context("Failed to add mount")?;
+
+    // One more mount per rendition, each fed by its own encoder branch
+    let mut renditions = Vec::new();
+    for rendition in &source_config.renditions {
+        match rtsp_server.add_rendition_mount(&source_config, rendition, codec) {
+            Ok(bus) => renditions.push((rendition.name.clone(), bus)),
+            Err(e) => {
+                rtsp_server.remove_mount(&source_config.name);
+                return Err(e).context("Failed to add rendition mount");
+            }
+        }
+    }
+
+    // H.264 transcode of an H.265 stream for clients that can't decode it
+    let compat = match (source_config.compat_h264, codec) {
+        (true, config::OutputCodec::H265) => match rtsp_server.add_compat_mount(&source_config) {
+            Ok(bus) => Some(bus),
+            Err(e) => {
+                rtsp_server.remove_mount(&source_config.name);
+                return Err(e).context("Failed to add H.264 compat mount");
+            }
+        },
+        (true, config::OutputCodec::H264) => {
+            warn!(
+                "Source '{}' already produces H.264; compat_h264 has no effect",
+                source_config.name
+            );
+            None
+        }
+        (false, _) => None,
+    };
+    if let Some(bus) = &compat {
+        if let Err(e) = compat::start(
+            &source_config.name,
+            &source_config.encode_config(),
+            &frame_tx,
+            Arc::clone(bus),
+        ) {
+            warn!(
+                "Failed to start H.264 compat for '{}': {:#}",
+                source_config.name, e
+            );
+        }
+    }
+
+    let source_name = source_config.name.clone();
+
+    // Tee the encoded frames into an HLS segmenter if requested
+    if source_config.hls {
+        if let Err(e) = hls::start(
+            &source_name,
+            codec,
+            &server.hls_directory,
+            frame_tx.subscribe(),
+        ) {
+            warn!("Failed to start HLS for '{}': {}", source_name, e);
+        }
+    }
+
+    // Record to disk if requested
+    if let Some(record_config) = &source_config.record {
+        if let Err(e) = record::start(&source_name, codec, record_config, frame_tx.subscribe()) {
+            warn!("Failed to start recording for '{}': {}", source_name, e);
+        }
+    }
+
+    // Push to an RTMP ingest
+    if let Some(rtmp_config) = &source_config.rtmp {
+        if let Err(e) = rtmp::start(&source_name, codec, rtmp_config, frame_tx.subscribe()) {
+            warn!("Failed to start RTMP output for '{}': {:#}", source_name, e);
+        }
+    }
+
+    // Same for SRT output
+    if let Some(target) = srt::SrtTarget::from_config(&source_config) {
+        if let Err(e) = srt::start(
+            &source_name,
+            codec,
+            &target,
+            &server.url_host(),
+            frame_tx.subscribe(),
+        ) {
+            warn!("Failed to start SRT output for '{}': {}", source_name, e);
+        }
+    }
+
+    let source = sources::Source::new(source_config, frame_tx, fallback, backend)
+        .map(|source| {
+            Arc::new(
+                source
+                    .with_renditions(renditions)
+                    .with_compat(compat)
+                    .with_idle_release(server.idle_release_secs)
+                    .with_idle_pause(server.pause_when_idle),
+            )
+        })
+        .and_then(|source| Arc::clone(&source).start().map(|()| source));
+    if source.is_err() {
+        rtsp_server.remove_mount(&source_name);
+    }
+    source
+}
+
+/// Decide whether startup can go on with `active` sources set up. None is
+/// only fine with `allow_empty_start`; a later reload adds the rest.
+fn ensure_started(active: usize, server: &config::ServerConfig) -> Result<()> {
+    if active > 0 {
+        return Ok(());
+    }
+    if !server.allow_empty_start {
+        anyhow::bail!(
+            "No sources started successfully (set [server] allow_empty_start to serve anyway)"
+        );
+    }
+    warn!("No sources started; serving anyway (allow_empty_start), send SIGHUP to retry them");
+    Ok(())
+}
+
+/// Re-read the config file and apply source changes. Untouched sources keep
+/// streaming; server settings need a restart and are only warned about.
+fn reload(
+    config_source: &config::ConfigSource,
+    server: &config::ServerConfig,
+    rtsp_server: &rtsp::RtspServer,
+    registry: &Registry,
+    log_levels: &logging::SourceLevels,
+    mpp: bool,
+    vaapi: Option<VaapiElement>,
+) {
+    // Stdin was consumed at startup; reading it again would find nothing
+    if *config_source == config::ConfigSource::Stdin {
+        warn!("Config was read from stdin and can't be reloaded; restart to change it");
+        return;
+    }
+    info!("Reloading config from: {}", config_source);
+    let config = match config::Config::load_from(config_source) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Reload failed, keeping the running config: {:#}", e);
+            return;
+        }
+    };
+    log_levels.set_from_config(&config.sources);
+
+    for setting in server.changed_settings(&config.server) {
+        warn!("[server] {} changed; restart to apply it", setting);
+    }
+
+    // Pick up renewed certificates
+    if let (Some(_), Some((cert, key))) = (server.tls(), config.server.tls()) {
+        if let Err(e) = rtsp_server.set_tls_certificate(cert, key) {
+            error!("Keeping the current TLS certificate: {:#}", e);
+        }
+    }
+
+    let diff = {
+        let mut running = registry.lock().unwrap();
+        let diff =
+            config::SourceDiff::between(running.values().map(|s| s.config()), &config.sources);
+
+        // Stop removed sources, and changed ones so they can be started afresh
+        for name in diff.removed.iter().chain(&diff.changed) {
+            if let Some(source) = running.remove(name) {
+                source.stop();
+                rtsp_server.remove_mount(name);
+            }
+        }
+        diff
+    };
+
+    if diff.is_empty() {
+        info!("Reload: sources unchanged");
+        return;
+    }
+    info!(
+        "Reload: {} added, {} removed, {} changed",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.changed.len()
+    );
+
+    for source_config in config.sources {
+        let name = source_config.name.clone();
+        if !diff.added.contains(&name) && !diff.changed.contains(&name) {
+            continue;
+        }
+
+        let started = resolve_backend(&source_config, mpp, vaapi)
+            .and_then(|backend| start_source(server, rtsp_server, source_config, backend));
+        match started {
+            Ok(source) => {
+                registry.lock().unwrap().insert(name, source);
+            }
+            Err(e) => error!("Failed to set up source '{}': {:#}", name, e),
+        }
+    }
+}
+
+/// `--version` output: what to paste into a support ticket
+fn version_string(mpp: bool, vaapi: Option<VaapiElement>) -> String {
+    let vaapi = match vaapi {
+        Some(element) => format!("yes ({:?})", element),
+        None => "no".to_string(),
+    };
+    format!(
+        "dart {} ({})\n{}\nMPP: {}\nVAAPI: {}",
+        env!("CARGO_PKG_VERSION"),
+        env!("DART_GIT_HASH"),
+        gstreamer::version_string(),
+        if mpp { "yes" } else { "no" },
+        vaapi
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_string() {
+        let version = version_string(false, Some(VaapiElement::Va));
+        let lines: Vec<_> = version.lines().collect();
+        assert!(lines[0].starts_with(&format!("dart {} (", env!("CARGO_PKG_VERSION"))));
+        assert_eq!(lines[1], gstreamer::version_string().as_str());
+        assert!(lines[1].starts_with("GStreamer 1."));
+        assert_eq!(lines[2], "MPP: no");
+        assert_eq!(lines[3], "VAAPI: yes (Va)");
+    }
+
+    #[test]
+    fn test_empty_start() {
+        let server = |toml: &str| -> config::ServerConfig { toml::from_str(toml).unwrap() };
+
+        let strict = server("");
+        assert!(!strict.allow_empty_start);
+        assert!(ensure_started(1, &strict).is_ok());
+        let err = ensure_started(0, &strict).unwrap_err().to_string();
+        assert!(err.contains("No sources started successfully"));
+
+        let lenient = server("allow_empty_start = true");
+        assert!(ensure_started(0, &lenient).is_ok());
+    }
+
+    #[test]
+    fn test_stop_signals() {
+        for stop in [SIGINT, SIGTERM] {
+            let (tx, rx) = mpsc::channel();
+            let mut shutting_down = false;
+
+            assert!(forward_signal(SIGHUP, &mut shutting_down, &tx));
+            assert!(matches!(rx.try_recv(), Ok(Signal::Reload)));
+
+            // Either stop signal takes the Ctrl+C shutdown path
+            assert!(forward_signal(stop, &mut shutting_down, &tx));
+            assert!(matches!(rx.try_recv(), Ok(Signal::Shutdown)));
+
+            // A second one, of either kind, means exit now
+            assert!(!forward_signal(SIGINT, &mut shutting_down, &tx));
+            assert!(!forward_signal(SIGTERM, &mut shutting_down, &tx));
+            assert!(rx.try_recv().is_err());
+        }
+    }
+}
@@ -0,0 +1,259 @@
+//! MJPEG over HTTP - `GET /{name}/mjpeg` on the HTTP API port
+//!
+//! Each client gets its own decoder, throttled to `[sources.mjpeg] fps`:
+//! appsrc -> h26Xparse -> decodebin -> videoconvert -> videorate -> jpegenc -> appsink
+//!
+//! Pictures are sent as `multipart/x-mixed-replace` parts, which browsers and
+//! panels that only speak MJPEG show as live video. While the source stalls,
+//! the last picture is sent again every few seconds so a client that went
+//! away is noticed and its decoder stopped.
+//!
+//! A source with `[sources.auth]` enabled requires the same credentials as
+//! HTTP Basic auth.
+
+use crate::config::OutputCodec;
+use crate::http::{source_authorized, Request, Response};
+use crate::rtsp::{FrameBus, StreamKind};
+use crate::sources::Source;
+use anyhow::{Context, Result};
+use gstreamer::prelude::*;
+use gstreamer_app::{AppSink, AppSrc};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Path suffix routed to this module
+pub const SUFFIX: &str = "/mjpeg";
+
+/// Separates the JPEGs in the multipart body
+const BOUNDARY: &str = "dartframe";
+
+/// How often the pusher and writer check whether the client went away
+const POLL: Duration = Duration::from_millis(500);
+
+/// Longest the writer stays silent; a write is how a dead socket shows up
+const KEEPALIVE: Duration = Duration::from_secs(5);
+
+/// Handle an MJPEG request
+pub fn handle_request(sources: &[Arc<Source>], request: &Request) -> Response {
+    let Some(name) = request
+        .path
+        .strip_prefix('/')
+        .and_then(|p| p.strip_suffix(SUFFIX))
+    else {
+        return Response::not_found();
+    };
+    if request.method != "GET" {
+        return Response::text(405, "Method Not Allowed");
+    }
+
+    let Some(source) = sources.iter().find(|s| s.name() == name) else {
+        return Response::not_found();
+    };
+    let Some(mjpeg) = &source.config().mjpeg else {
+        return Response::not_found();
+    };
+    if !source_authorized(source.config().auth.as_ref(), request) {
+        return Response::unauthorized();
+    }
+
+    match Client::start(name, source.frame_bus(), source.output_codec(), mjpeg.fps) {
+        Ok(client) => Response::stream(&content_type(), move |out| client.run(out))
+            .with_header("Cache-Control", "no-cache"),
+        Err(e) => {
+            warn!("MJPEG for '{}' failed: {:#}", name, e);
+            Response::text(500, "MJPEG failed")
+        }
+    }
+}
+
+fn content_type() -> String {
+    format!("multipart/x-mixed-replace; boundary={}", BOUNDARY)
+}
+
+/// Write one JPEG as a multipart part
+fn write_part(out: &mut dyn Write, jpeg: &[u8]) -> std::io::Result<()> {
+    write!(
+        out,
+        "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+        BOUNDARY,
+        jpeg.len()
+    )?;
+    out.write_all(jpeg)?;
+    out.write_all(b"\r\n")?;
+    out.flush()
+}
+
+/// Build the decode-and-JPEG launch string
+fn pipeline_string(codec: OutputCodec, fps: u32) -> String {
+    let (caps, parse) = match codec {
+        OutputCodec::H264 => (crate::sources::h264_caps(), "h264parse"),
+        OutputCodec::H265 => (crate::sources::h265_caps(), "h265parse"),
+    };
+
+    // A slow client only ever gets the newest pictures
+    format!(
+        "appsrc name=src is-live=true format=time do-timestamp=true caps={caps} \
+         ! {parse} \
+         ! decodebin \
+         ! videoconvert \
+         ! videorate drop-only=true max-rate={fps} \
+         ! jpegenc \
+         ! appsink name=sink sync=false max-buffers=2 drop=true",
+        caps = caps,
+        parse = parse,
+        fps = fps,
+    )
+}
+
+/// One connected MJPEG client: its pipeline and the thread feeding it.
+/// Dropping it stops both.
+struct Client {
+    name: String,
+    pipeline: gstreamer::Pipeline,
+    sink: AppSink,
+    bus: Arc<FrameBus>,
+    running: Arc<AtomicBool>,
+}
+
+impl Client {
+    fn start(name: &str, bus: Arc<FrameBus>, codec: OutputCodec, fps: u32) -> Result<Self> {
+        let pipeline_str = pipeline_string(codec, fps);
+        debug!("MJPEG pipeline for '{}': {}", name, pipeline_str);
+
+        let pipeline = gstreamer::parse::launch(&pipeline_str)
+            .context("Failed to create MJPEG pipeline")?
+            .downcast::<gstreamer::Pipeline>()
+            .map_err(|_| anyhow::anyhow!("Failed to downcast to Pipeline"))?;
+        let appsrc = pipeline
+            .by_name("src")
+            .ok_or_else(|| anyhow::anyhow!("Missing src element"))?
+            .dynamic_cast::<AppSrc>()
+            .map_err(|_| anyhow::anyhow!("Failed to cast to AppSrc"))?;
+        let sink = pipeline
+            .by_name("sink")
+            .ok_or_else(|| anyhow::anyhow!("Missing sink element"))?
+            .dynamic_cast::<AppSink>()
+            .map_err(|_| anyhow::anyhow!("Failed to cast to AppSink"))?;
+
+        pipeline
+            .set_state(gstreamer::State::Playing)
+            .map_err(|e| anyhow::anyhow!("Failed to start MJPEG pipeline: {:?}", e))?;
+
+        let running = Arc::new(AtomicBool::new(true));
+        let mut frames = bus.subscribe();
+        bus.viewer_joined();
+        bus.request_keyframe();
+        info!("MJPEG client of '{}' connected", name);
+
+        let pusher_running = Arc::clone(&running);
+        std::thread::spawn(move || {
+            while pusher_running.load(Ordering::SeqCst) {
+                // The decoder can't start mid-GOP
+                let frame = match frames.recv_from_keyframe_timeout(POLL) {
+                    Ok(frame) => frame,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                if frame.kind != StreamKind::Video {
+                    continue;
+                }
+
+                if appsrc.push_buffer(frame.output_buffer()).is_err() {
+                    break;
+                }
+            }
+            // Lets the writer finish when the source goes away
+            appsrc.end_of_stream().ok();
+        });
+
+        Ok(Self {
+            name: name.to_string(),
+            pipeline,
+            sink,
+            bus,
+            running,
+        })
+    }
+
+    /// Send JPEGs to the client until it disconnects, the stream ends or the
+    /// decoder fails
+    fn run(self, out: &mut dyn Write) -> std::io::Result<()> {
+        let timeout = gstreamer::ClockTime::from_mseconds(POLL.as_millis() as u64);
+        let bus = self.pipeline.bus().expect("Pipeline without bus");
+        let mut last = None;
+        let mut last_write = Instant::now();
+        while !self.sink.is_eos() {
+            if let Some(msg) = bus.pop_filtered(&[gstreamer::MessageType::Error]) {
+                if let gstreamer::MessageView::Error(err) = msg.view() {
+                    warn!("MJPEG for '{}' failed: {}", self.name, err.error());
+                }
+                break;
+            }
+            match self.sink.try_pull_sample(timeout) {
+                Some(sample) => {
+                    write_sample(out, &sample)?;
+                    last = Some(sample);
+                }
+                None if last_write.elapsed() >= KEEPALIVE => match &last {
+                    Some(sample) => write_sample(out, sample)?,
+                    // Before the first picture, CRLFs are multipart preamble
+                    None => {
+                        out.write_all(b"\r\n")?;
+                        out.flush()?;
+                    }
+                },
+                None => continue,
+            }
+            last_write = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+/// Write a JPEG sample as a multipart part
+fn write_sample(out: &mut dyn Write, sample: &gstreamer::Sample) -> std::io::Result<()> {
+    match sample.buffer().map(|buffer| buffer.map_readable()) {
+        Some(Ok(map)) => write_part(out, map.as_slice()),
+        _ => Ok(()),
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        self.bus.viewer_left();
+        self.pipeline.set_state(gstreamer::State::Null).ok();
+        info!("MJPEG client of '{}' disconnected", self.name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multipart_framing() {
+        let mut out = Vec::new();
+        write_part(&mut out, b"\xFF\xD8first\xFF\xD9").unwrap();
+        write_part(&mut out, b"\xFF\xD8second\xFF\xD9").unwrap();
+
+        let expected: &[u8] = b"--dartframe\r\nContent-Type: image/jpeg\r\nContent-Length: 9\r\n\r\n\xFF\xD8first\xFF\xD9\r\n\
+            --dartframe\r\nContent-Type: image/jpeg\r\nContent-Length: 10\r\n\r\n\xFF\xD8second\xFF\xD9\r\n";
+        assert_eq!(out, expected);
+        assert_eq!(
+            content_type(),
+            "multipart/x-mixed-replace; boundary=dartframe"
+        );
+    }
+
+    #[test]
+    fn test_pipeline_string_throttles() {
+        let pipeline = pipeline_string(OutputCodec::H264, 2);
+        assert!(pipeline.contains("caps=video/x-h264"));
+        assert!(pipeline.contains("! h264parse ! decodebin ! videoconvert ! videorate drop-only=true max-rate=2 ! jpegenc"));
+    }
+}
@@ -0,0 +1,312 @@
+//! Recording - tees a source's encoded frames into rotating MP4 segments
+//!
+//! Pipeline: appsrc -> h26Xparse -> splitmuxsink (mp4mux)
+//!
+//! Segments are written to `{directory}/{name}/YYYYMMDD-HHMMSS-mmm.mp4` (UTC).
+//! When `max_files` is set the oldest segments are deleted as new ones are
+//! opened.
+//! A recording failure (disk full, directory removed, ...) only stops the
+//! recording; it is retried after `RETRY_INTERVAL` and the stream is unaffected.
+
+use crate::config::{OutputCodec, RecordConfig};
+use crate::rtsp::{FrameReceiver, StreamKind};
+use anyhow::{Context, Result};
+use gstreamer::prelude::*;
+use gstreamer_app::AppSrc;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+/// How long to wait before restarting a failed recording
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Start recording a source, fed from `frames`
+pub fn start(
+    name: &str,
+    codec: OutputCodec,
+    config: &RecordConfig,
+    mut frames: FrameReceiver,
+) -> Result<()> {
+    let segment_dir = config.directory.join(name);
+    std::fs::create_dir_all(&segment_dir).with_context(|| {
+        format!(
+            "Failed to create recording directory: {}",
+            segment_dir.display()
+        )
+    })?;
+
+    info!("Recording '{}' to {}", name, segment_dir.display());
+
+    let name = name.to_string();
+    let config = config.clone();
+    std::thread::spawn(move || loop {
+        match record(&name, codec, &config, &segment_dir, &mut frames) {
+            // Frame channel closed - the source is gone
+            Ok(()) => break,
+            Err(e) => {
+                warn!(
+                    "Recording for '{}' failed: {:#}. Retrying in {:?}",
+                    name, e, RETRY_INTERVAL
+                );
+                if !drain_for(&frames, RETRY_INTERVAL) {
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Run one recording pipeline until the frame channel closes (Ok) or it fails (Err)
+fn record(
+    name: &str,
+    codec: OutputCodec,
+    config: &RecordConfig,
+    segment_dir: &Path,
+    frames: &mut FrameReceiver,
+) -> Result<()> {
+    let pipeline_str = pipeline_string(codec, config.segment_seconds);
+    debug!(
+        "Recording pipeline for '{}': {}",
+        name,
+        crate::logging::redact_pipeline(&pipeline_str)
+    );
+
+    let pipeline = gstreamer::parse::launch(&pipeline_str)
+        .context("Failed to create recording pipeline")?
+        .downcast::<gstreamer::Pipeline>()
+        .map_err(|_| anyhow::anyhow!("Failed to downcast to Pipeline"))?;
+
+    let appsrc = pipeline
+        .by_name("videosrc")
+        .ok_or_else(|| anyhow::anyhow!("Recording pipeline missing 'videosrc' element"))?
+        .dynamic_cast::<AppSrc>()
+        .map_err(|_| anyhow::anyhow!("Failed to cast to AppSrc"))?;
+
+    let mux = pipeline
+        .by_name("mux")
+        .ok_or_else(|| anyhow::anyhow!("Recording pipeline missing 'mux' element"))?;
+
+    // Name each segment by wall-clock time and prune old ones as it opens
+    let dir = segment_dir.to_path_buf();
+    let max_files = config.max_files;
+    let source_name = name.to_string();
+    mux.connect("format-location", false, move |_| {
+        if let Some(max_files) = max_files {
+            // Make room for the segment about to be created
+            match prune_segments(&dir, max_files.saturating_sub(1)) {
+                Ok(removed) => {
+                    for path in removed {
+                        debug!("Pruned recording for '{}': {}", source_name, path.display());
+                    }
+                }
+                Err(e) => warn!("Failed to prune recordings for '{}': {}", source_name, e),
+            }
+        }
+
+        let path = dir.join(segment_filename(SystemTime::now()));
+        debug!(
+            "New recording segment for '{}': {}",
+            source_name,
+            path.display()
+        );
+        Some(path.to_string_lossy().to_value())
+    });
+
+    let bus = pipeline
+        .bus()
+        .ok_or_else(|| anyhow::anyhow!("Recording pipeline has no bus"))?;
+
+    pipeline
+        .set_state(gstreamer::State::Playing)
+        .map_err(|e| anyhow::anyhow!("Failed to start recording pipeline: {:?}", e))?;
+
+    // Segments must start on a keyframe, including after a restart
+    frames.resync();
+    let result = loop {
+        let Ok(frame) = frames.recv_from_keyframe() else {
+            break Ok(());
+        };
+
+        if let Some(msg) = bus.pop_filtered(&[gstreamer::MessageType::Error]) {
+            if let gstreamer::MessageView::Error(err) = msg.view() {
+                break Err(anyhow::anyhow!("{}", err.error()));
+            }
+        }
+
+        // Video only; forwarded audio is not muxed into the recording
+        if frame.kind != StreamKind::Video {
+            continue;
+        }
+
+        if let Err(e) = appsrc.push_buffer(frame.output_buffer()) {
+            break Err(anyhow::anyhow!("push failed: {:?}", e));
+        }
+    };
+
+    // Finalize the open segment so it stays playable
+    appsrc.end_of_stream().ok();
+    bus.timed_pop_filtered(
+        gstreamer::ClockTime::from_seconds(5),
+        &[gstreamer::MessageType::Eos, gstreamer::MessageType::Error],
+    );
+    pipeline.set_state(gstreamer::State::Null).ok();
+    debug!("Recording pipeline for '{}' stopped", name);
+
+    result
+}
+
+/// Discard frames for `duration`. Returns false if the channel closed.
+pub fn drain_for(frames: &FrameReceiver, duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return true;
+        }
+        match frames.recv_timeout(remaining) {
+            Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+}
+
+/// Build the recording launch string
+fn pipeline_string(codec: OutputCodec, segment_seconds: u64) -> String {
+    let (caps, parse) = match codec {
+        OutputCodec::H264 => (crate::sources::h264_caps(), "h264parse"),
+        OutputCodec::H265 => (crate::sources::h265_caps(), "h265parse"),
+    };
+
+    format!(
+        "appsrc name=videosrc is-live=true format=time do-timestamp=true caps={caps} \
+         ! {parse} \
+         ! splitmuxsink name=mux muxer-factory=mp4mux max-size-time={max_size_time}",
+        caps = caps,
+        parse = parse,
+        max_size_time = segment_seconds * 1_000_000_000,
+    )
+}
+
+/// Segment file name for a wall-clock time: `YYYYMMDD-HHMMSS-mmm.mp4` (UTC).
+/// Milliseconds keep short segments, or a restart right after one, from
+/// reusing a name.
+fn segment_filename(time: SystemTime) -> String {
+    let millis = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_millis())
+        .unwrap_or(0);
+    format!("{}-{:03}.mp4", timestamp(time), millis)
+}
+
+/// Wall-clock time as `YYYYMMDD-HHMMSS` (UTC), for file names
+pub fn timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+/// Delete the oldest `.mp4` segments in `dir` so at most `keep` remain.
+/// Returns the removed paths.
+fn prune_segments(dir: &Path, keep: usize) -> std::io::Result<Vec<PathBuf>> {
+    let mut segments: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "mp4"))
+        .collect();
+
+    if segments.len() <= keep {
+        return Ok(Vec::new());
+    }
+
+    // Timestamped names sort chronologically
+    segments.sort();
+    let excess = segments.len() - keep;
+    let removed: Vec<PathBuf> = segments.into_iter().take(excess).collect();
+    for path in &removed {
+        std::fs::remove_file(path)?;
+    }
+
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segment_filename() {
+        // 2024-02-29 13:05:09 UTC
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_211_909);
+        assert_eq!(segment_filename(time), "20240229-130509-000.mp4");
+        assert_eq!(segment_filename(UNIX_EPOCH), "19700101-000000-000.mp4");
+
+        // Segments within the same second still get distinct names
+        let later = time + Duration::from_millis(42);
+        assert_eq!(segment_filename(later), "20240229-130509-042.mp4");
+        assert!(segment_filename(time) < segment_filename(later));
+    }
+
+    #[test]
+    fn test_prune_segments_keeps_newest() {
+        let dir = std::env::temp_dir().join(format!("dart-record-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        for name in [
+            "20240101-000000.mp4",
+            "20240101-000100.mp4",
+            "20240101-000200.mp4",
+            "20240101-000300.mp4",
+            "notes.txt",
+        ] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let removed = prune_segments(&dir, 2).unwrap();
+        assert_eq!(
+            removed,
+            vec![
+                dir.join("20240101-000000.mp4"),
+                dir.join("20240101-000100.mp4")
+            ]
+        );
+        assert!(dir.join("20240101-000300.mp4").exists());
+        assert!(dir.join("notes.txt").exists());
+
+        // Already within the limit
+        assert!(prune_segments(&dir, 2).unwrap().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pipeline_string() {
+        let pipeline = pipeline_string(OutputCodec::H264, 60);
+        assert!(pipeline.contains("! h264parse ! splitmuxsink name=mux muxer-factory=mp4mux"));
+        assert!(pipeline.ends_with("max-size-time=60000000000"));
+    }
+}
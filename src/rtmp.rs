@@ -0,0 +1,183 @@
+//! RTMP output - pushes a source's encoded frames to an RTMP ingest
+//!
+//! Pipeline: appsrc -> h264parse -> flvmux -> rtmpsink
+//!
+//! FLV only carries H.264, so H.265 sources must transcode with an H.264
+//! encoder. The stream key in the URL is masked wherever it is logged.
+//! A dropped connection (ingest restart, network loss, ...) only stops the
+//! push; it is retried after `RETRY_INTERVAL` and the stream is unaffected.
+
+use crate::config::{OutputCodec, RtmpConfig};
+use crate::record::drain_for;
+use crate::rtsp::{FrameReceiver, StreamKind};
+use anyhow::{Context, Result};
+use gstreamer::prelude::*;
+use gstreamer_app::AppSrc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// How long to wait before reconnecting to the ingest
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Start an RTMP output for a source, fed from `frames`
+pub fn start(
+    name: &str,
+    codec: OutputCodec,
+    config: &RtmpConfig,
+    mut frames: FrameReceiver,
+) -> Result<()> {
+    let pipeline_str = pipeline_string(codec, &config.url)?;
+    let display_url = display_url(&config.url);
+    debug!(
+        "RTMP pipeline for '{}': {}",
+        name,
+        pipeline_str.replace(&config.url, &display_url)
+    );
+
+    let name = name.to_string();
+    std::thread::spawn(move || loop {
+        match push(&name, &pipeline_str, &display_url, &mut frames) {
+            // Frame channel closed - the source is gone
+            Ok(()) => break,
+            Err(e) => {
+                warn!(
+                    "RTMP output for '{}' failed: {:#}. Retrying in {:?}",
+                    name, e, RETRY_INTERVAL
+                );
+                if !drain_for(&frames, RETRY_INTERVAL) {
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Run one RTMP pipeline until the frame channel closes (Ok) or it fails (Err)
+fn push(
+    name: &str,
+    pipeline_str: &str,
+    display_url: &str,
+    frames: &mut FrameReceiver,
+) -> Result<()> {
+    let pipeline = gstreamer::parse::launch(pipeline_str)
+        .context("Failed to create RTMP pipeline")?
+        .downcast::<gstreamer::Pipeline>()
+        .map_err(|_| anyhow::anyhow!("Failed to downcast to Pipeline"))?;
+
+    let appsrc = pipeline
+        .by_name("videosrc")
+        .ok_or_else(|| anyhow::anyhow!("RTMP pipeline missing 'videosrc' element"))?
+        .dynamic_cast::<AppSrc>()
+        .map_err(|_| anyhow::anyhow!("Failed to cast to AppSrc"))?;
+
+    let bus = pipeline
+        .bus()
+        .ok_or_else(|| anyhow::anyhow!("RTMP pipeline has no bus"))?;
+
+    pipeline
+        .set_state(gstreamer::State::Playing)
+        .map_err(|e| anyhow::anyhow!("Failed to start RTMP pipeline: {:?}", e))?;
+
+    info!("Started RTMP output for '{}': {}", name, display_url);
+
+    // Ingests expect the stream to open with a keyframe, including after a restart
+    frames.resync();
+    let result = loop {
+        let Ok(frame) = frames.recv_from_keyframe() else {
+            break Ok(());
+        };
+
+        // rtmpsink posts an error when the ingest drops the connection
+        if let Some(msg) =
+            bus.pop_filtered(&[gstreamer::MessageType::Error, gstreamer::MessageType::Eos])
+        {
+            match msg.view() {
+                gstreamer::MessageView::Error(err) => {
+                    break Err(anyhow::anyhow!("{}", err.error()));
+                }
+                _ => break Err(anyhow::anyhow!("pipeline ended")),
+            }
+        }
+
+        // Video only; forwarded audio is not muxed into the FLV stream
+        if frame.kind != StreamKind::Video {
+            continue;
+        }
+
+        if let Err(e) = appsrc.push_buffer(frame.output_buffer()) {
+            break Err(anyhow::anyhow!("push failed: {:?}", e));
+        }
+    };
+
+    pipeline.set_state(gstreamer::State::Null).ok();
+    debug!("RTMP pipeline for '{}' stopped", name);
+
+    result
+}
+
+/// Build the RTMP output launch string. Fails for H.265, which FLV can't carry.
+fn pipeline_string(codec: OutputCodec, url: &str) -> Result<String> {
+    if codec == OutputCodec::H265 {
+        anyhow::bail!(
+            "RTMP needs H.264 but the source produces H.265; use an H.264 encoder (encoder = \"x264\" or \"vaapi\")"
+        );
+    }
+
+    // flvmux wants AVC with whole access units; h264parse converts
+    Ok(format!(
+        "appsrc name=videosrc is-live=true format=time do-timestamp=true caps={caps} \
+         ! h264parse \
+         ! video/x-h264,stream-format=avc,alignment=au \
+         ! flvmux streamable=true \
+         ! rtmpsink location=\"{url} live=1\" sync=false",
+        caps = crate::sources::h264_caps(),
+        url = url,
+    ))
+}
+
+/// The URL with its last path segment (the stream key) masked, for printing
+pub fn display_url(url: &str) -> String {
+    let path_start = url.find("://").map_or(0, |i| i + 3);
+    match url[path_start..].find('/').and_then(|_| url.rfind('/')) {
+        Some(slash) if slash + 1 < url.len() => format!("{}/***", &url[..slash]),
+        _ => url.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "rtmp://a.rtmp.youtube.com/live2/abcd-efgh-ijkl";
+
+    #[test]
+    fn test_pipeline_string() {
+        let pipeline = pipeline_string(OutputCodec::H264, URL).unwrap();
+        assert!(pipeline.contains("caps=video/x-h264"));
+        assert!(pipeline.contains(
+            "! h264parse ! video/x-h264,stream-format=avc,alignment=au ! flvmux streamable=true"
+        ));
+        assert!(pipeline.ends_with("! rtmpsink location=\"rtmp://a.rtmp.youtube.com/live2/abcd-efgh-ijkl live=1\" sync=false"));
+    }
+
+    #[test]
+    fn test_rejects_h265() {
+        let err = pipeline_string(OutputCodec::H265, URL)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("RTMP needs H.264 but the source produces H.265"));
+    }
+
+    #[test]
+    fn test_display_url_masks_stream_key() {
+        assert_eq!(display_url(URL), "rtmp://a.rtmp.youtube.com/live2/***");
+        assert_eq!(
+            display_url("rtmps://live.twitch.tv/app/live_123"),
+            "rtmps://live.twitch.tv/app/***"
+        );
+        assert_eq!(display_url("rtmp://ingest.local"), "rtmp://ingest.local");
+        assert_eq!(display_url("rtmp://ingest.local/"), "rtmp://ingest.local/");
+    }
+}
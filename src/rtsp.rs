@@ -1,27 +1,575 @@
-use crate::config::{AuthConfig, OutputCodec, SourceConfig};
-use crate::sources;
-use anyhow::Result;
+use crate::config::{
+    AuthConfig, AuthMethod, Framerate, MulticastConfig, OutputCodec, RenditionConfig, SourceConfig,
+    SourceType,
+};
+use crate::logging;
+use anyhow::{Context, Result};
 use gstreamer::prelude::*;
 use gstreamer_app::AppSrc;
+use gstreamer_rtsp_server::gio;
+use gstreamer_rtsp_server::gst_rtsp::RTSPAuthMethod;
 use gstreamer_rtsp_server::prelude::*;
-use std::sync::mpsc::Sender;
-use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{RecvError, RecvTimeoutError};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::time::{Duration, Instant};
 use tracing::{debug, error, info, warn};
 
 /// Frame data sent from source to RTSP output
+///
+/// Cloning is cheap: the buffer is reference counted, so every output shares
+/// the payload the source pipeline produced.
+#[derive(Clone)]
 pub struct FrameData {
-    pub data: Vec<u8>,
+    pub buffer: gstreamer::Buffer,
     pub is_keyframe: bool,
+    pub kind: StreamKind,
 }
 
-/// Handle to send frames to an RTSP output
-pub type FrameSender = Sender<FrameData>;
+impl FrameData {
+    /// Wrap an encoded buffer without copying its payload. Timestamps are
+    /// cleared so each output's appsrc stamps it on its own clock, and
+    /// DELTA_UNIT is set to match `is_keyframe`.
+    pub fn new(mut buffer: gstreamer::Buffer, is_keyframe: bool, kind: StreamKind) -> Self {
+        // Copies the buffer header only if it is shared; the memory never is
+        let buffer_ref = buffer.make_mut();
+        buffer_ref.set_pts(None);
+        buffer_ref.set_dts(None);
+        buffer_ref.set_duration(None);
+        if is_keyframe {
+            buffer_ref.unset_flags(gstreamer::BufferFlags::DELTA_UNIT);
+        } else {
+            buffer_ref.set_flags(gstreamer::BufferFlags::DELTA_UNIT);
+        }
+
+        Self {
+            buffer,
+            is_keyframe,
+            kind,
+        }
+    }
+
+    /// Buffer for one output to push. Each output gets its own header (its
+    /// appsrc writes timestamps into it) over the shared payload.
+    pub fn output_buffer(&self) -> gstreamer::Buffer {
+        self.buffer.copy()
+    }
+}
+
+/// Which elementary stream a frame belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamKind {
+    Video,
+    /// AAC in ADTS framing
+    Audio,
+}
+
+/// Caps for forwarded audio (AAC, ADTS framed so no codec_data is needed)
+pub fn aac_caps() -> &'static str {
+    "audio/mpeg,mpegversion=4,stream-format=adts"
+}
+
+/// Frames an output may have queued by default before old ones are dropped
+pub const DEFAULT_QUEUE_FRAMES: usize = 60;
+
+/// Default size of the queue in front of each mount's payloader
+pub const DEFAULT_OUTPUT_QUEUE_BUFFERS: usize = 30;
+
+/// Bounded queue between a source and one output. When a slow output lets it
+/// fill up, the oldest delta frame is dropped to make room; keyframes are
+/// never dropped while a delta frame could go instead, so the output can
+/// always resync. A queue of nothing but keyframes (or audio) drops its oldest
+/// entry, which leaves every remaining frame decodable.
+struct FrameQueue {
+    state: Mutex<QueueState>,
+    ready: Condvar,
+    capacity: usize,
+}
+
+#[derive(Default)]
+struct QueueState {
+    frames: VecDeque<FrameData>,
+    /// Frames dropped since the receiver last asked
+    dropped: u64,
+    /// The bus has gone away; nothing more will arrive
+    closed: bool,
+}
+
+impl FrameQueue {
+    fn push(&self, frame: FrameData) {
+        let mut state = self.state.lock().unwrap();
+        if state.frames.len() >= self.capacity {
+            let victim = state
+                .frames
+                .iter()
+                .position(|f| !f.is_keyframe)
+                .unwrap_or(0);
+            state.frames.remove(victim);
+            state.dropped += 1;
+        }
+        state.frames.push_back(frame);
+        self.ready.notify_one();
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+}
+
+/// Receiving end of an output's frame queue
+pub struct FrameReceiver {
+    queue: Arc<FrameQueue>,
+    /// Whether `recv_from_keyframe` has passed a video keyframe yet
+    synced: bool,
+}
+
+impl FrameReceiver {
+    /// Block until a frame arrives. Fails once the source's bus is gone and
+    /// the queue has drained.
+    pub fn recv(&self) -> Result<FrameData, RecvError> {
+        let mut state = self.queue.state.lock().unwrap();
+        loop {
+            if let Some(frame) = state.frames.pop_front() {
+                return Ok(frame);
+            }
+            if state.closed {
+                return Err(RecvError);
+            }
+            state = self.queue.ready.wait(state).unwrap();
+        }
+    }
+
+    /// Like `recv`, giving up after `timeout`
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<FrameData, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.queue.state.lock().unwrap();
+        loop {
+            if let Some(frame) = state.frames.pop_front() {
+                return Ok(frame);
+            }
+            if state.closed {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = self.queue.ready.wait_timeout(state, remaining).unwrap().0;
+        }
+    }
+
+    /// Like `recv`, but drops everything (audio too) until the first video
+    /// keyframe, since decoders and muxers can't start mid-GOP
+    pub fn recv_from_keyframe(&mut self) -> Result<FrameData, RecvError> {
+        loop {
+            let frame = self.recv()?;
+            if self.admit(&frame) {
+                return Ok(frame);
+            }
+        }
+    }
+
+    /// Like `recv_from_keyframe`, giving up after `timeout`
+    pub fn recv_from_keyframe_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<FrameData, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let frame = self.recv_timeout(remaining)?;
+            if self.admit(&frame) {
+                return Ok(frame);
+            }
+        }
+    }
+
+    /// Make `recv_from_keyframe` wait for the next keyframe again
+    pub fn resync(&mut self) {
+        self.synced = false;
+    }
+
+    /// Whether `recv_from_keyframe` has passed a keyframe since the last resync
+    pub fn is_synced(&self) -> bool {
+        self.synced
+    }
+
+    fn admit(&mut self, frame: &FrameData) -> bool {
+        self.synced |= frame.kind == StreamKind::Video && frame.is_keyframe;
+        self.synced
+    }
+
+    /// Number of frames dropped for this output since the last call
+    pub fn take_dropped(&self) -> u64 {
+        std::mem::take(&mut self.queue.state.lock().unwrap().dropped)
+    }
+}
+
+/// Fans a source's encoded frames out to every attached output
+/// (RTSP media instances, HLS, ...). Each output gets its own bounded queue;
+/// outputs whose receiver has gone away are dropped on the next send.
+pub struct FrameBus {
+    queues: Mutex<Vec<Weak<FrameQueue>>>,
+    queue_frames: usize,
+    /// Clients playing the bus's mount (or watching it over WebRTC)
+    viewers: AtomicUsize,
+    /// Set when a client starts playing, until the source forces a keyframe
+    keyframe_wanted: AtomicBool,
+}
+
+impl Default for FrameBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_QUEUE_FRAMES)
+    }
+}
+
+impl FrameBus {
+    /// Bus whose outputs each queue at most `queue_frames` frames
+    pub fn new(queue_frames: usize) -> Self {
+        Self {
+            queues: Mutex::new(Vec::new()),
+            queue_frames: queue_frames.max(1),
+            viewers: AtomicUsize::new(0),
+            keyframe_wanted: AtomicBool::new(false),
+        }
+    }
+
+    /// Number of clients playing this bus's mount
+    pub fn viewers(&self) -> usize {
+        self.viewers.load(Ordering::SeqCst)
+    }
+
+    /// Count a client that started playing
+    pub fn viewer_joined(&self) {
+        self.viewers.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Ask the source for a keyframe now, so a client joining mid-GOP
+    /// doesn't wait out the rest of it
+    pub fn request_keyframe(&self) {
+        self.keyframe_wanted.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether a keyframe was requested since the last call
+    pub fn take_keyframe_request(&self) -> bool {
+        self.keyframe_wanted.swap(false, Ordering::SeqCst)
+    }
+
+    /// Count a client that stopped playing
+    pub fn viewer_left(&self) {
+        let _ = self
+            .viewers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+    }
+
+    /// Attach a new output and return its receiving end
+    pub fn subscribe(&self) -> FrameReceiver {
+        let queue = Arc::new(FrameQueue {
+            state: Mutex::new(QueueState::default()),
+            ready: Condvar::new(),
+            capacity: self.queue_frames,
+        });
+        self.queues.lock().unwrap().push(Arc::downgrade(&queue));
+        FrameReceiver {
+            queue,
+            synced: false,
+        }
+    }
+
+    /// Send a frame to every attached output
+    pub fn send(&self, frame: FrameData) {
+        self.queues
+            .lock()
+            .unwrap()
+            .retain(|queue| match queue.upgrade() {
+                Some(queue) => {
+                    queue.push(frame.clone());
+                    true
+                }
+                None => false,
+            });
+    }
+}
+
+impl Drop for FrameBus {
+    fn drop(&mut self) {
+        for queue in self.queues.get_mut().unwrap().iter() {
+            if let Some(queue) = queue.upgrade() {
+                queue.close();
+            }
+        }
+    }
+}
+
+/// Backpressure from a mount's video appsrc, fed by its need-data and
+/// enough-data signals. While the appsrc has enough data its pusher drops
+/// delta frames rather than queueing behind them, but keyframes still go
+/// through. After a drop, deltas wait for the next keyframe so no frame goes
+/// out without its references.
+#[derive(Default)]
+struct PushGate {
+    /// enough-data was signalled and need-data hasn't followed yet
+    full: AtomicBool,
+    /// Delta frames were dropped, so wait for a keyframe before pushing
+    /// deltas again
+    resync: AtomicBool,
+}
+
+impl PushGate {
+    /// The appsrc's queue is full
+    fn enough_data(&self) {
+        self.full.store(true, Ordering::SeqCst);
+    }
+
+    /// The appsrc wants data again. Returns whether frames were dropped in
+    /// the meantime, i.e. the stream needs a keyframe to resume.
+    fn need_data(&self) -> bool {
+        self.full.store(false, Ordering::SeqCst);
+        self.resync.load(Ordering::SeqCst)
+    }
+
+    /// Whether to push a frame now
+    fn admit(&self, is_keyframe: bool) -> bool {
+        if is_keyframe {
+            self.resync.store(false, Ordering::SeqCst);
+            return true;
+        }
+        if self.full.load(Ordering::SeqCst) || self.resync.load(Ordering::SeqCst) {
+            self.resync.store(true, Ordering::SeqCst);
+            return false;
+        }
+        true
+    }
+}
+
+/// Thins a passthrough stream to about `fps` by dropping whole GOPs, the
+/// smallest unit that can go without breaking decoding. Each GOP is
+/// forwarded or dropped at its keyframe, depending on whether the frames
+/// sent so far are ahead of the target rate.
+struct GopDropper {
+    fps: f64,
+    /// Arrival time of the first keyframe
+    start: Option<Duration>,
+    sent: u64,
+    /// Whether the current GOP is being forwarded
+    forwarding: bool,
+}
+
+impl GopDropper {
+    fn new(fps: f64) -> Self {
+        Self {
+            fps,
+            start: None,
+            sent: 0,
+            forwarding: false,
+        }
+    }
+
+    /// Whether to forward a video frame that arrived at `at`
+    fn admit(&mut self, is_keyframe: bool, at: Duration) -> bool {
+        if is_keyframe {
+            let start = *self.start.get_or_insert(at);
+            let allowed = at.saturating_sub(start).as_secs_f64() * self.fps;
+            self.forwarding = self.sent as f64 <= allowed;
+        }
+        if self.forwarding {
+            self.sent += 1;
+        }
+        self.forwarding
+    }
+}
+
+/// Role granted to clients that connect without credentials
+const ANONYMOUS_ROLE: &str = "anonymous";
+
+/// Output credentials registered with the server-wide auth handler.
+///
+/// Each distinct username/password/method gets its own role, and each mount
+/// only grants access to the role of its own credentials. Unprotected mounts
+/// grant access to the anonymous role instead.
+#[derive(Debug, Default)]
+struct AuthRegistry {
+    /// Registered (username, password, method); the index forms the role name
+    credentials: Vec<(String, String, AuthMethod)>,
+    /// (mount path, role) for each protected mount
+    mounts: Vec<(String, String)>,
+}
+
+impl AuthRegistry {
+    /// Register credentials for a mount. Returns the role for the mount and
+    /// whether the credentials are new (and so still need a token on the server).
+    fn register(
+        &mut self,
+        mount_path: &str,
+        username: &str,
+        password: &str,
+        method: AuthMethod,
+    ) -> (String, bool) {
+        let existing = self
+            .credentials
+            .iter()
+            .position(|(u, p, m)| u == username && p == password && *m == method);
+
+        let (index, is_new) = match existing {
+            Some(index) => (index, false),
+            None => {
+                self.credentials
+                    .push((username.to_string(), password.to_string(), method));
+                (self.credentials.len() - 1, true)
+            }
+        };
+
+        let role = format!("viewer-{}", index);
+        self.remove(mount_path);
+        self.mounts.push((mount_path.to_string(), role.clone()));
+        (role, is_new)
+    }
+
+    /// Scheme to offer in challenges. Config validation keeps all sources
+    /// on one scheme, since the server challenges with it for every mount.
+    fn supported_methods(&self) -> RTSPAuthMethod {
+        let digest = self
+            .credentials
+            .iter()
+            .any(|(_, _, m)| *m == AuthMethod::Digest);
+        if digest {
+            RTSPAuthMethod::Digest
+        } else {
+            RTSPAuthMethod::Basic
+        }
+    }
+
+    /// Forget the role assigned to a mount
+    fn remove(&mut self, mount_path: &str) {
+        self.mounts.retain(|(path, _)| path != mount_path);
+    }
+
+    /// Role required to access a mount, if it is protected
+    #[cfg(test)]
+    fn role_for(&self, mount_path: &str) -> Option<&str> {
+        self.mounts
+            .iter()
+            .find(|(path, _)| path == mount_path)
+            .map(|(_, role)| role.as_str())
+    }
+}
+
+/// Load a PEM certificate/key pair for the server
+fn load_tls_certificate(cert: &Path, key: &Path) -> Result<gio::TlsCertificate> {
+    gio::TlsCertificate::from_files(cert, key).with_context(|| {
+        format!(
+            "Failed to load TLS certificate {} / key {}",
+            cert.display(),
+            key.display()
+        )
+    })
+}
+
+/// Frame buses by mount path, for attributing client requests to mounts
+type MountBuses = Arc<Mutex<HashMap<String, Weak<FrameBus>>>>;
+
+/// The bus of the mount a request path falls under: the mount itself or one
+/// of its streams (`/cam1/stream/stream=0`)
+fn bus_for_path(buses: &HashMap<String, Weak<FrameBus>>, path: &str) -> Option<Arc<FrameBus>> {
+    let mut path = path.trim_end_matches('/');
+    loop {
+        if let Some(bus) = buses.get(path) {
+            return bus.upgrade();
+        }
+        path = path.rsplit_once('/')?.0;
+    }
+}
+
+/// Mounts one RTSP client is playing. Each counts once towards its mount's
+/// viewers, however many PLAYs the client sends (say, after a PAUSE), until
+/// the client tears it down or its connection closes.
+#[derive(Default)]
+struct ClientViews(Vec<Weak<FrameBus>>);
+
+impl ClientViews {
+    fn position(&self, bus: &Arc<FrameBus>) -> Option<usize> {
+        let bus = Arc::downgrade(bus);
+        self.0.iter().position(|playing| playing.ptr_eq(&bus))
+    }
+
+    fn play(&mut self, bus: &Arc<FrameBus>) {
+        if self.position(bus).is_none() {
+            self.0.push(Arc::downgrade(bus));
+            bus.viewer_joined();
+            bus.request_keyframe();
+        }
+    }
+
+    fn teardown(&mut self, bus: &Arc<FrameBus>) {
+        if let Some(i) = self.position(bus) {
+            self.0.swap_remove(i);
+            bus.viewer_left();
+        }
+    }
+
+    fn close(&mut self) {
+        for bus in self.0.drain(..).filter_map(|bus| bus.upgrade()) {
+            bus.viewer_left();
+        }
+    }
+}
+
+/// Follow a newly connected client's PLAY and TEARDOWN requests to keep the
+/// viewer counts of the mounts it plays
+fn track_client(client: &gstreamer_rtsp_server::RTSPClient, buses: &MountBuses) {
+    let views = Arc::new(Mutex::new(ClientViews::default()));
+    let buses = Arc::clone(buses);
+    let request_bus = Arc::new(move |ctx: &gstreamer_rtsp_server::RTSPContext| {
+        let path = ctx.uri()?.decode_path_components().join("/");
+        bus_for_path(&buses.lock().unwrap(), &path)
+    });
+
+    let (playing, lookup) = (Arc::clone(&views), Arc::clone(&request_bus));
+    client.connect_play_request(move |_, ctx| {
+        if let Some(bus) = lookup(ctx) {
+            playing.lock().unwrap().play(&bus);
+        }
+    });
+
+    let (playing, lookup) = (Arc::clone(&views), request_bus);
+    client.connect_teardown_request(move |_, ctx| {
+        if let Some(bus) = lookup(ctx) {
+            playing.lock().unwrap().teardown(&bus);
+        }
+    });
+
+    client.connect_closed(move |_| views.lock().unwrap().close());
+}
 
 /// RTSP server wrapper
+///
+/// The server's sockets are serviced by a GLib main context. [`start`](Self::start)
+/// attaches to the default context and runs a loop for it on a thread of its
+/// own, so dart owns that context. An app with its own GLib loop calls
+/// [`attach`](Self::attach) with its context instead and keeps running that
+/// loop itself; no thread is spawned.
 pub struct RtspServer {
     server: gstreamer_rtsp_server::RTSPServer,
     mounts: gstreamer_rtsp_server::RTSPMountPoints,
-    main_loop: glib::MainLoop,
+    auth: gstreamer_rtsp_server::RTSPAuth,
+    auth_registry: Mutex<AuthRegistry>,
+    /// Mount paths (main and renditions) by source name, removed along with the source
+    source_mounts: Mutex<HashMap<String, Vec<String>>>,
+    /// Multicast address pool by source name, shared by the source's mounts
+    /// so they never get the same group and port
+    multicast_pools: Mutex<HashMap<String, gstreamer_rtsp_server::RTSPAddressPool>>,
+    /// Every mount's frame bus, for counting its viewers
+    mount_buses: MountBuses,
+    /// Wall-clock pipeline clock shared by every mount, so RTCP sender
+    /// reports from different sources line up
+    clock: gstreamer::Clock,
+    /// The loop `start` runs, if the server owns one
+    main_loop: Mutex<Option<glib::MainLoop>>,
+    /// The context the server is attached to, and its source there
+    attached: Mutex<Option<(glib::MainContext, glib::SourceId)>>,
     port: u16,
 }
 
@@ -36,189 +584,182 @@ impl RtspServer {
             .mount_points()
             .ok_or_else(|| anyhow::anyhow!("Failed to get mount points"))?;
 
-        let main_loop = glib::MainLoop::new(None, false);
+        // Single auth handler for the whole server. Clients without credentials
+        // get the anonymous role; each mount decides which roles may access it.
+        let auth = gstreamer_rtsp_server::RTSPAuth::new();
+        let mut anonymous = gstreamer_rtsp_server::RTSPToken::builder()
+            .field(
+                gstreamer_rtsp_server::RTSP_TOKEN_MEDIA_FACTORY_ROLE,
+                ANONYMOUS_ROLE,
+            )
+            .build();
+        auth.set_default_token(Some(&mut anonymous));
+        server.set_auth(Some(&auth));
+
+        let mount_buses = MountBuses::default();
+        let buses = Arc::clone(&mount_buses);
+        server.connect_client_connected(move |_, client| track_client(client, &buses));
+
+        let clock = glib::Object::builder::<gstreamer::SystemClock>()
+            .property("clock-type", gstreamer::ClockType::Realtime)
+            .build()
+            .upcast::<gstreamer::Clock>();
 
         Ok(Self {
             server,
             mounts,
-            main_loop,
+            auth,
+            auth_registry: Mutex::new(AuthRegistry::default()),
+            source_mounts: Mutex::new(HashMap::new()),
+            multicast_pools: Mutex::new(HashMap::new()),
+            mount_buses,
+            clock,
+            main_loop: Mutex::new(None),
+            attached: Mutex::new(None),
             port,
         })
     }
 
-    /// Start the RTSP server in a background thread
-    pub fn start(&self) -> Result<()> {
-        let main_loop = self.main_loop.clone();
+    /// Serve rtsps:// with the given PEM certificate and key. Can be called
+    /// again to swap certificates; only new connections see the change.
+    pub fn set_tls_certificate(&self, cert: &Path, key: &Path) -> Result<()> {
+        let certificate = load_tls_certificate(cert, key)?;
+        self.auth.set_tls_certificate(Some(&certificate));
+        info!("RTSP TLS certificate loaded from {}", cert.display());
+        Ok(())
+    }
 
-        // Attach server to default main context
-        let _source_id = self.server.attach(None);
+    /// Start the RTSP server on the default main context, running its loop
+    /// in a background thread
+    pub fn start(&self) -> Result<()> {
+        let context = glib::MainContext::default();
+        self.attach(&context)?;
 
         // Run main loop in separate thread
+        let main_loop = glib::MainLoop::new(Some(&context), false);
+        *self.main_loop.lock().unwrap() = Some(main_loop.clone());
         std::thread::spawn(move || {
             main_loop.run();
         });
 
+        Ok(())
+    }
+
+    /// Start serving on `context` without spawning a thread. The caller owns
+    /// the context and must keep a loop running on it; `stop` detaches again.
+    pub fn attach(&self, context: &glib::MainContext) -> Result<()> {
+        let source_id = self
+            .server
+            .attach(Some(context))
+            .context("Failed to attach RTSP server to main context")?;
+        *self.attached.lock().unwrap() = Some((context.clone(), source_id));
+
         info!(
             "RTSP server started on {}:{}",
             self.server.address().unwrap_or_else(|| "0.0.0.0".into()),
-            self.port
+            self.bound_port().unwrap_or(self.port)
         );
 
         Ok(())
     }
 
-    /// Add a V4L2 source mount using a direct factory launch pipeline.
-    /// The RTSP server manages the entire pipeline lifecycle — no appsrc needed.
-    pub fn add_v4l2_mount(
-        &self,
-        source: &SourceConfig,
-        mpp: bool,
-    ) -> Result<()> {
-        let mount_path = format!("/{}/stream", source.name);
-
-        let device = source
-            .device
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("V4L2 source requires 'device'"))?;
-
-        let encode = source.encode_config();
-        let factory = gstreamer_rtsp_server::RTSPMediaFactory::new();
-
-        let launch_str = if mpp {
-            let encoder = sources::build_mpp_h265_encoder_string(&encode);
-
-            let mut caps_parts = vec!["format=NV12".to_string()];
-            if let Some(w) = source.width {
-                caps_parts.push(format!("width={}", w));
-            }
-            if let Some(h) = source.height {
-                caps_parts.push(format!("height={}", h));
-            }
-            if let Some(f) = source.framerate {
-                caps_parts.push(format!("framerate={}/1", f));
-            }
-            let source_caps = format!("video/x-raw,{}", caps_parts.join(","));
-
-            format!(
-                "( v4l2src device={device} \
-                   ! {source_caps} \
-                   ! {encoder} \
-                   ! {h265_caps} \
-                   ! h265parse config-interval=-1 \
-                   ! rtph265pay name=pay0 pt=96 )",
-                device = device,
-                source_caps = source_caps,
-                encoder = encoder,
-                h265_caps = sources::h265_caps(),
-            )
-        } else {
-            let encoder = sources::build_encoder_string(&encode);
-
-            // Build source caps for capture cards with explicit format
-            let source_caps = if let Some(format) = &source.format {
-                let mut caps_parts = vec![format!("format={}", format)];
-                if let Some(w) = source.width {
-                    caps_parts.push(format!("width={}", w));
-                }
-                if let Some(h) = source.height {
-                    caps_parts.push(format!("height={}", h));
-                }
-                caps_parts.push("colorimetry=bt601".to_string());
-                format!(" ! video/x-raw,{}", caps_parts.join(","))
-            } else {
-                String::new()
-            };
-
-            // Build output caps for after conversion
-            let output_caps = match (source.width, source.height, source.framerate) {
-                (Some(w), Some(h), Some(f)) => {
-                    format!("video/x-raw,width={},height={},framerate={}/1", w, h, f)
-                }
-                (Some(w), Some(h), None) => format!("video/x-raw,width={},height={}", w, h),
-                _ => String::from("video/x-raw"),
-            };
-
-            format!(
-                "( v4l2src device={device}{source_caps} \
-                   ! videoconvert ! videoscale \
-                   ! {output_caps} \
-                   ! {encoder} \
-                   ! {h264_caps} \
-                   ! h264parse \
-                   ! rtph264pay name=pay0 pt=96 )",
-                device = device,
-                source_caps = source_caps,
-                output_caps = output_caps,
-                encoder = encoder,
-                h264_caps = sources::h264_caps(),
-            )
-        };
-
-        debug!("V4L2 factory launch: {}", launch_str);
+    /// The port the server listens on once started; with port 0 requested,
+    /// the one the OS picked. None before `start`/`attach`.
+    pub fn bound_port(&self) -> Option<u16> {
+        u16::try_from(self.server.bound_port())
+            .ok()
+            .filter(|&port| port != 0)
+    }
 
-        factory.set_launch(&launch_str);
-        factory.set_shared(true);
+    /// Add a stream mount point using appsrc.
+    /// Returns the frame bus that the source pushes frames into.
+    pub fn add_mount(&self, source: &SourceConfig, codec: OutputCodec) -> Result<Arc<FrameBus>> {
+        let mount_path = source.mount_path();
+        let video = MountVideo::for_source(source, None);
+        let frame_bus = self.add_mount_at(&mount_path, source, codec, video, source.audio)?;
+        self.record_mount(&source.name, mount_path);
+        Ok(frame_bus)
+    }
 
-        // Set up authentication if configured
-        if let Some(auth_config) = &source.auth {
-            if auth_config.enabled {
-                if let Err(e) = self.setup_auth(auth_config) {
-                    warn!("Failed to setup auth for '{}': {}", source.name, e);
-                }
-            }
-        }
+    /// Add a mount for one of a source's renditions, protected by the same
+    /// credentials. Renditions are video only.
+    pub fn add_rendition_mount(
+        &self,
+        source: &SourceConfig,
+        rendition: &RenditionConfig,
+        codec: OutputCodec,
+    ) -> Result<Arc<FrameBus>> {
+        let mount_path = rendition_mount_path(&source.name, &rendition.name);
+        let video = MountVideo::for_source(source, Some(rendition));
+        let frame_bus = self.add_mount_at(&mount_path, source, codec, video, false)?;
+        self.record_mount(&source.name, mount_path);
+        Ok(frame_bus)
+    }
 
-        self.mounts.add_factory(&mount_path, factory);
-        info!("Added RTSP mount: rtsp://localhost:{}{}", self.port, mount_path);
+    /// Add the H.264 compatibility mount for an H.265 source, video only.
+    /// Its frames come from a transcoder fed by the source (see `compat`).
+    pub fn add_compat_mount(&self, source: &SourceConfig) -> Result<Arc<FrameBus>> {
+        let mount_path = compat_mount_path(&source.name);
+        let video = MountVideo::for_source(source, None);
+        let frame_bus = self.add_mount_at(&mount_path, source, OutputCodec::H264, video, false)?;
+        self.record_mount(&source.name, mount_path);
+        Ok(frame_bus)
+    }
 
-        Ok(())
+    /// Remember a mount so `remove_mount` takes it down with its source
+    fn record_mount(&self, source: &str, mount_path: String) {
+        self.source_mounts
+            .lock()
+            .unwrap()
+            .entry(source.to_string())
+            .or_default()
+            .push(mount_path);
     }
 
-    /// Add a stream mount point using appsrc (for RTSP and other dynamic sources).
-    /// Returns a channel sender that can be used to push frames.
-    pub fn add_mount(
+    /// Add an appsrc mount at `mount_path` fed by a new frame bus
+    fn add_mount_at(
         &self,
+        mount_path: &str,
         source: &SourceConfig,
         codec: OutputCodec,
-    ) -> Result<Arc<Mutex<Option<FrameSender>>>> {
-        let mount_path = format!("/{}/stream", source.name);
-
+        video: MountVideo,
+        audio: bool,
+    ) -> Result<Arc<FrameBus>> {
         // Create factory with appsrc pipeline, adapting caps/payloader to codec
         let factory = gstreamer_rtsp_server::RTSPMediaFactory::new();
-        let launch_str = match codec {
-            OutputCodec::H264 => {
-                "( appsrc name=videosrc is-live=true format=time do-timestamp=true \
-                   caps=video/x-h264,stream-format=byte-stream,alignment=au \
-                   ! h264parse \
-                   ! rtph264pay name=pay0 pt=96 )".to_string()
-            }
-            OutputCodec::H265 => {
-                "( appsrc name=videosrc is-live=true format=time do-timestamp=true \
-                   caps=video/x-h265,stream-format=byte-stream,alignment=au \
-                   ! h265parse config-interval=-1 \
-                   ! rtph265pay name=pay0 pt=96 )".to_string()
-            }
-        };
+        let launch_str = mount_launch_string(codec, video, audio, source.output_queue_buffers);
         factory.set_launch(&launch_str);
         factory.set_shared(true);
+        factory.set_clock(Some(&self.clock));
 
-        // Set up authentication if configured
-        if let Some(auth_config) = &source.auth {
-            if auth_config.enabled {
-                if let Err(e) = self.setup_auth(auth_config) {
-                    warn!("Failed to setup auth for '{}': {}", source.name, e);
-                }
-            }
+        // Restrict access to this mount's credentials (or allow anonymous)
+        self.setup_auth(&factory, mount_path, source.auth.as_ref())?;
+
+        if let Some(multicast) = &source.multicast {
+            let pool = self.multicast_pool(&source.name, multicast)?;
+            // The factory already allows UDP multicast; it needs a pool to use it
+            factory.set_address_pool(Some(&pool));
         }
 
-        // Channel for frames - initially None, populated when client connects
-        let frame_tx: Arc<Mutex<Option<FrameSender>>> = Arc::new(Mutex::new(None));
-        let frame_tx_clone = Arc::clone(&frame_tx);
+        // Frame bus - each media instance subscribes when a client connects
+        let frame_bus = Arc::new(FrameBus::new(source.queue_frames));
+        let frame_bus_clone = Arc::clone(&frame_bus);
         let source_name = source.name.clone();
+        let output_fps = source.output_framerate.map(|fps| fps.fps());
 
         // Connect to media-configure signal
         factory.connect_media_configure(move |_factory, media| {
             let element = media.element();
+
+            // The media adds its rtpbin to the pipeline when it prepares
+            if let Some(pipeline) = element.parent().and_downcast::<gstreamer::Bin>() {
+                pipeline.connect_element_added(|_, element| {
+                    if element.factory().is_some_and(|f| f.name() == "rtpbin") {
+                        configure_rtpbin(element);
+                    }
+                });
+            }
+
             let Some(bin) = element.downcast_ref::<gstreamer::Bin>() else {
                 error!("Failed to downcast media element to Bin");
                 return;
@@ -234,43 +775,102 @@ impl RtspServer {
                 return;
             };
 
-            // Create channel for this media instance
-            let (tx, rx) = std::sync::mpsc::channel::<FrameData>();
-            *frame_tx_clone.lock().unwrap() = Some(tx);
+            // Audio branch only exists when the source forwards audio
+            let audiosrc = bin
+                .by_name("audiosrc")
+                .and_then(|e| e.dynamic_cast::<AppSrc>().ok());
+
+            // Subscribe this media instance to the source's frames
+            let mut rx = frame_bus_clone.subscribe();
 
             let name = source_name.clone();
+            let mut dropper = output_fps.map(GopDropper::new);
+
+            // Only push while the appsrc wants data; after a gap, ask the
+            // source for a keyframe to resume on
+            let gate = Arc::new(PushGate::default());
+            let need_gate = Arc::clone(&gate);
+            let enough_gate = Arc::clone(&gate);
+            let bus = Arc::downgrade(&frame_bus_clone);
+            appsrc.set_callbacks(
+                gstreamer_app::AppSrcCallbacks::builder()
+                    .need_data(move |_, _| {
+                        if need_gate.need_data() {
+                            if let Some(bus) = bus.upgrade() {
+                                bus.request_keyframe();
+                            }
+                        }
+                    })
+                    .enough_data(move |_| enough_gate.enough_data())
+                    .build(),
+            );
 
             // Spawn thread to push frames to appsrc
+            let span = tracing::info_span!(logging::SOURCE_SPAN, source = %name);
             std::thread::spawn(move || {
-                let mut waiting_for_keyframe = true;
+                let _span = span.enter();
                 let mut frame_count = 0u64;
+                let mut held_back = 0u64;
+                let started = Instant::now();
 
                 debug!("Frame pusher thread started for source '{}'", name);
 
-                while let Ok(frame) = rx.recv() {
-                    // Wait for keyframe before starting (cleaner playback start)
-                    if waiting_for_keyframe {
-                        if !frame.is_keyframe {
-                            continue;
+                // Wait for keyframe before starting (cleaner playback start);
+                // audio starts with the video
+                loop {
+                    let starting = !rx.is_synced();
+                    let Ok(frame) = rx.recv_from_keyframe() else {
+                        break;
+                    };
+                    if starting {
+                        info!(
+                            "Got initial keyframe for source '{}', starting stream",
+                            name
+                        );
+                    }
+
+                    let dropped = rx.take_dropped();
+                    if dropped > 0 {
+                        warn!(
+                            "Source '{}': client falling behind, dropped {} frame(s)",
+                            name, dropped
+                        );
+                    }
+
+                    if frame.kind == StreamKind::Audio {
+                        // A source without an audio track simply never sends any
+                        if let Some(audiosrc) = &audiosrc {
+                            if let Err(e) = audiosrc.push_buffer(frame.output_buffer()) {
+                                debug!("Source '{}': audio push failed: {:?}", name, e);
+                                break;
+                            }
                         }
-                        info!("Got initial keyframe for source '{}', starting stream", name);
-                        waiting_for_keyframe = false;
+                        continue;
                     }
 
-                    // Create GStreamer buffer from frame data
-                    let mut buffer = gstreamer::Buffer::from_slice(frame.data);
-                    {
-                        let buffer_ref = buffer.get_mut().unwrap();
-                        if !frame.is_keyframe {
-                            buffer_ref.set_flags(gstreamer::BufferFlags::DELTA_UNIT);
+                    if let Some(dropper) = &mut dropper {
+                        if !dropper.admit(frame.is_keyframe, started.elapsed()) {
+                            continue;
                         }
                     }
 
+                    if !gate.admit(frame.is_keyframe) {
+                        held_back += 1;
+                        continue;
+                    }
+                    if held_back > 0 {
+                        warn!(
+                            "Source '{}': client's stream was full, held back {} frame(s)",
+                            name, held_back
+                        );
+                        held_back = 0;
+                    }
+
                     // Push buffer to appsrc
-                    match appsrc.push_buffer(buffer) {
+                    match appsrc.push_buffer(frame.output_buffer()) {
                         Ok(_) => {
                             frame_count += 1;
-                            if frame_count % 300 == 0 {
+                            if frame_count.is_multiple_of(300) {
                                 debug!(
                                     "Source '{}': pushed {} frames",
                                     name, frame_count
@@ -295,52 +895,941 @@ impl RtspServer {
         });
 
         // Add factory to mount points
-        self.mounts.add_factory(&mount_path, factory);
+        self.mounts.add_factory(mount_path, factory);
+        self.mount_buses
+            .lock()
+            .unwrap()
+            .insert(mount_path.to_string(), Arc::downgrade(&frame_bus));
         info!("Added RTSP mount: rtsp://localhost:{}{}",
               self.port,
               mount_path);
 
-        Ok(frame_tx)
+        Ok(frame_bus)
     }
 
-    /// Remove a mount point
+    /// Remove a source's mount point, along with any rendition mounts
     pub fn remove_mount(&self, name: &str) {
-        let mount_path = format!("/{}/stream", name);
-        self.mounts.remove_factory(&mount_path);
-        info!("Removed RTSP mount: {}", mount_path);
-    }
-
-    /// Set up authentication on the server
-    fn setup_auth(&self, auth_config: &AuthConfig) -> Result<()> {
-        let username = auth_config
-            .username
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("Auth enabled but username not set"))?;
-        let password = auth_config
-            .password
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("Auth enabled but password not set"))?;
-
-        // Create auth handler
-        let auth = gstreamer_rtsp_server::RTSPAuth::new();
+        let mount_paths = self
+            .source_mounts
+            .lock()
+            .unwrap()
+            .remove(name)
+            .unwrap_or_default();
+        self.multicast_pools.lock().unwrap().remove(name);
 
-        // Create token for authenticated users
-        let token = gstreamer_rtsp_server::RTSPToken::new_empty();
+        for mount_path in mount_paths {
+            self.mounts.remove_factory(&mount_path);
+            self.auth_registry.lock().unwrap().remove(&mount_path);
+            self.mount_buses.lock().unwrap().remove(&mount_path);
+            info!("Removed RTSP mount: {}", mount_path);
+        }
+    }
 
-        // Add basic auth credentials
-        let basic = gstreamer_rtsp_server::RTSPAuth::make_basic(username, password);
-        auth.add_basic(&basic, &token);
+    /// The source's multicast address pool, created on its first mount
+    fn multicast_pool(
+        &self,
+        source_name: &str,
+        multicast: &MulticastConfig,
+    ) -> Result<gstreamer_rtsp_server::RTSPAddressPool> {
+        let mut pools = self.multicast_pools.lock().unwrap();
+        if let Some(pool) = pools.get(source_name) {
+            return Ok(pool.clone());
+        }
 
-        // Set auth on server
-        self.server.set_auth(Some(&auth));
+        let pool = gstreamer_rtsp_server::RTSPAddressPool::new();
+        pool.add_range(
+            &multicast.address_min.to_string(),
+            &multicast.address_max().to_string(),
+            multicast.port_min,
+            multicast.port_max,
+            multicast.ttl,
+        )
+        .with_context(|| {
+            format!(
+                "Failed to add multicast range {} - {} for source '{}'",
+                multicast.address_min,
+                multicast.address_max(),
+                source_name
+            )
+        })?;
+        info!(
+            "Source '{}' offers multicast on {} - {}, ports {}-{}",
+            source_name,
+            multicast.address_min,
+            multicast.address_max(),
+            multicast.port_min,
+            multicast.port_max
+        );
+        pools.insert(source_name.to_string(), pool.clone());
+        Ok(pool)
+    }
+
+    /// Grant access to a mount's factory. Protected mounts get a role tied to
+    /// their own credentials; unprotected mounts are open to anonymous clients.
+    fn setup_auth(
+        &self,
+        factory: &gstreamer_rtsp_server::RTSPMediaFactory,
+        mount_path: &str,
+        auth_config: Option<&AuthConfig>,
+    ) -> Result<()> {
+        let role = match auth_config.filter(|a| a.enabled) {
+            Some(auth_config) => {
+                let username = auth_config
+                    .username
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("Auth enabled but username not set"))?;
+                let password = auth_config
+                    .password
+                    .as_ref()
+                    .ok_or_else(|| anyhow::anyhow!("Auth enabled but password not set"))?;
+
+                let mut registry = self.auth_registry.lock().unwrap();
+                let (role, is_new) =
+                    registry.register(mount_path, username, password, auth_config.method);
+
+                if is_new {
+                    // Token for clients presenting these credentials
+                    let token = gstreamer_rtsp_server::RTSPToken::builder()
+                        .field(
+                            gstreamer_rtsp_server::RTSP_TOKEN_MEDIA_FACTORY_ROLE,
+                            role.as_str(),
+                        )
+                        .build();
+
+                    match auth_config.method {
+                        AuthMethod::Basic => {
+                            let basic =
+                                gstreamer_rtsp_server::RTSPAuth::make_basic(username, password);
+                            self.auth.add_basic(&basic, &token);
+                        }
+                        AuthMethod::Digest => self.auth.add_digest(username, password, &token),
+                    }
+                    self.auth
+                        .set_supported_methods(registry.supported_methods());
+                }
+
+                debug!(
+                    "{:?} authentication configured for {}",
+                    auth_config.method, mount_path
+                );
+                role
+            }
+            None => ANONYMOUS_ROLE.to_string(),
+        };
+
+        factory.add_role_from_structure(
+            &gstreamer::Structure::builder(role.as_str())
+                .field(gstreamer_rtsp_server::RTSP_PERM_MEDIA_FACTORY_ACCESS, true)
+                .field(
+                    gstreamer_rtsp_server::RTSP_PERM_MEDIA_FACTORY_CONSTRUCT,
+                    true,
+                )
+                .build(),
+        );
 
-        debug!("Authentication configured");
         Ok(())
     }
 
     /// Stop the RTSP server
     pub fn stop(&self) {
-        self.main_loop.quit();
+        // Stop listening; a caller's own loop keeps running
+        if let Some((context, source_id)) = self.attached.lock().unwrap().take() {
+            if let Some(source) = context.find_source_by_id(&source_id) {
+                source.destroy();
+            }
+        }
+        if let Some(main_loop) = self.main_loop.lock().unwrap().take() {
+            main_loop.quit();
+        }
         info!("RTSP server stopped");
     }
 }
+
+/// Mount path of a source's main stream
+pub fn stream_mount_path(source: &str) -> String {
+    format!("/{}/stream", source)
+}
+
+/// Mount path of a source's H.264 compatibility transcode
+pub fn compat_mount_path(source: &str) -> String {
+    format!("/{}/h264", source)
+}
+
+/// Mount path of one of a source's renditions
+pub fn rendition_mount_path(source: &str, rendition: &str) -> String {
+    format!("/{}/{}", source, rendition)
+}
+
+/// Size and rate a mount's stream is known to have, advertised in its appsrc
+/// caps. The payloader turns the framerate into the SDP's `a=framerate`, so
+/// NVRs don't have to guess it from the stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct MountVideo {
+    size: Option<(u32, u32)>,
+    framerate: Option<Framerate>,
+}
+
+impl MountVideo {
+    /// What the source's pipeline enforces for this mount. Passthrough keeps
+    /// the camera's own format, so nothing is advertised; only capture
+    /// sources set their framerate.
+    fn for_source(source: &SourceConfig, rendition: Option<&RenditionConfig>) -> Self {
+        if !source.encodes() {
+            return Self::default();
+        }
+        let size = match (source.width, source.height) {
+            (Some(w), Some(h)) => Some((w, h)),
+            _ => None,
+        };
+        let size = match rendition.map(|r| (r.width, r.height)) {
+            Some((Some(w), Some(h))) => Some((w, h)),
+            _ => size,
+        };
+        let size = size.map(|(w, h)| source.clamp_size(w, h));
+        let framerate = match source.source_type {
+            SourceType::V4l2 | SourceType::Test => source.framerate,
+            SourceType::Rtsp | SourceType::Srt => None,
+        };
+        Self { size, framerate }
+    }
+
+    /// Extra caps fields, each with a leading comma
+    fn caps_fields(&self) -> String {
+        let mut fields = String::new();
+        if let Some((width, height)) = self.size {
+            fields.push_str(&format!(",width={},height={}", width, height));
+        }
+        if let Some(framerate) = self.framerate {
+            fields.push_str(&format!(",framerate={}", framerate.fraction()));
+        }
+        fields
+    }
+}
+
+/// Payloader settings for RTCP sync: RTP timestamps start at zero running
+/// time rather than a random offset, so they track the shared pipeline clock
+const PAY_SYNC: &str = "timestamp-offset=0";
+
+/// Make a media's rtpbin put wall-clock time in RTCP sender reports, in the
+/// NTP epoch (1900) that RFC 3550 receivers expect. It comes from the same
+/// realtime clock as the shared pipeline clock, so SRs from every mount carry
+/// one timeline and a receiver can line sources up.
+fn configure_rtpbin(rtpbin: &gstreamer::Element) {
+    rtpbin.set_property_from_str("ntp-time-source", "ntp");
+    // Stamp the SR with the time the RTP data was captured, not sent
+    rtpbin.set_property("rtcp-sync-send-time", false);
+}
+
+/// Build the appsrc mount launch string: video on pay0, optional AAC on pay1
+///
+/// A queue decouples the payloader from the frame pusher thread. It does not
+/// leak: GStreamer's leaky queue cannot tell keyframes apart. Instead, when
+/// the appsrc signals enough-data the pusher's `PushGate` drops delta frames
+/// and keeps keyframes. Those keyframes block in the appsrc
+/// (`block=true`) until there is room, and meanwhile the mount's
+/// `FrameQueue` drops deltas the same way.
+fn mount_launch_string(
+    codec: OutputCodec,
+    video: MountVideo,
+    audio: bool,
+    queue_buffers: usize,
+) -> String {
+    let (caps, parse, pay) = match codec {
+        OutputCodec::H264 => (
+            "video/x-h264,stream-format=byte-stream,alignment=au",
+            "h264parse",
+            "rtph264pay",
+        ),
+        OutputCodec::H265 => (
+            "video/x-h265,stream-format=byte-stream,alignment=au",
+            "h265parse config-interval=-1",
+            "rtph265pay",
+        ),
+    };
+    let video = format!(
+        "appsrc name=videosrc is-live=true format=time do-timestamp=true block=true caps={caps}{fields} \
+         ! {parse} \
+         ! queue max-size-buffers={queue_buffers} max-size-bytes=0 max-size-time=0 \
+         ! {pay} name=pay0 pt=96 {sync}",
+        caps = caps,
+        fields = video.caps_fields(),
+        parse = parse,
+        queue_buffers = queue_buffers,
+        pay = pay,
+        sync = PAY_SYNC,
+    );
+
+    if audio {
+        format!(
+            "( {video} \
+               appsrc name=audiosrc is-live=true format=time do-timestamp=true caps={caps} \
+               ! aacparse \
+               ! rtpmp4gpay name=pay1 pt=97 {sync} )",
+            video = video,
+            caps = aac_caps(),
+            sync = PAY_SYNC,
+        )
+    } else {
+        format!("( {} )", video)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gstreamer_rtsp_server::gst_rtsp::RTSPLowerTrans;
+
+    fn frame(is_keyframe: bool, tag: u8) -> FrameData {
+        FrameData::new(
+            gstreamer::Buffer::from_slice([tag]),
+            is_keyframe,
+            StreamKind::Video,
+        )
+    }
+
+    fn tag(frame: &FrameData) -> u8 {
+        frame.buffer.map_readable().unwrap()[0]
+    }
+
+    #[test]
+    fn test_full_queue_drops_delta_frames() {
+        gstreamer::init().unwrap();
+
+        let bus = FrameBus::new(4);
+        let rx = bus.subscribe();
+
+        // K0 D1 D2 K3 fills the queue; D4 and D5 each evict the oldest delta
+        for (i, is_keyframe) in [true, false, false, true, false, false]
+            .into_iter()
+            .enumerate()
+        {
+            bus.send(frame(is_keyframe, i as u8));
+        }
+        assert_eq!(rx.take_dropped(), 2);
+        assert_eq!(rx.take_dropped(), 0);
+
+        let received: Vec<_> = std::iter::from_fn(|| rx.recv_timeout(Duration::ZERO).ok())
+            .map(|f| tag(&f))
+            .collect();
+        assert_eq!(received, vec![0, 3, 4, 5]);
+
+        // Only keyframes left to evict: the oldest goes, the rest still decode
+        for i in 0..5 {
+            bus.send(frame(true, i));
+        }
+        assert_eq!(tag(&rx.recv().unwrap()), 1);
+    }
+
+    #[test]
+    fn test_recv_from_keyframe() {
+        gstreamer::init().unwrap();
+
+        let bus = FrameBus::default();
+        let mut rx = bus.subscribe();
+        let audio = |tag: u8| {
+            FrameData::new(
+                gstreamer::Buffer::from_slice([tag]),
+                true,
+                StreamKind::Audio,
+            )
+        };
+
+        // Audio and deltas before the first video keyframe are dropped
+        bus.send(audio(0));
+        bus.send(frame(false, 1));
+        bus.send(frame(true, 2));
+        bus.send(audio(3));
+        bus.send(frame(false, 4));
+        assert!(!rx.is_synced());
+        let received: Vec<_> =
+            std::iter::from_fn(|| rx.recv_from_keyframe_timeout(Duration::ZERO).ok())
+                .map(|f| tag(&f))
+                .collect();
+        assert_eq!(received, vec![2, 3, 4]);
+        assert!(rx.is_synced());
+
+        // After a resync it waits for the next keyframe again
+        rx.resync();
+        bus.send(frame(false, 5));
+        bus.send(frame(true, 6));
+        assert_eq!(tag(&rx.recv_from_keyframe().unwrap()), 6);
+        assert!(matches!(
+            rx.recv_from_keyframe_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        ));
+    }
+
+    #[test]
+    fn test_receiver_sees_bus_drop() {
+        gstreamer::init().unwrap();
+
+        let bus = FrameBus::default();
+        let rx = bus.subscribe();
+        bus.send(frame(true, 7));
+        drop(bus);
+
+        // Queued frames still drain before the disconnect shows
+        assert_eq!(tag(&rx.recv().unwrap()), 7);
+        assert!(rx.recv().is_err());
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+    }
+
+    #[test]
+    fn test_frames_share_payload() {
+        gstreamer::init().unwrap();
+
+        let mut buffer = gstreamer::Buffer::from_slice(vec![0u8; 4096]);
+        buffer
+            .get_mut()
+            .unwrap()
+            .set_pts(gstreamer::ClockTime::from_seconds(1));
+        let payload = buffer.map_readable().unwrap().as_ptr();
+
+        let bus = FrameBus::default();
+        let outputs = [bus.subscribe(), bus.subscribe()];
+        bus.send(FrameData::new(buffer, false, StreamKind::Video));
+
+        for rx in outputs {
+            let pushed = rx.recv().unwrap().output_buffer();
+            // Same memory the source produced, no copy on the way
+            assert_eq!(pushed.map_readable().unwrap().as_ptr(), payload);
+            assert_eq!(pushed.pts(), None);
+            assert!(pushed.flags().contains(gstreamer::BufferFlags::DELTA_UNIT));
+        }
+    }
+
+    #[test]
+    fn test_v4l2_mount_shares_one_capture() {
+        gstreamer::init().unwrap();
+
+        let source: SourceConfig = toml::from_str(
+            r#"
+            name = "cam1"
+            type = "v4l2"
+            device = "/dev/video0"
+            [encode]
+        "#,
+        )
+        .unwrap();
+
+        // Only the source's own pipeline touches the device
+        let capture =
+            crate::sources::pipeline_string(&source, crate::sources::EncoderBackend::X264).unwrap();
+        assert_eq!(capture.matches("v4l2src").count(), 1);
+        assert!(capture.ends_with(crate::sources::appsink_config()));
+        let mount = mount_launch_string(
+            OutputCodec::H264,
+            MountVideo::default(),
+            false,
+            source.output_queue_buffers,
+        );
+        assert!(mount.starts_with("( appsrc name=videosrc"));
+        assert!(!mount.contains("v4l2src"));
+
+        // Each client's media subscribes to the mount's bus; one captured
+        // frame reaches both
+        let bus = FrameBus::new(source.queue_frames);
+        let clients = [bus.subscribe(), bus.subscribe()];
+        for i in 0..3 {
+            bus.send(frame(i == 0, i));
+        }
+        for client in &clients {
+            let received: Vec<_> = std::iter::from_fn(|| client.recv_timeout(Duration::ZERO).ok())
+                .map(|f| tag(&f))
+                .collect();
+            assert_eq!(received, vec![0, 1, 2]);
+        }
+    }
+
+    #[test]
+    fn test_bus_for_path() {
+        let main = Arc::new(FrameBus::default());
+        let low = Arc::new(FrameBus::default());
+        let buses = HashMap::from([
+            ("/cam1/stream".to_string(), Arc::downgrade(&main)),
+            ("/cam1/low".to_string(), Arc::downgrade(&low)),
+        ]);
+        let found = |path| bus_for_path(&buses, path);
+
+        assert!(Arc::ptr_eq(&found("/cam1/stream").unwrap(), &main));
+        assert!(Arc::ptr_eq(&found("/cam1/stream/").unwrap(), &main));
+        assert!(Arc::ptr_eq(&found("/cam1/stream/stream=0").unwrap(), &main));
+        assert!(Arc::ptr_eq(&found("/cam1/low").unwrap(), &low));
+        assert!(found("/cam1").is_none());
+        assert!(found("/cam2/stream").is_none());
+        assert!(found("").is_none());
+    }
+
+    #[test]
+    fn test_viewer_counting() {
+        let bus = Arc::new(FrameBus::default());
+        let other = Arc::new(FrameBus::default());
+        let (mut first, mut second) = (ClientViews::default(), ClientViews::default());
+
+        // Two clients on one shared mount are two viewers
+        first.play(&bus);
+        second.play(&bus);
+        assert_eq!(bus.viewers(), 2);
+
+        // PLAY again after PAUSE is still one viewer
+        first.play(&bus);
+        assert_eq!(bus.viewers(), 2);
+
+        // One client can watch several mounts
+        first.play(&other);
+        assert_eq!(other.viewers(), 1);
+
+        first.teardown(&bus);
+        assert_eq!(bus.viewers(), 1);
+        // A repeated or stray TEARDOWN doesn't count twice
+        first.teardown(&bus);
+        assert_eq!(bus.viewers(), 1);
+
+        // A dropped connection releases everything it was playing
+        first.close();
+        second.close();
+        assert_eq!((bus.viewers(), other.viewers()), (0, 0));
+        second.close();
+        assert_eq!(bus.viewers(), 0);
+    }
+
+    #[test]
+    fn test_play_requests_keyframe() {
+        let bus = Arc::new(FrameBus::default());
+        let mut client = ClientViews::default();
+        assert!(!bus.take_keyframe_request());
+
+        client.play(&bus);
+        assert!(bus.take_keyframe_request());
+        assert!(!bus.take_keyframe_request());
+
+        // Resuming a mount the client already plays needs no new keyframe
+        client.play(&bus);
+        assert!(!bus.take_keyframe_request());
+    }
+
+    #[test]
+    fn test_mount_launch_audio() {
+        let video_only = mount_launch_string(OutputCodec::H264, MountVideo::default(), false, 30);
+        assert!(video_only.contains("rtph264pay name=pay0"));
+        assert!(!video_only.contains("pay1"));
+
+        let with_audio = mount_launch_string(OutputCodec::H265, MountVideo::default(), true, 30);
+        assert!(with_audio.contains("rtph265pay name=pay0"));
+        assert!(with_audio.contains(&format!(
+            "appsrc name=audiosrc is-live=true format=time do-timestamp=true caps={}",
+            aac_caps()
+        )));
+        assert!(
+            with_audio.ends_with("! aacparse ! rtpmp4gpay name=pay1 pt=97 timestamp-offset=0 )")
+        );
+    }
+
+    #[test]
+    fn test_mount_caps_advertise_video() {
+        let source = |toml: &str| -> SourceConfig { toml::from_str(toml).unwrap() };
+        let appsrc_caps = |video| {
+            let mount = mount_launch_string(OutputCodec::H264, video, false, 30);
+            mount
+                .split(" ! ")
+                .next()
+                .unwrap()
+                .rsplit("caps=")
+                .next()
+                .unwrap()
+                .to_string()
+        };
+
+        let webcam = source(
+            r#"
+            name = "hdmi"
+            type = "v4l2"
+            device = "/dev/video0"
+            width = 1920
+            height = 1080
+            framerate = 30
+            [encode]
+            [[rendition]]
+            name = "low"
+            bitrate = 500
+            width = 640
+            height = 360
+            "#,
+        );
+        let main = MountVideo::for_source(&webcam, None);
+        assert_eq!(
+            appsrc_caps(main),
+            "video/x-h264,stream-format=byte-stream,alignment=au,width=1920,height=1080,framerate=30/1"
+        );
+        let low = MountVideo::for_source(&webcam, Some(&webcam.renditions[0]));
+        assert!(appsrc_caps(low).ends_with(",width=640,height=360,framerate=30/1"));
+
+        // The advertised size is the one left after max_width clamps it
+        let mut webcam = webcam;
+        webcam.max_width = Some(1280);
+        assert!(appsrc_caps(MountVideo::for_source(&webcam, None))
+            .ends_with(",width=1280,height=720,framerate=30/1"));
+
+        // NTSC capture gives the SDP its exact rate
+        webcam.framerate = Some(Framerate::new(30000, 1001).unwrap());
+        assert!(
+            appsrc_caps(MountVideo::for_source(&webcam, None)).ends_with(",framerate=30000/1001")
+        );
+
+        // Passthrough streams keep whatever the camera sends
+        let camera = source(
+            r#"
+            name = "cam1"
+            type = "rtsp"
+            url = "rtsp://camera.local/stream"
+            width = 1280
+            height = 720
+            framerate = 25
+            "#,
+        );
+        assert_eq!(MountVideo::for_source(&camera, None), MountVideo::default());
+        assert_eq!(
+            appsrc_caps(MountVideo::default()),
+            crate::sources::h264_caps()
+        );
+
+        // A transcoded network stream is scaled but keeps its own rate
+        let transcoded = source(
+            r#"
+            name = "cam1"
+            type = "rtsp"
+            url = "rtsp://camera.local/stream"
+            width = 1280
+            height = 720
+            framerate = 25
+            transcode = true
+            [encode]
+            "#,
+        );
+        assert_eq!(
+            appsrc_caps(MountVideo::for_source(&transcoded, None)),
+            format!("{},width=1280,height=720", crate::sources::h264_caps())
+        );
+    }
+
+    #[test]
+    fn test_mount_output_queue() {
+        let h264 = mount_launch_string(OutputCodec::H264, MountVideo::default(), false, 30);
+        assert!(h264.contains("do-timestamp=true block=true caps=video/x-h264"));
+        assert!(h264.contains(
+            "! h264parse ! queue max-size-buffers=30 max-size-bytes=0 max-size-time=0 ! rtph264pay name=pay0"
+        ));
+        assert!(!h264.contains("leaky"));
+
+        let h265 = mount_launch_string(OutputCodec::H265, MountVideo::default(), false, 5);
+        assert!(h265.contains(
+            "! h265parse config-interval=-1 ! queue max-size-buffers=5 max-size-bytes=0 max-size-time=0 ! rtph265pay name=pay0"
+        ));
+    }
+
+    #[test]
+    fn test_rendition_mount_paths() {
+        let source: SourceConfig = toml::from_str(
+            r#"
+            name = "cam1"
+            type = "test"
+            [encode]
+            bitrate = 4000
+            [[rendition]]
+            name = "high"
+            bitrate = 2500
+            [[rendition]]
+            name = "low"
+            bitrate = 600
+            width = 640
+        "#,
+        )
+        .unwrap();
+
+        let mounts: Vec<_> = source
+            .renditions
+            .iter()
+            .map(|r| {
+                (
+                    rendition_mount_path(&source.name, &r.name),
+                    source.rendition_encode(r).bitrate,
+                )
+            })
+            .collect();
+        assert_eq!(
+            mounts,
+            [
+                ("/cam1/high".to_string(), 2500),
+                ("/cam1/low".to_string(), 600)
+            ]
+        );
+        assert_eq!(stream_mount_path(&source.name), "/cam1/stream");
+    }
+
+    #[test]
+    fn test_auth_registry_per_mount_roles() {
+        let mut registry = AuthRegistry::default();
+
+        let (role1, new1) =
+            registry.register("/cam1/stream", "alice", "secret1", AuthMethod::Basic);
+        let (role2, new2) = registry.register("/cam2/stream", "bob", "secret2", AuthMethod::Basic);
+
+        assert!(new1 && new2);
+        assert_ne!(role1, role2);
+        assert_eq!(registry.role_for("/cam1/stream"), Some(role1.as_str()));
+        assert_eq!(registry.role_for("/cam2/stream"), Some(role2.as_str()));
+        assert_eq!(registry.role_for("/cam3/stream"), None);
+    }
+
+    #[test]
+    fn test_auth_registry_shared_credentials_reuse_role() {
+        let mut registry = AuthRegistry::default();
+
+        let (role1, _) = registry.register("/cam1/stream", "alice", "secret", AuthMethod::Basic);
+        let (role2, is_new) =
+            registry.register("/cam2/stream", "alice", "secret", AuthMethod::Basic);
+        assert!(!is_new);
+        assert_eq!(role1, role2);
+
+        registry.remove("/cam1/stream");
+        assert_eq!(registry.role_for("/cam1/stream"), None);
+        assert_eq!(registry.role_for("/cam2/stream"), Some(role2.as_str()));
+    }
+
+    #[test]
+    fn test_auth_registry_method_selection() {
+        let mut registry = AuthRegistry::default();
+        assert_eq!(registry.supported_methods(), RTSPAuthMethod::Basic);
+
+        // Same credentials with a different scheme need their own role
+        let (basic, _) = registry.register("/cam1/stream", "alice", "secret", AuthMethod::Basic);
+        let (digest, is_new) =
+            registry.register("/cam2/stream", "alice", "secret", AuthMethod::Digest);
+        assert!(is_new);
+        assert_ne!(basic, digest);
+        assert_eq!(registry.supported_methods(), RTSPAuthMethod::Digest);
+
+        let mut basic_only = AuthRegistry::default();
+        basic_only.register("/cam1/stream", "alice", "secret", AuthMethod::Basic);
+        assert_eq!(basic_only.supported_methods(), RTSPAuthMethod::Basic);
+    }
+
+    #[test]
+    fn test_mount_payloaders_fix_timestamp_offset() {
+        let mount = mount_launch_string(OutputCodec::H264, MountVideo::default(), true, 30);
+        assert!(mount.contains("! rtph264pay name=pay0 pt=96 timestamp-offset=0 "));
+        assert!(mount.contains("! rtpmp4gpay name=pay1 pt=97 timestamp-offset=0 "));
+    }
+
+    #[test]
+    #[ignore = "needs a GStreamer runtime with rtpbin, rtph264pay and rtpmp4gpay"]
+    fn test_rtcp_sync_properties_on_elements() {
+        gstreamer::init().unwrap();
+
+        let mount = mount_launch_string(OutputCodec::H264, MountVideo::default(), true, 30);
+        let bin = gstreamer::parse::launch(mount.trim_start_matches('(').trim_end_matches(')'))
+            .unwrap()
+            .downcast::<gstreamer::Bin>()
+            .unwrap();
+        for pay in ["pay0", "pay1"] {
+            let pay = bin.by_name(pay).unwrap();
+            assert_eq!(pay.property::<u32>("timestamp-offset"), 0);
+        }
+
+        let rtpbin = gstreamer::ElementFactory::make("rtpbin").build().unwrap();
+        configure_rtpbin(&rtpbin);
+        let source = rtpbin.property_value("ntp-time-source");
+        let source = glib::EnumValue::from_value(&source).unwrap().1;
+        assert_eq!(source.nick(), "ntp");
+        assert!(!rtpbin.property::<bool>("rtcp-sync-send-time"));
+    }
+
+    #[test]
+    #[ignore = "needs gst-rtsp-server and a GIO TLS backend (glib-networking)"]
+    fn test_tls_certificate_fixture() {
+        gstreamer::init().unwrap();
+        let testdata = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata");
+
+        let server = RtspServer::new(0, "127.0.0.1").unwrap();
+        server
+            .set_tls_certificate(
+                &testdata.join("tls-cert.pem"),
+                &testdata.join("tls-key.pem"),
+            )
+            .unwrap();
+        assert!(server.auth.tls_certificate().is_some());
+
+        // A key that doesn't parse is reported, not ignored
+        assert!(server
+            .set_tls_certificate(
+                &testdata.join("tls-cert.pem"),
+                &testdata.join("missing.pem")
+            )
+            .is_err());
+    }
+
+    #[test]
+    #[ignore = "needs gst-rtsp-server"]
+    fn test_attach_to_custom_context() {
+        gstreamer::init().unwrap();
+        let context = glib::MainContext::new();
+        let server = RtspServer::new(0, "127.0.0.1").unwrap();
+        server.attach(&context).unwrap();
+
+        // Serving from the caller's context, with no loop of our own
+        let source = {
+            let attached = server.attached.lock().unwrap();
+            let (attached, source_id) = attached.as_ref().unwrap();
+            assert_eq!(*attached, context);
+            assert!(glib::MainContext::default()
+                .find_source_by_id(source_id)
+                .is_none());
+            context.find_source_by_id(source_id).unwrap()
+        };
+        assert!(server.main_loop.lock().unwrap().is_none());
+
+        // Stopping detaches from the caller's context
+        server.stop();
+        assert!(source.is_destroyed());
+    }
+
+    #[test]
+    #[ignore = "needs gst-rtsp-server"]
+    fn test_ephemeral_port_is_reported() {
+        gstreamer::init().unwrap();
+        let context = glib::MainContext::new();
+        let server = RtspServer::new(0, "127.0.0.1").unwrap();
+        assert_eq!(server.bound_port(), None);
+
+        server.attach(&context).unwrap();
+        let port = server.bound_port().unwrap();
+        assert_ne!(port, 0);
+        // It's really listening there
+        assert!(std::net::TcpStream::connect(("127.0.0.1", port)).is_ok());
+        server.stop();
+    }
+
+    #[test]
+    #[ignore = "needs gst-rtsp-server"]
+    fn test_compat_mount_registered() {
+        gstreamer::init().unwrap();
+        let source: SourceConfig = toml::from_str(
+            r#"
+            name = "cam1"
+            type = "rtsp"
+            url = "rtsp://camera.local/stream"
+            source_codec = "h265"
+            compat_h264 = true
+        "#,
+        )
+        .unwrap();
+
+        let server = RtspServer::new(0, "127.0.0.1").unwrap();
+        server.add_mount(&source, OutputCodec::H265).unwrap();
+        server.add_compat_mount(&source).unwrap();
+
+        let launch = |path: &str| {
+            let (factory, matched) = server.mounts.match_(path);
+            assert_eq!(matched, path.len() as i32);
+            factory.launch().unwrap().to_string()
+        };
+        assert!(launch("/cam1/stream").contains("rtph265pay name=pay0"));
+        assert!(launch("/cam1/h264").contains("rtph264pay name=pay0"));
+        assert_eq!(
+            server.source_mounts.lock().unwrap()["cam1"],
+            source.mount_paths()
+        );
+
+        // Both go with the source
+        server.remove_mount("cam1");
+        assert!(!server.source_mounts.lock().unwrap().contains_key("cam1"));
+    }
+
+    #[test]
+    #[ignore = "needs gst-rtsp-server"]
+    fn test_multicast_mount_configured() {
+        gstreamer::init().unwrap();
+        let source: SourceConfig = toml::from_str(
+            r#"
+            name = "cam1"
+            type = "rtsp"
+            url = "rtsp://camera.local/stream"
+
+            [multicast]
+            address_min = "239.255.42.1"
+            address_max = "239.255.42.9"
+            port_min = 5000
+            port_max = 5099
+            ttl = 2
+        "#,
+        )
+        .unwrap();
+
+        let server = RtspServer::new(0, "127.0.0.1").unwrap();
+        server.add_mount(&source, OutputCodec::H264).unwrap();
+
+        let (factory, _) = server.mounts.match_("/cam1/stream");
+        assert!(factory.protocols().contains(RTSPLowerTrans::UDP_MCAST));
+        assert!(factory.protocols().contains(RTSPLowerTrans::TCP));
+        assert!(factory.address_pool().is_some());
+        assert!(server.multicast_pools.lock().unwrap().contains_key("cam1"));
+
+        server.remove_mount("cam1");
+        assert!(server.multicast_pools.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_push_gate_backpressure() {
+        let gate = PushGate::default();
+        assert!(gate.admit(true));
+        assert!(gate.admit(false));
+
+        // enough-data: deltas are dropped, keyframes still go through
+        gate.enough_data();
+        assert!(!gate.admit(false));
+        assert!(gate.admit(true));
+        assert!(!gate.admit(false));
+
+        // need-data after a gap asks for a keyframe and resumes deltas on one
+        assert!(gate.need_data());
+        assert!(!gate.admit(false));
+        assert!(gate.admit(true));
+        assert!(gate.admit(false));
+
+        // need-data without anything dropped resumes straight away
+        gate.enough_data();
+        assert!(!gate.need_data());
+        assert!(gate.admit(false));
+    }
+
+    #[test]
+    fn test_gop_dropper_thins_by_whole_gops() {
+        // 30 fps with a keyframe every second, thinned to 10 fps
+        let mut dropper = GopDropper::new(10.0);
+        let forwarded: Vec<bool> = (0..180u64)
+            .map(|i| dropper.admit(i % 30 == 0, Duration::from_millis(i * 1000 / 30)))
+            .collect();
+
+        // One GOP in three goes through, whole
+        for (gop, frames) in forwarded.chunks(30).enumerate() {
+            let expected = gop % 3 == 0;
+            assert!(frames.iter().all(|&f| f == expected), "GOP {}", gop);
+        }
+        assert_eq!(forwarded.iter().filter(|&&f| f).count(), 60);
+
+        // Nothing before the first keyframe, which always goes through
+        let mut dropper = GopDropper::new(1.0);
+        assert!(!dropper.admit(false, Duration::ZERO));
+        assert!(dropper.admit(true, Duration::from_millis(40)));
+        assert!(dropper.admit(false, Duration::from_millis(80)));
+
+        // A target above the source rate keeps everything
+        let mut dropper = GopDropper::new(60.0);
+        assert!(
+            (0..90u64).all(|i| dropper.admit(i % 30 == 0, Duration::from_millis(i * 1000 / 30)))
+        );
+    }
+}
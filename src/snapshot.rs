@@ -0,0 +1,303 @@
+//! JPEG snapshots - `GET /{name}/snapshot.jpg` on the HTTP API port
+//!
+//! Decodes the source's most recent keyframe on demand:
+//! appsrc -> h26Xparse -> decodebin -> videoconvert -> jpegenc -> appsink
+//!
+//! The same decode also saves a JPEG to disk each time a source recovers
+//! from fallback (`[sources.snapshot_on_recover]`).
+
+use crate::config::OutputCodec;
+use crate::http::{source_authorized, Request, Response};
+use crate::rtsp::{FrameData, FrameReceiver, StreamKind};
+use crate::sources::{KeyframeCache, Source};
+use anyhow::{Context, Result};
+use gstreamer::prelude::*;
+use gstreamer_app::{AppSink, AppSrc};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, info, warn};
+
+/// How long to wait for the decoder to produce a picture
+const DECODE_TIMEOUT_SECS: u64 = 5;
+
+/// How long a recovery snapshot waits for the source's first keyframe
+const RECOVER_KEYFRAME_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Handle a snapshot request
+pub fn handle_request(sources: &[Arc<Source>], request: &Request) -> Response {
+    let Some(name) = request
+        .path
+        .strip_prefix('/')
+        .and_then(|p| p.strip_suffix("/snapshot.jpg"))
+    else {
+        return Response::not_found();
+    };
+    if request.method != "GET" {
+        return Response::text(405, "Method Not Allowed");
+    }
+
+    let Some(source) = sources.iter().find(|s| s.name() == name) else {
+        return Response::not_found();
+    };
+    if !source_authorized(source.config().auth.as_ref(), request) {
+        return Response::unauthorized();
+    }
+
+    // Nothing decoded yet (starting up, or in fallback since startup)
+    let Some(keyframe) = source.last_keyframe() else {
+        return Response::text(503, "No keyframe available yet");
+    };
+
+    match encode_jpeg(&keyframe, source.output_codec()) {
+        Ok(jpeg) => Response::ok("image/jpeg", jpeg),
+        Err(e) => {
+            warn!("Snapshot for '{}' failed: {:#}", name, e);
+            Response::text(500, "Snapshot failed")
+        }
+    }
+}
+
+/// Save the next live video keyframe from `frames` as a JPEG in `dir`, in
+/// the background. Fallback frames share the bus, so only a keyframe that
+/// `live_keyframe` also holds counts.
+pub fn save_on_recover(
+    name: &str,
+    dir: &Path,
+    codec: OutputCodec,
+    frames: FrameReceiver,
+    live_keyframe: KeyframeCache,
+) {
+    let name = name.to_string();
+    let dir = dir.to_path_buf();
+    std::thread::spawn(move || {
+        let deadline = Instant::now() + RECOVER_KEYFRAME_TIMEOUT;
+        let keyframe = loop {
+            match frames.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(frame) if is_live_keyframe(&frame, &live_keyframe) => break frame.buffer,
+                Ok(_) => continue,
+                Err(_) => {
+                    warn!("No keyframe from '{}' to snapshot after recovering", name);
+                    return;
+                }
+            }
+        };
+        // Done with the stream; don't hold frames back for the decode
+        drop(frames);
+
+        let path = dir.join(recover_file_name(&name, SystemTime::now()));
+        let result = encode_jpeg(&keyframe, codec).and_then(|jpeg| {
+            std::fs::create_dir_all(&dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+            std::fs::write(&path, jpeg)
+                .with_context(|| format!("Failed to write {}", path.display()))
+        });
+        match result {
+            Ok(()) => info!(
+                "Saved recovery snapshot of '{}' to {}",
+                name,
+                path.display()
+            ),
+            Err(e) => warn!("Recovery snapshot of '{}' failed: {:#}", name, e),
+        }
+    });
+}
+
+/// Whether `frame` is a keyframe from the live pipeline. Its appsink callback
+/// caches each live keyframe before sending it; fallback frames never are.
+fn is_live_keyframe(frame: &FrameData, live_keyframe: &KeyframeCache) -> bool {
+    frame.kind == StreamKind::Video
+        && frame.is_keyframe
+        && live_keyframe
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|live| live.as_ptr() == frame.buffer.as_ptr())
+}
+
+/// Recovery snapshot file name: `{name}-YYYYMMDD-HHMMSS.jpg` (UTC)
+fn recover_file_name(name: &str, time: SystemTime) -> String {
+    format!("{}-{}.jpg", name, crate::record::timestamp(time))
+}
+
+/// Decode one encoded keyframe and re-encode it as JPEG
+pub fn encode_jpeg(keyframe: &gstreamer::Buffer, codec: OutputCodec) -> Result<Vec<u8>> {
+    let pipeline_str = pipeline_string(codec);
+    debug!(
+        "Snapshot pipeline: {}",
+        crate::logging::redact_pipeline(&pipeline_str)
+    );
+
+    let pipeline = gstreamer::parse::launch(&pipeline_str)
+        .context("Failed to create snapshot pipeline")?
+        .downcast::<gstreamer::Pipeline>()
+        .map_err(|_| anyhow::anyhow!("Failed to downcast to Pipeline"))?;
+
+    let appsrc = pipeline
+        .by_name("src")
+        .ok_or_else(|| anyhow::anyhow!("Missing src element"))?
+        .dynamic_cast::<AppSrc>()
+        .map_err(|_| anyhow::anyhow!("Failed to cast to AppSrc"))?;
+    let sink = pipeline
+        .by_name("sink")
+        .ok_or_else(|| anyhow::anyhow!("Missing sink element"))?
+        .dynamic_cast::<AppSink>()
+        .map_err(|_| anyhow::anyhow!("Failed to cast to AppSink"))?;
+
+    pipeline
+        .set_state(gstreamer::State::Playing)
+        .map_err(|e| anyhow::anyhow!("Failed to start snapshot pipeline: {:?}", e))?;
+
+    // One keyframe then EOS so the decoder flushes the picture out
+    let result = appsrc
+        .push_buffer(keyframe.copy())
+        .map_err(|e| anyhow::anyhow!("Failed to push keyframe: {:?}", e))
+        .and_then(|_| {
+            appsrc
+                .end_of_stream()
+                .map_err(|e| anyhow::anyhow!("Failed to end stream: {:?}", e))
+        })
+        .and_then(|_| {
+            let sample = sink
+                .try_pull_sample(gstreamer::ClockTime::from_seconds(DECODE_TIMEOUT_SECS))
+                .ok_or_else(|| anyhow::anyhow!("Decoder produced no picture"))?;
+            let buffer = sample
+                .buffer()
+                .ok_or_else(|| anyhow::anyhow!("Snapshot sample has no buffer"))?;
+            let map = buffer
+                .map_readable()
+                .map_err(|_| anyhow::anyhow!("Failed to map snapshot buffer"))?;
+            Ok(map.as_slice().to_vec())
+        });
+
+    pipeline.set_state(gstreamer::State::Null).ok();
+    result
+}
+
+/// Build the decode-and-JPEG launch string
+fn pipeline_string(codec: OutputCodec) -> String {
+    let (caps, parse) = match codec {
+        OutputCodec::H264 => (crate::sources::h264_caps(), "h264parse"),
+        OutputCodec::H265 => (crate::sources::h265_caps(), "h265parse"),
+    };
+
+    format!(
+        "appsrc name=src format=time caps={caps} \
+         ! {parse} \
+         ! decodebin \
+         ! videoconvert \
+         ! jpegenc snapshot=true \
+         ! appsink name=sink sync=false",
+        caps = caps,
+        parse = parse,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline_string() {
+        let pipeline = pipeline_string(OutputCodec::H265);
+        assert!(pipeline.contains("caps=video/x-h265"));
+        assert!(pipeline.contains("! h265parse ! decodebin ! videoconvert ! jpegenc"));
+    }
+
+    #[test]
+    fn test_snapshot_requires_source_auth() {
+        let config = toml::from_str(
+            r#"
+            name = "cam1"
+            type = "rtsp"
+            url = "rtsp://camera.local/stream"
+            [auth]
+            enabled = true
+            username = "admin"
+            password = "secret"
+        "#,
+        )
+        .unwrap();
+        let source = Source::new(
+            config,
+            Arc::new(crate::rtsp::FrameBus::default()),
+            None,
+            crate::sources::EncoderBackend::X264,
+        )
+        .unwrap();
+        let request = |authorization: Option<&str>| Request {
+            method: "GET".to_string(),
+            path: "/cam1/snapshot.jpg".to_string(),
+            headers: authorization
+                .map(|value| ("authorization".to_string(), value.to_string()))
+                .into_iter()
+                .collect(),
+            body: Vec::new(),
+        };
+        let sources = [Arc::new(source)];
+
+        let response = handle_request(&sources, &request(None));
+        assert_eq!(response.status, 401);
+        // admin:wrong
+        let response = handle_request(&sources, &request(Some("Basic YWRtaW46d3Jvbmc=")));
+        assert_eq!(response.status, 401);
+        // admin:secret gets through to the (still empty) keyframe cache
+        let response = handle_request(&sources, &request(Some("Basic YWRtaW46c2VjcmV0")));
+        assert_eq!(response.status, 503);
+    }
+
+    #[test]
+    fn test_recover_snapshot_skips_fallback_frames() {
+        gstreamer::init().unwrap();
+        let live = FrameData::new(gstreamer::Buffer::new(), true, StreamKind::Video);
+        let cache: KeyframeCache = Arc::new(std::sync::Mutex::new(Some(live.buffer.clone())));
+        assert!(is_live_keyframe(&live, &cache));
+        assert!(is_live_keyframe(&live.clone(), &cache));
+
+        // A fallback keyframe sent while the camera flaps is not the live one
+        let fallback = FrameData::new(gstreamer::Buffer::new(), true, StreamKind::Video);
+        assert!(!is_live_keyframe(&fallback, &cache));
+        *cache.lock().unwrap() = None;
+        assert!(!is_live_keyframe(&live, &cache));
+    }
+
+    #[test]
+    fn test_recover_file_name() {
+        let time = std::time::UNIX_EPOCH + Duration::from_secs(1_709_211_909);
+        assert_eq!(
+            recover_file_name("lobby", time),
+            "lobby-20240229-130509.jpg"
+        );
+    }
+
+    #[test]
+    #[ignore = "needs a GStreamer runtime with x264enc, avdec_h264 and jpegenc"]
+    fn test_encode_jpeg_from_keyframe() {
+        gstreamer::init().unwrap();
+
+        // Encode a single synthetic keyframe
+        let pipeline = gstreamer::parse::launch(&format!(
+            "videotestsrc num-buffers=1 ! video/x-raw,width=320,height=240 \
+             ! x264enc key-int-max=1 ! {} ! h264parse ! appsink name=sink sync=false",
+            crate::sources::h264_caps()
+        ))
+        .unwrap()
+        .downcast::<gstreamer::Pipeline>()
+        .unwrap();
+        let sink = pipeline
+            .by_name("sink")
+            .unwrap()
+            .dynamic_cast::<AppSink>()
+            .unwrap();
+        pipeline.set_state(gstreamer::State::Playing).unwrap();
+        let sample = sink
+            .try_pull_sample(gstreamer::ClockTime::from_seconds(5))
+            .unwrap();
+        let keyframe = sample.buffer_owned().unwrap();
+        pipeline.set_state(gstreamer::State::Null).unwrap();
+
+        let jpeg = encode_jpeg(&keyframe, OutputCodec::H264).unwrap();
+        assert_eq!(&jpeg[..2], &[0xFF, 0xD8], "missing JPEG SOI");
+        assert_eq!(&jpeg[jpeg.len() - 2..], &[0xFF, 0xD9], "missing JPEG EOI");
+    }
+}
@@ -1,29 +1,157 @@
 pub mod rtsp;
+pub mod srt;
+pub mod test;
 pub mod v4l2;
 
-use crate::config::{EncodeConfig, SourceConfig, SourceType};
-use crate::fallback::FallbackFrame;
-use crate::rtsp::{FrameData, FrameSender};
+use crate::config::{
+    EncodeConfig, EncoderChoice, OutputCodec, OverlayPosition, RateControl, SourceConfig,
+    SourceType, DEFAULT_QUANTIZER,
+};
+use crate::error::DartError;
+use crate::fallback::FallbackSource;
+use crate::logging;
+use crate::rtsp::{FrameBus, FrameData, StreamKind};
 use anyhow::Result;
 use gstreamer::prelude::*;
 use gstreamer_app::AppSink;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
-use tracing::{debug, error, info, warn};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, info_span, warn};
 
-/// Check if Rockchip MPP H.265 encoder is available
+/// Fast poll interval for the first reconnect probe after a failure
+const FAST_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long a reconnect probe waits for the source to answer
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often a probe checks its pipeline's bus, in milliseconds
+const PROBE_POLL_MS: u64 = 100;
+
+/// How long an intentional stop waits for EOS to drain through a pipeline
+pub const EOS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How often an idle source checks for a client
+const IDLE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Pause before restarting a run loop that panicked
+const PANIC_RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Rockchip MPP elements: the H.265 encoder, and the decoder RTSP/SRT
+/// transcoding needs. MPP is only used when all of them are installed.
+const MPP_ELEMENTS: &[&str] = &["mpph265enc", "mppvideodec"];
+
+/// Check if the Rockchip MPP encoder and decoder are available
 pub fn mpp_available() -> bool {
-    gstreamer::ElementFactory::find("mpph265enc").is_some()
+    mpp_usable(|name| gstreamer::ElementFactory::find(name).is_some())
 }
 
-/// Source state
+/// Whether every MPP element passes `available`. A partial install (say the
+/// encoder without the decoder) is reported, since the board clearly has MPP.
+fn mpp_usable(available: impl Fn(&str) -> bool) -> bool {
+    let missing = missing_elements(MPP_ELEMENTS, available);
+    if !missing.is_empty() && missing.len() < MPP_ELEMENTS.len() {
+        warn!(
+            "Rockchip MPP is incomplete ({} not found), not using hardware encoding",
+            missing.join(", ")
+        );
+    }
+    missing.is_empty()
+}
+
+/// The elements of `required` that are not `available`
+fn missing_elements<'a>(required: &[&'a str], available: impl Fn(&str) -> bool) -> Vec<&'a str> {
+    required
+        .iter()
+        .copied()
+        .filter(|name| !available(name))
+        .collect()
+}
+
+/// Check for a VAAPI H.264 encoder, preferring the newer `va` plugin
+pub fn vaapi_available() -> Option<VaapiElement> {
+    if gstreamer::ElementFactory::find("vah264enc").is_some() {
+        Some(VaapiElement::Va)
+    } else if gstreamer::ElementFactory::find("vaapih264enc").is_some() {
+        Some(VaapiElement::Vaapi)
+    } else {
+        None
+    }
+}
+
+/// VAAPI encoder element family
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VaapiElement {
+    /// `vah264enc` from gst-plugins-bad (GStreamer 1.22+)
+    Va,
+    /// `vaapih264enc` from the legacy gstreamer-vaapi plugin
+    Vaapi,
+}
+
+/// Encoder used for sources that encode or transcode
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncoderBackend {
+    /// Rockchip MPP hardware H.265
+    Mpp,
+    /// Intel/AMD VAAPI hardware H.264
+    Vaapi(VaapiElement),
+    /// Software x264 H.264
+    X264,
+}
+
+impl EncoderBackend {
+    /// Pick a backend by precedence: MPP > VAAPI > x264
+    pub fn select(mpp: bool, vaapi: Option<VaapiElement>) -> Self {
+        match (mpp, vaapi) {
+            (true, _) => Self::Mpp,
+            (false, Some(element)) => Self::Vaapi(element),
+            (false, None) => Self::X264,
+        }
+    }
+
+    /// Resolve a configured encoder choice against what is available
+    pub fn resolve(choice: EncoderChoice, mpp: bool, vaapi: Option<VaapiElement>) -> Result<Self> {
+        match choice {
+            EncoderChoice::Auto => Ok(Self::select(mpp, vaapi)),
+            EncoderChoice::X264 => Ok(Self::X264),
+            EncoderChoice::Mpp if mpp => Ok(Self::Mpp),
+            EncoderChoice::Mpp => {
+                anyhow::bail!(
+                    "encoder = \"mpp\" but {} are not all available",
+                    MPP_ELEMENTS.join(" and ")
+                )
+            }
+            EncoderChoice::Vaapi => vaapi.map(Self::Vaapi).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "encoder = \"vaapi\" but neither vah264enc nor vaapih264enc is available"
+                )
+            }),
+        }
+    }
+
+    /// Codec this backend encodes to
+    pub fn output_codec(self) -> OutputCodec {
+        match self {
+            Self::Mpp => OutputCodec::H265,
+            Self::Vaapi(_) | Self::X264 => OutputCodec::H264,
+        }
+    }
+}
+
+/// Source state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum SourceState {
     /// Streaming live from source
     Live,
     /// Source disconnected, showing fallback
     Fallback,
+    /// Pipeline released until an RTSP client connects
+    Idle,
+    /// Pipeline paused (device open, nothing captured) until a client connects
+    Paused,
     /// Stopped
     Stopped,
 }
@@ -32,89 +160,324 @@ pub enum SourceState {
 pub struct Source {
     name: String,
     config: SourceConfig,
-    frame_tx: Arc<Mutex<Option<FrameSender>>>,
-    fallback: Option<FallbackFrame>,
+    frame_tx: Arc<FrameBus>,
+    /// Frame bus of each rendition's mount, by rendition name
+    renditions: Vec<(String, Arc<FrameBus>)>,
+    /// Frame bus of the H.264 compatibility mount, fed by its own transcoder
+    compat: Option<Arc<FrameBus>>,
+    fallback: Option<FallbackSource>,
     state: Arc<Mutex<SourceState>>,
-    running: Arc<AtomicBool>,
-    mpp: bool,
+    /// Cleared to stop the current run. Each start gets a fresh flag so a run
+    /// that is still winding down can't carry on after a restart.
+    running: Mutex<Arc<AtomicBool>>,
+    backend: EncoderBackend,
+    /// Most recent pipeline error
+    last_error: Mutex<Option<Arc<DartError>>>,
+    /// Number of times the pipeline has dropped and gone back to reconnecting,
+    /// once the source has been live; failed first connects don't count
+    reconnect_count: AtomicU64,
+    /// Number of times the run loop panicked and was restarted
+    restart_count: AtomicU64,
+    /// When the current pipeline delivered its first frame
+    connected_since: Mutex<Option<Instant>>,
+    /// When the source first delivered a frame; kept across reconnects
+    started_at: Mutex<Option<Instant>>,
+    /// When the source last switched to fallback
+    fallback_since: Mutex<Option<Instant>>,
+    /// Most recent video keyframe, for snapshots
+    last_keyframe: KeyframeCache,
+    /// When the current pipeline last produced a video frame
+    last_frame: FrameClock,
+    /// The pipeline while it is running, for live property changes
+    pipeline: Mutex<Option<gstreamer::Pipeline>>,
+    /// Bitrate (kbps) set at runtime; replaces the configured one on reconnect
+    bitrate_override: Mutex<Option<u32>>,
+    /// Release the pipeline after this long without clients
+    idle_release: Option<Duration>,
+    /// Pause the pipeline while nobody watches
+    idle_pause: bool,
+}
+
+/// Latest encoded video keyframe, shared with the appsink callback
+pub(crate) type KeyframeCache = Arc<Mutex<Option<gstreamer::Buffer>>>;
+
+/// Arrival time of the latest video frame, shared with the appsink callback
+type FrameClock = Arc<Mutex<Option<Instant>>>;
+
+/// Run once, with its arrival time, on the first video frame of a pipeline run
+type FirstFrame = Box<dyn FnOnce(Instant) + Send>;
+
+/// What only the main video appsink keeps track of
+struct MainSink {
+    /// Latest keyframe, for snapshots
+    last_keyframe: KeyframeCache,
+    /// Taken by the first video frame
+    first_frame: Mutex<Option<FirstFrame>>,
+}
+
+/// Decides when a source nobody is watching should release its pipeline.
+/// The countdown starts at the first check without a client and resets
+/// whenever one is seen.
+struct IdleTimer {
+    timeout: Option<Duration>,
+    idle_since: Option<Instant>,
+}
+
+impl IdleTimer {
+    /// Timer releasing after `timeout` unwatched; `None` never releases
+    fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            timeout,
+            idle_since: None,
+        }
+    }
+
+    /// Record whether anyone is watching as of `now`. True once nobody has
+    /// been for the whole timeout.
+    fn is_idle(&mut self, watched: bool, now: Instant) -> bool {
+        let Some(timeout) = self.timeout else {
+            return false;
+        };
+        if watched {
+            self.idle_since = None;
+            return false;
+        }
+        let since = *self.idle_since.get_or_insert(now);
+        now.saturating_duration_since(since) >= timeout
+    }
+}
+
+/// Pauses a running pipeline while nobody watches and resumes it for the
+/// first client. Unlike idle release the device stays open, so resuming
+/// takes one keyframe rather than a full restart.
+struct IdlePause {
+    enabled: bool,
+    paused: bool,
+}
+
+impl IdlePause {
+    fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            paused: false,
+        }
+    }
+
+    /// The state the pipeline should move to now that `watched` is known,
+    /// if it should change
+    fn transition(&mut self, watched: bool) -> Option<gstreamer::State> {
+        match (self.paused, watched) {
+            (false, false) if self.enabled => {
+                self.paused = true;
+                Some(gstreamer::State::Paused)
+            }
+            (true, true) => {
+                self.paused = false;
+                Some(gstreamer::State::Playing)
+            }
+            _ => None,
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused
+    }
+}
+
+/// Spots a pipeline that is still connected but has stopped delivering
+/// frames. The grace period starts when the pipeline does, so a slow first
+/// frame counts too.
+struct FreezeWatchdog {
+    timeout: Option<Duration>,
+    started: Instant,
+}
+
+impl FreezeWatchdog {
+    /// Watchdog for a pipeline started at `started`; a zero timeout disables it
+    fn new(timeout_secs: u64, started: Instant) -> Self {
+        Self {
+            timeout: (timeout_secs > 0).then(|| Duration::from_secs(timeout_secs)),
+            started,
+        }
+    }
+
+    /// Whether nothing has arrived for the whole timeout as of `now`
+    fn is_frozen(&self, last_frame: Option<Instant>, now: Instant) -> bool {
+        let Some(timeout) = self.timeout else {
+            return false;
+        };
+        let since = last_frame.map_or(self.started, |t| t.max(self.started));
+        now.saturating_duration_since(since) >= timeout
+    }
 }
 
 impl Source {
     /// Create a new source from configuration
     pub fn new(
         config: SourceConfig,
-        frame_tx: Arc<Mutex<Option<FrameSender>>>,
-        fallback: Option<FallbackFrame>,
-        mpp: bool,
+        frame_tx: Arc<FrameBus>,
+        fallback: Option<FallbackSource>,
+        backend: EncoderBackend,
     ) -> Result<Self> {
         Ok(Self {
             name: config.name.clone(),
             config,
             frame_tx,
+            renditions: Vec::new(),
+            compat: None,
             fallback,
             state: Arc::new(Mutex::new(SourceState::Stopped)),
-            running: Arc::new(AtomicBool::new(false)),
-            mpp,
+            running: Mutex::new(Arc::new(AtomicBool::new(false))),
+            backend,
+            last_error: Mutex::new(None),
+            reconnect_count: AtomicU64::new(0),
+            restart_count: AtomicU64::new(0),
+            connected_since: Mutex::new(None),
+            started_at: Mutex::new(None),
+            fallback_since: Mutex::new(None),
+            last_keyframe: Arc::new(Mutex::new(None)),
+            last_frame: Arc::new(Mutex::new(None)),
+            pipeline: Mutex::new(None),
+            bitrate_override: Mutex::new(None),
+            idle_release: None,
+            idle_pause: false,
         })
     }
 
+    /// Feed each rendition's appsink into its own frame bus
+    pub fn with_renditions(mut self, renditions: Vec<(String, Arc<FrameBus>)>) -> Self {
+        self.renditions = renditions;
+        self
+    }
+
+    /// Count the H.264 compatibility mount's clients as viewers of this source
+    pub fn with_compat(mut self, compat: Option<Arc<FrameBus>>) -> Self {
+        self.compat = compat;
+        self
+    }
+
+    /// Release the pipeline after `secs` seconds without RTSP clients (0
+    /// keeps it running). Sources with HLS, recording or SRT output always
+    /// run, since those need frames regardless.
+    pub fn with_idle_release(mut self, secs: u64) -> Self {
+        if secs > 0 && self.config.has_continuous_outputs() {
+            info!(
+                "Source '{}' has outputs that run without clients; idle release disabled",
+                self.name
+            );
+        } else if secs > 0 {
+            self.idle_release = Some(Duration::from_secs(secs));
+        }
+        self
+    }
+
+    /// Pause the pipeline whenever it has no clients, resuming with a fresh
+    /// keyframe for the first one. Skipped for the same sources as idle release.
+    pub fn with_idle_pause(mut self, enabled: bool) -> Self {
+        if enabled && self.config.has_continuous_outputs() {
+            info!(
+                "Source '{}' has outputs that run without clients; idle pause disabled",
+                self.name
+            );
+        } else {
+            self.idle_pause = enabled;
+        }
+        self
+    }
+
     /// Start the source with automatic reconnection
     pub fn start(self: Arc<Self>) -> Result<()> {
-        self.running.store(true, Ordering::SeqCst);
+        let running = Arc::new(AtomicBool::new(true));
+        {
+            let mut current = self.running.lock().unwrap();
+            if current.load(Ordering::SeqCst) {
+                anyhow::bail!("Source '{}' is already running", self.name);
+            }
+            *current = Arc::clone(&running);
+        }
         *self.state.lock().unwrap() = SourceState::Live;
 
         let source = Arc::clone(&self);
+        let span = self.span();
         std::thread::spawn(move || {
-            source.run_loop();
+            span.in_scope(|| {
+                supervise(
+                    &source.name,
+                    &running,
+                    &source.restart_count,
+                    PANIC_RESTART_DELAY,
+                    || {
+                        // A run that panicked may have left its pipeline playing
+                        if let Some(pipeline) = source.pipeline.lock().unwrap().take() {
+                            let _ = pipeline.set_state(gstreamer::State::Null);
+                        }
+                        source.run_loop(&running)
+                    },
+                )
+            });
         });
 
         info!("Started source: {}", self.name);
         Ok(())
     }
 
-    /// Main run loop with reconnection logic
-    fn run_loop(&self) {
-        // Fast poll interval for recovery (2 seconds)
-        const FAST_POLL_INTERVAL: Duration = Duration::from_secs(2);
+    /// Span that tags everything logged on this source's behalf
+    pub fn span(&self) -> tracing::Span {
+        info_span!(logging::SOURCE_SPAN, source = %self.name)
+    }
 
-        while self.running.load(Ordering::SeqCst) {
+    /// Main run loop with reconnection logic
+    fn run_loop(self: &Arc<Self>, running: &Arc<AtomicBool>) {
+        // Counts failed probes across runs, so a camera that accepts the
+        // probe but fails the pipeline still backs off
+        let mut attempt = 0;
+        while running.load(Ordering::SeqCst) {
             // Try to create and run the pipeline
-            match self.create_and_run_pipeline() {
-                Ok(()) => {
-                    // Pipeline ended normally (EOS) - try to reconnect
-                    if !self.running.load(Ordering::SeqCst) {
-                        break;
-                    }
-                    info!("Source '{}' ended, will reconnect", self.name);
-                }
-                Err(e) => {
-                    error!("Source '{}' error: {}", self.name, e);
-                }
+            let started = Instant::now();
+            let result = self.create_and_run_pipeline(running);
+            *self.pipeline.lock().unwrap() = None;
+            if result.is_ok() && !running.load(Ordering::SeqCst) {
+                break;
+            }
+            if result.is_ok() && self.state() == SourceState::Idle {
+                self.wait_for_viewer(running);
+                continue;
+            }
+            self.record_disconnect(result);
+
+            // A run that delivered frames was a successful connect
+            if self
+                .last_frame
+                .lock()
+                .unwrap()
+                .is_some_and(|t| t >= started)
+            {
+                attempt = 0;
             }
 
-            // Switch to fallback mode (only for RTSP sources)
-            // V4L2 devices just log error and retry
-            if self.config.source_type == SourceType::Rtsp && self.fallback.is_some() {
-                *self.state.lock().unwrap() = SourceState::Fallback;
-                info!("Source '{}' switched to fallback mode", self.name);
-
+            // Switch to fallback mode if we have a fallback image;
+            // otherwise just retry
+            if self.enter_fallback() {
                 // Start fallback frame sender
-                self.start_fallback_sender();
+                self.start_fallback_sender(Arc::clone(running));
             } else if self.config.source_type == SourceType::V4l2 {
                 warn!("Source '{}': V4L2 device not available, retrying...", self.name);
             }
 
-            // Fast polling loop - try to reconnect quickly
+            // Polling loop - first probe is quick, then back off from
+            // reconnect_interval up to reconnect_max_secs
             loop {
-                if !self.running.load(Ordering::SeqCst) {
+                if !running.load(Ordering::SeqCst) {
                     break;
                 }
 
+                let delay = self.reconnect_delay(attempt);
+                attempt += 1;
                 debug!(
                     "Source '{}' checking connectivity in {:?}...",
-                    self.name, FAST_POLL_INTERVAL
+                    self.name, delay
                 );
-                std::thread::sleep(FAST_POLL_INTERVAL);
+                std::thread::sleep(delay);
 
                 // Quick probe to check if source is available
                 if self.probe_source() {
@@ -124,171 +487,206 @@ impl Source {
             }
         }
 
-        *self.state.lock().unwrap() = SourceState::Stopped;
+        // A restart may already have handed the state to a new run
+        if Arc::ptr_eq(&self.running.lock().unwrap(), running) {
+            *self.state.lock().unwrap() = SourceState::Stopped;
+        }
         debug!("Source '{}' run loop ended", self.name);
     }
 
-    /// Quick probe to check if source is available without starting full pipeline
-    fn probe_source(&self) -> bool {
-        match self.config.source_type {
-            SourceType::Rtsp => self.probe_rtsp(),
-            SourceType::V4l2 => self.probe_v4l2(),
-        }
+    /// RTSP and WebRTC clients playing the main, a rendition or the
+    /// compatibility mount
+    pub fn viewers(&self) -> usize {
+        std::iter::once(&self.frame_tx)
+            .chain(self.renditions.iter().map(|(_, bus)| bus))
+            .chain(&self.compat)
+            .map(|bus| bus.viewers())
+            .sum()
     }
 
-    /// Probe RTSP source by attempting a quick connection
-    fn probe_rtsp(&self) -> bool {
-        let url = match &self.config.url {
-            Some(u) => u,
-            None => return false,
-        };
+    /// Ask the encoder for a keyframe on the main and every rendition mount
+    fn request_keyframes(&self) {
+        std::iter::once(&self.frame_tx)
+            .chain(self.renditions.iter().map(|(_, bus)| bus))
+            .for_each(|bus| bus.request_keyframe());
+    }
 
-        // Try to create a minimal pipeline just to test connectivity
-        // Use a short timeout (2 seconds)
-        let mut pipeline_str = format!(
-            "rtspsrc location=\"{}\" latency=0 timeout=2000000 ! fakesink",
-            url
-        );
+    /// Whether any client is connected to the main or a rendition mount
+    fn is_watched(&self) -> bool {
+        self.viewers() > 0
+    }
 
-        if let Some(user) = &self.config.username {
-            pipeline_str = format!(
-                "rtspsrc location=\"{}\" latency=0 timeout=2000000 user-id=\"{}\"",
-                url, user
-            );
-            if let Some(pass) = &self.config.password {
-                pipeline_str.push_str(&format!(" user-pw=\"{}\"", pass));
+    /// Sit idle until a client connects or the source is stopped
+    fn wait_for_viewer(&self, running: &AtomicBool) {
+        while running.load(Ordering::SeqCst) {
+            if self.is_watched() {
+                info!(
+                    "Source '{}' has a client, restarting its pipeline",
+                    self.name
+                );
+                return;
             }
-            pipeline_str.push_str(" ! fakesink");
+            std::thread::sleep(IDLE_POLL_INTERVAL);
         }
+    }
 
-        let pipeline = match gstreamer::parse::launch(&pipeline_str) {
-            Ok(p) => p,
-            Err(_) => return false,
-        };
-
-        // Try to set to PAUSED (will attempt connection)
-        let result = pipeline.set_state(gstreamer::State::Paused);
-
-        // Wait briefly for state change
-        if result.is_ok() {
-            let bus = pipeline.bus();
-            if let Some(bus) = bus {
-                // Wait up to 2 seconds for state change or error
-                for _ in 0..20 {
-                    if let Some(msg) = bus.timed_pop(gstreamer::ClockTime::from_mseconds(100)) {
-                        match msg.view() {
-                            gstreamer::MessageView::Error(_) => {
-                                pipeline.set_state(gstreamer::State::Null).ok();
-                                return false;
-                            }
-                            gstreamer::MessageView::StateChanged(state) => {
-                                if state.current() == gstreamer::State::Paused {
-                                    pipeline.set_state(gstreamer::State::Null).ok();
-                                    return true;
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
-                }
+    /// Account for a pipeline run that ended (EOS) or failed; a reconnect follows
+    fn record_disconnect(&self, result: Result<(), DartError>) {
+        match result {
+            // Pipeline ended normally (EOS) - try to reconnect
+            Ok(()) => info!("Source '{}' ended, will reconnect", self.name),
+            Err(e) => {
+                error!("Source '{}' error: {:#}", self.name, e);
+                *self.last_error.lock().unwrap() = Some(Arc::new(e));
             }
         }
 
-        pipeline.set_state(gstreamer::State::Null).ok();
-        false
+        *self.connected_since.lock().unwrap() = None;
+        if self.started_at().is_some() {
+            self.reconnect_count.fetch_add(1, Ordering::SeqCst);
+        }
     }
 
-    /// Probe V4L2 device by trying to negotiate caps
-    fn probe_v4l2(&self) -> bool {
-        let device = match &self.config.device {
-            Some(d) => d,
-            None => return false,
-        };
-
-        // First check if device exists
-        if !std::path::Path::new(device).exists() {
+    /// Switch to fallback if a fallback frame is configured. Returns whether it did.
+    fn enter_fallback(&self) -> bool {
+        if self.fallback.is_none() {
             return false;
         }
 
-        // Try to create a minimal pipeline to test if we can negotiate caps
-        // This will fail if there's no signal (for capture cards like TC358743)
-        let caps = if let Some(format) = &self.config.format {
-            let mut parts = vec![format!("format={}", format)];
-            if let Some(w) = self.config.width {
-                parts.push(format!("width={}", w));
-            }
-            if let Some(h) = self.config.height {
-                parts.push(format!("height={}", h));
-            }
-            parts.push("colorimetry=bt601".to_string());
-            format!(" ! video/x-raw,{}", parts.join(","))
-        } else {
-            String::new()
-        };
-
-        let pipeline_str = format!(
-            "v4l2src device={}{} ! fakesink",
-            device, caps
-        );
+        *self.state.lock().unwrap() = SourceState::Fallback;
+        *self.fallback_since.lock().unwrap() = Some(Instant::now());
+        info!("Source '{}' switched to fallback mode", self.name);
+        true
+    }
 
-        let pipeline = match gstreamer::parse::launch(&pipeline_str) {
-            Ok(p) => p,
-            Err(_) => return false,
-        };
+    /// Note that a pipeline connected at `now`
+    fn mark_connected(&self, now: Instant) {
+        *self.connected_since.lock().unwrap() = Some(now);
+        self.started_at.lock().unwrap().get_or_insert(now);
+    }
 
-        // Try to set to PAUSED - this will attempt to negotiate caps
-        let result = pipeline.set_state(gstreamer::State::Paused);
-        if result.is_err() {
-            pipeline.set_state(gstreamer::State::Null).ok();
-            return false;
+    /// A pipeline run delivered its first video frame at `now`: go live, and
+    /// start counting uptime
+    fn first_frame(&self, now: Instant) {
+        // Stopped while the pipeline was starting
+        if !self.is_running() {
+            return;
         }
 
-        // Wait for state change or error
-        if let Some(bus) = pipeline.bus() {
-            for _ in 0..20 {
-                if let Some(msg) = bus.timed_pop(gstreamer::ClockTime::from_mseconds(100)) {
-                    match msg.view() {
-                        gstreamer::MessageView::Error(_) => {
-                            pipeline.set_state(gstreamer::State::Null).ok();
-                            return false;
-                        }
-                        gstreamer::MessageView::StateChanged(state) => {
-                            if state.current() == gstreamer::State::Paused {
-                                pipeline.set_state(gstreamer::State::Null).ok();
-                                return true;
-                            }
-                        }
-                        _ => {}
-                    }
-                }
+        if self.go_live() {
+            info!("Source '{}' recovered from fallback", self.name);
+            if let Some(snapshot) = &self.config.snapshot_on_recover {
+                let frames = self.frame_tx.subscribe();
+                self.frame_tx.request_keyframe();
+                crate::snapshot::save_on_recover(
+                    &self.name,
+                    &snapshot.dir,
+                    self.output_codec(),
+                    frames,
+                    Arc::clone(&self.last_keyframe),
+                );
             }
         }
+        self.mark_connected(now);
+        *self.fallback_since.lock().unwrap() = None;
+    }
 
-        pipeline.set_state(gstreamer::State::Null).ok();
-        false
+    /// Switch to live. Returns whether the source was in fallback until now.
+    fn go_live(&self) -> bool {
+        let previous = std::mem::replace(&mut *self.state.lock().unwrap(), SourceState::Live);
+        previous == SourceState::Fallback
+    }
+
+    /// Delay before reconnect probe number `attempt` (0-based) after a failure.
+    ///
+    /// The first probe waits `FAST_POLL_INTERVAL` (or `reconnect_interval` if that
+    /// is shorter) so brief drops recover quickly; later probes wait
+    /// `reconnect_interval`, doubling each time up to `reconnect_max_secs`.
+    /// `reconnect_jitter` then takes a random share off. A probe never happens
+    /// immediately after a failure.
+    fn reconnect_delay(&self, attempt: u32) -> Duration {
+        let interval = Duration::from_secs(self.config.reconnect_interval);
+        let delay = if attempt == 0 {
+            FAST_POLL_INTERVAL.min(interval)
+        } else {
+            let doublings = 2u32.saturating_pow(attempt - 1);
+            interval
+                .saturating_mul(doublings)
+                .min(self.config.reconnect_max())
+        };
+        with_jitter(delay, self.config.reconnect_jitter, random_fraction())
+    }
+
+    /// Quick probe to check if source is available without starting full pipeline
+    fn probe_source(&self) -> bool {
+        probe(&self.config, PROBE_TIMEOUT).unwrap_or(true)
     }
 
     /// Create and run the pipeline, returns when pipeline ends or errors
-    fn create_and_run_pipeline(&self) -> Result<()> {
+    fn create_and_run_pipeline(self: &Arc<Self>, running: &AtomicBool) -> Result<(), DartError> {
+        let pipeline_str = pipeline_string(&self.config, self.backend)?;
+        debug!("Pipeline: {}", logging::redact_pipeline(&pipeline_str));
         let pipeline = match self.config.source_type {
-            SourceType::V4l2 => v4l2::create_pipeline(&self.config, self.mpp)?,
-            SourceType::Rtsp => rtsp::create_pipeline(&self.config, self.mpp)?,
+            SourceType::Rtsp => rtsp::build_pipeline(&self.config, self.backend)?,
+            _ => launch_pipeline(&pipeline_str)?,
         };
+        watch_clamp(&self.name, &pipeline);
 
         // Set up appsink callbacks
         let frame_tx = Arc::clone(&self.frame_tx);
-        let name = self.name.clone();
         let state = Arc::clone(&self.state);
+        let last_keyframe = Arc::clone(&self.last_keyframe);
+        let last_frame = Arc::clone(&self.last_frame);
+
+        // The source is up once frames flow, not when the pipeline reaches PLAYING
+        let source = Arc::downgrade(self);
+        let first_frame: FirstFrame = Box::new(move |now| {
+            if let Some(source) = source.upgrade() {
+                source.first_frame(now);
+            }
+        });
+
+        // Passthrough has no encoder to ask for keyframes
+        let force_keyframes = self.config.encodes();
+        setup_appsink_callbacks(
+            &pipeline,
+            frame_tx,
+            state,
+            last_keyframe,
+            last_frame,
+            force_keyframes,
+            first_frame,
+        )?;
+        for (rendition, bus) in &self.renditions {
+            connect_rendition_appsink(
+                &pipeline,
+                rendition,
+                Arc::clone(bus),
+                Arc::clone(&self.state),
+                Arc::clone(&self.last_frame),
+            )?;
+        }
+
+        if let Some(index) = self.config.stream_index {
+            rtsp::select_video_track(&self.name, &pipeline, index)?;
+        }
 
-        setup_appsink_callbacks(&pipeline, &name, frame_tx, state)?;
+        // Keep a bitrate changed at runtime across reconnects
+        if let Some(kbps) = *self.bitrate_override.lock().unwrap() {
+            set_encoder_bitrate(&pipeline, self.backend, kbps)?;
+        }
 
         // Start pipeline
         pipeline
             .set_state(gstreamer::State::Playing)
             .map_err(|e| anyhow::anyhow!("Failed to start pipeline: {:?}", e))?;
-
-        *self.state.lock().unwrap() = SourceState::Live;
+        *self.pipeline.lock().unwrap() = Some(pipeline.clone());
         info!("Source '{}' pipeline started", self.name);
+        let pipeline_object = pipeline.clone().upcast::<gstreamer::Object>();
+
+        let mut watchdog = FreezeWatchdog::new(self.config.freeze_timeout_secs, Instant::now());
+        let mut idle = IdleTimer::new(self.idle_release);
+        let mut pause = IdlePause::new(self.idle_pause);
 
         // Wait for pipeline to end or error
         let bus = pipeline
@@ -296,19 +694,30 @@ impl Source {
             .ok_or_else(|| anyhow::anyhow!("No bus on pipeline"))?;
 
         loop {
-            if !self.running.load(Ordering::SeqCst) {
-                break;
+            if !running.load(Ordering::SeqCst) {
+                // Intentional stop: let the last GOP drain before tearing down
+                if !shutdown(&pipeline, EOS_TIMEOUT) {
+                    warn!(
+                        "Source '{}' did not drain within {:?}, forcing stop",
+                        self.name, EOS_TIMEOUT
+                    );
+                }
+                return Ok(());
             }
 
             // Poll bus with timeout
             if let Some(msg) = bus.timed_pop(gstreamer::ClockTime::from_mseconds(500)) {
                 match msg.view() {
                     gstreamer::MessageView::Error(err) => {
+                        // Graph as negotiation left it, before teardown
+                        dump_dot(&pipeline, &format!("{}-error", self.name));
                         pipeline.set_state(gstreamer::State::Null).ok();
-                        return Err(anyhow::anyhow!(
-                            "Pipeline error: {} ({:?})",
-                            err.error(),
-                            err.debug()
+                        let debug = err.debug().map(|d| logging::redact_pipeline(&d));
+                        let error = err.error().to_string();
+                        return Err(DartError::from_bus_error(
+                            &self.config,
+                            &error,
+                            debug.as_deref(),
                         ));
                     }
                     gstreamer::MessageView::Eos(_) => {
@@ -320,12 +729,64 @@ impl Source {
                             "Source '{}' warning: {} ({:?})",
                             self.name,
                             warn.error(),
-                            warn.debug()
+                            warn.debug().map(|d| logging::redact_pipeline(&d))
                         );
                     }
+                    gstreamer::MessageView::StateChanged(change)
+                        if msg.src() == Some(&pipeline_object)
+                            && change.current() == gstreamer::State::Playing =>
+                    {
+                        dump_dot(&pipeline, &self.name);
+                    }
                     _ => {}
                 }
             }
+
+            // Nobody watching: stop capturing, but keep the device open
+            match pause.transition(self.is_watched()) {
+                Some(gstreamer::State::Paused) => {
+                    info!(
+                        "Source '{}' has no clients, pausing its pipeline",
+                        self.name
+                    );
+                    *self.state.lock().unwrap() = SourceState::Paused;
+                    pipeline.set_state(gstreamer::State::Paused).ok();
+                }
+                Some(_) => {
+                    info!("Source '{}' has a client, resuming its pipeline", self.name);
+                    pipeline
+                        .set_state(gstreamer::State::Playing)
+                        .map_err(|e| anyhow::anyhow!("Failed to resume pipeline: {:?}", e))?;
+                    *self.state.lock().unwrap() = SourceState::Live;
+                    // The encoder's GOP carried on from before the pause
+                    self.request_keyframes();
+                    watchdog = FreezeWatchdog::new(self.config.freeze_timeout_secs, Instant::now());
+                }
+                None => {}
+            }
+
+            // Connected but silent: treat like an error and reconnect
+            if !pause.is_paused()
+                && watchdog.is_frozen(*self.last_frame.lock().unwrap(), Instant::now())
+            {
+                pipeline.set_state(gstreamer::State::Null).ok();
+                return Err(DartError::Timeout {
+                    secs: self.config.freeze_timeout_secs,
+                });
+            }
+
+            // Nobody watching: free the device and encoder until someone is
+            if idle.is_idle(self.is_watched(), Instant::now()) {
+                info!(
+                    "Source '{}' has had no clients for {:?}, releasing its pipeline",
+                    self.name,
+                    self.idle_release.unwrap_or_default()
+                );
+                *self.state.lock().unwrap() = SourceState::Idle;
+                *self.connected_since.lock().unwrap() = None;
+                shutdown(&pipeline, EOS_TIMEOUT);
+                return Ok(());
+            }
         }
 
         pipeline.set_state(gstreamer::State::Null).ok();
@@ -333,43 +794,49 @@ impl Source {
     }
 
     /// Send fallback frames while in fallback state
-    fn start_fallback_sender(&self) {
+    fn start_fallback_sender(&self, running: Arc<AtomicBool>) {
         let fallback = match &self.fallback {
             Some(f) => f.clone(),
             None => return,
         };
 
-        let frame_tx = Arc::clone(&self.frame_tx);
+        // Renditions show the same fallback frames as the main stream
+        let buses: Vec<_> = std::iter::once(&self.frame_tx)
+            .chain(self.renditions.iter().map(|(_, bus)| bus))
+            .cloned()
+            .collect();
         let state = Arc::clone(&self.state);
-        let running = Arc::clone(&self.running);
         let name = self.name.clone();
 
-        // Send fallback frames at ~1 fps while in fallback state
+        // Cycle through the fallback frames (`fallback_fps` for a still image,
+        // the clip's own rate for a clip, each frame's delay for a GIF) while
+        // in fallback state. Timestamps are cleared and restamped by each
+        // mount's appsrc, so they stay monotonic across resends.
+        let still_interval = self.config.fallback_interval();
+        let span = tracing::Span::current();
         std::thread::spawn(move || {
+            let _span = span.enter();
             debug!("Fallback sender started for '{}'", name);
-            let frame_interval = Duration::from_secs(1);
-
-            while running.load(Ordering::SeqCst) {
+            for (index, fallback_frame) in fallback.frames().iter().enumerate().cycle() {
                 // Check if we're still in fallback state
-                if *state.lock().unwrap() != SourceState::Fallback {
+                if !running.load(Ordering::SeqCst)
+                    || *state.lock().unwrap() != SourceState::Fallback
+                {
                     break;
                 }
 
                 // Send fallback frame
-                let frame = FrameData {
-                    data: fallback.data().to_vec(),
-                    is_keyframe: true,
-                };
-
-                if let Ok(guard) = frame_tx.lock() {
-                    if let Some(tx) = guard.as_ref() {
-                        if tx.send(frame).is_err() {
-                            debug!("Fallback sender '{}': receiver disconnected", name);
-                        }
-                    }
+                let frame = FrameData::new(
+                    fallback_frame.buffer.clone(),
+                    fallback_frame.is_keyframe,
+                    StreamKind::Video,
+                );
+
+                for bus in &buses {
+                    bus.send(frame.clone());
                 }
 
-                std::thread::sleep(frame_interval);
+                std::thread::sleep(fallback.frame_delay(index, still_interval));
             }
 
             debug!("Fallback sender ended for '{}'", name);
@@ -378,11 +845,16 @@ impl Source {
 
     /// Stop the source
     pub fn stop(&self) {
-        self.running.store(false, Ordering::SeqCst);
+        self.running.lock().unwrap().store(false, Ordering::SeqCst);
         *self.state.lock().unwrap() = SourceState::Stopped;
         info!("Stopped source: {}", self.name);
     }
 
+    /// Whether the source has been started and not stopped since
+    pub fn is_running(&self) -> bool {
+        self.running.lock().unwrap().load(Ordering::SeqCst)
+    }
+
     /// Get source name
     pub fn name(&self) -> &str {
         &self.name
@@ -392,14 +864,196 @@ impl Source {
     pub fn state(&self) -> SourceState {
         *self.state.lock().unwrap()
     }
+
+    /// Configuration the source was started with
+    pub fn config(&self) -> &SourceConfig {
+        &self.config
+    }
+
+    /// Get source type
+    pub fn source_type(&self) -> SourceType {
+        self.config.source_type
+    }
+
+    /// Most recent pipeline error, if any
+    pub fn last_error(&self) -> Option<String> {
+        self.last_failure().map(|e| format!("{:#}", e))
+    }
+
+    /// Most recent pipeline error, for matching on its kind
+    pub fn last_failure(&self) -> Option<Arc<DartError>> {
+        self.last_error.lock().unwrap().clone()
+    }
+
+    /// Number of times the source has dropped and reconnected
+    pub fn reconnect_count(&self) -> u64 {
+        self.reconnect_count.load(Ordering::SeqCst)
+    }
+
+    /// Number of times the source's run loop panicked and was restarted
+    pub fn restart_count(&self) -> u64 {
+        self.restart_count.load(Ordering::SeqCst)
+    }
+
+    /// When the current pipeline connected, if it is connected
+    pub fn connected_since(&self) -> Option<Instant> {
+        *self.connected_since.lock().unwrap()
+    }
+
+    /// When the source first went live, if it has
+    pub fn started_at(&self) -> Option<Instant> {
+        *self.started_at.lock().unwrap()
+    }
+
+    /// Time since the current pipeline went live, or since the source first
+    /// did with `uptime_across_reconnects`. None while it isn't connected.
+    pub fn uptime(&self) -> Option<Duration> {
+        self.uptime_at(Instant::now())
+    }
+
+    fn uptime_at(&self, now: Instant) -> Option<Duration> {
+        let connected = self.connected_since()?;
+        if self.config.uptime_across_reconnects {
+            return Some(now.saturating_duration_since(self.started_at().unwrap_or(connected)));
+        }
+        Some(now.saturating_duration_since(connected))
+    }
+
+    /// Time spent in fallback, if currently in fallback
+    pub fn fallback_duration(&self) -> Option<Duration> {
+        if self.state() != SourceState::Fallback {
+            return None;
+        }
+        self.fallback_since.lock().unwrap().map(|t| t.elapsed())
+    }
+
+    /// Most recent live video keyframe, if one has been received
+    pub fn last_keyframe(&self) -> Option<gstreamer::Buffer> {
+        self.last_keyframe.lock().unwrap().clone()
+    }
+
+    /// Codec of the frames this source pushes to its mount
+    pub fn output_codec(&self) -> OutputCodec {
+        self.config.output_codec(self.backend)
+    }
+
+    /// Bus carrying the main output's frames, for outputs attached on demand
+    pub fn frame_bus(&self) -> Arc<FrameBus> {
+        Arc::clone(&self.frame_tx)
+    }
+
+    /// Change the main output's bitrate (kbps) without restarting the
+    /// pipeline, so connected clients stay connected. Only sources that
+    /// encode have a bitrate to change; renditions keep theirs.
+    pub fn set_bitrate(&self, kbps: u32) -> Result<()> {
+        if !self.config.encodes() {
+            anyhow::bail!("Source '{}' is passthrough and has no encoder", self.name);
+        }
+        // Same bounds as a configured bitrate
+        EncodeConfig {
+            bitrate: kbps,
+            ..self.config.encode_config()
+        }
+        .validate(&self.name)?;
+
+        if let Some(pipeline) = self.pipeline.lock().unwrap().as_ref() {
+            set_encoder_bitrate(pipeline, self.backend, kbps)?;
+        }
+        *self.bitrate_override.lock().unwrap() = Some(kbps);
+        info!("Source '{}' bitrate set to {} kbps", self.name, kbps);
+        Ok(())
+    }
+}
+
+/// Set the bitrate of a pipeline's main encoder while it runs
+fn set_encoder_bitrate(
+    pipeline: &gstreamer::Pipeline,
+    backend: EncoderBackend,
+    kbps: u32,
+) -> Result<()> {
+    let encoder = pipeline
+        .by_name(ENCODER_NAME)
+        .ok_or_else(|| anyhow::anyhow!("Pipeline missing '{}' element", ENCODER_NAME))?;
+
+    // mpph265enc takes bps; x264enc and the VAAPI encoders take kbps
+    let (property, value) = match backend {
+        EncoderBackend::Mpp => ("bps", u64::from(kbps) * 1000),
+        EncoderBackend::Vaapi(_) | EncoderBackend::X264 => ("bitrate", u64::from(kbps)),
+    };
+    if encoder.find_property(property).is_none() {
+        anyhow::bail!("Encoder has no '{}' property", property);
+    }
+    encoder.set_property_from_str(property, &value.to_string());
+    Ok(())
+}
+
+/// Call `run` on a source's thread until it returns, restarting it after
+/// `delay` whenever it panics, unless the source was stopped meanwhile.
+/// Each restart is counted in `restarts`.
+fn supervise(
+    name: &str,
+    running: &AtomicBool,
+    restarts: &AtomicU64,
+    delay: Duration,
+    mut run: impl FnMut(),
+) {
+    loop {
+        let Err(panic) = std::panic::catch_unwind(std::panic::AssertUnwindSafe(&mut run)) else {
+            return;
+        };
+        let message = panic
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown panic");
+        if !running.load(Ordering::SeqCst) {
+            error!(
+                "Source '{}' run loop panicked while stopping: {}",
+                name, message
+            );
+            return;
+        }
+        let count = restarts.fetch_add(1, Ordering::SeqCst) + 1;
+        error!(
+            "Source '{}' run loop panicked: {}; restarting (restart {})",
+            name, message, count
+        );
+        std::thread::sleep(delay);
+    }
+}
+
+/// Stop a pipeline cleanly: send EOS so parsers, muxers and sinks can finish
+/// what they hold, wait up to `timeout` for it to reach the bus, then go to
+/// Null regardless. Returns whether the EOS arrived in time.
+pub fn shutdown(pipeline: &gstreamer::Pipeline, timeout: Duration) -> bool {
+    // Sending EOS into a source whose streaming thread is stuck can block, so
+    // do it off-thread; going to Null below unblocks it
+    let eos_target = pipeline.clone();
+    std::thread::spawn(move || {
+        eos_target.send_event(gstreamer::event::Eos::new());
+    });
+
+    let drained = pipeline.bus().is_some_and(|bus| {
+        bus.timed_pop_filtered(
+            gstreamer::ClockTime::from_mseconds(timeout.as_millis() as u64),
+            &[gstreamer::MessageType::Eos, gstreamer::MessageType::Error],
+        )
+        .is_some_and(|msg| msg.type_() == gstreamer::MessageType::Eos)
+    });
+
+    pipeline.set_state(gstreamer::State::Null).ok();
+    drained
 }
 
 /// Set up appsink callbacks to receive frames
 fn setup_appsink_callbacks(
     pipeline: &gstreamer::Pipeline,
-    name: &str,
-    frame_tx: Arc<Mutex<Option<FrameSender>>>,
+    frame_tx: Arc<FrameBus>,
     state: Arc<Mutex<SourceState>>,
+    last_keyframe: KeyframeCache,
+    last_frame: FrameClock,
+    force_keyframes: bool,
+    first_frame: FirstFrame,
 ) -> Result<()> {
     let sink = pipeline
         .by_name("sink")
@@ -409,66 +1063,685 @@ fn setup_appsink_callbacks(
         .dynamic_cast::<AppSink>()
         .map_err(|_| anyhow::anyhow!("Failed to cast to AppSink"))?;
 
-    let name = name.to_string();
-
-    appsink.set_callbacks(
-        gstreamer_app::AppSinkCallbacks::builder()
-            .new_sample(move |sink| {
-                // Only send frames when in Live state
-                if *state.lock().unwrap() != SourceState::Live {
-                    return Ok(gstreamer::FlowSuccess::Ok);
-                }
+    connect_appsink(
+        &appsink,
+        StreamKind::Video,
+        Arc::clone(&frame_tx),
+        Arc::clone(&state),
+        Some(MainSink {
+            last_keyframe,
+            first_frame: Mutex::new(Some(first_frame)),
+        }),
+        Arc::clone(&last_frame),
+        force_keyframes,
+    );
 
-                let sample = sink.pull_sample().map_err(|_| gstreamer::FlowError::Eos)?;
-                let buffer = sample.buffer().ok_or(gstreamer::FlowError::Error)?;
-                let map = buffer.map_readable().map_err(|_| gstreamer::FlowError::Error)?;
+    // Optional audio branch (RTSP sources with audio = true)
+    if let Some(audiosink) = pipeline.by_name("audiosink") {
+        let audiosink = audiosink
+            .dynamic_cast::<AppSink>()
+            .map_err(|_| anyhow::anyhow!("Failed to cast to AppSink"))?;
+        connect_appsink(
+            &audiosink,
+            StreamKind::Audio,
+            frame_tx,
+            state,
+            None,
+            last_frame,
+            false,
+        );
+    }
 
-                // Check if this is a keyframe (no DELTA_UNIT flag)
-                let is_keyframe = !buffer.flags().contains(gstreamer::BufferFlags::DELTA_UNIT);
+    Ok(())
+}
 
-                let frame = FrameData {
-                    data: map.as_slice().to_vec(),
-                    is_keyframe,
-                };
+/// Forward a rendition's appsink to its mount. Snapshots come from the main
+/// output only, so the keyframe cache is left alone.
+fn connect_rendition_appsink(
+    pipeline: &gstreamer::Pipeline,
+    rendition: &str,
+    frame_tx: Arc<FrameBus>,
+    state: Arc<Mutex<SourceState>>,
+    last_frame: FrameClock,
+) -> Result<()> {
+    let sink_name = rendition_sink_name(rendition);
+    let appsink = pipeline
+        .by_name(&sink_name)
+        .ok_or_else(|| anyhow::anyhow!("Pipeline missing '{}' element", sink_name))?
+        .dynamic_cast::<AppSink>()
+        .map_err(|_| anyhow::anyhow!("Failed to cast to AppSink"))?;
 
-                // Send frame if we have a receiver
-                if let Ok(guard) = frame_tx.lock() {
-                    if let Some(tx) = guard.as_ref() {
-                        if tx.send(frame).is_err() {
-                            debug!("Source '{}': frame receiver disconnected", name);
-                        }
+    connect_appsink(
+        &appsink,
+        StreamKind::Video,
+        frame_tx,
+        state,
+        None,
+        last_frame,
+        true,
+    );
+    Ok(())
+}
+
+/// Forward every sample from an appsink to the frame bus, tagged with `kind`.
+/// With `force_keyframes`, a keyframe request on the bus is passed upstream
+/// to the encoder.
+fn connect_appsink(
+    appsink: &AppSink,
+    kind: StreamKind,
+    frame_tx: Arc<FrameBus>,
+    state: Arc<Mutex<SourceState>>,
+    main: Option<MainSink>,
+    last_frame: FrameClock,
+    force_keyframes: bool,
+) {
+    appsink.set_callbacks(
+        gstreamer_app::AppSinkCallbacks::builder()
+            .new_sample(move |sink| {
+                // Feed the freeze watchdog; audio alone doesn't count as alive
+                if kind == StreamKind::Video {
+                    let now = Instant::now();
+                    *last_frame.lock().unwrap() = Some(now);
+
+                    // Goes live, so this frame is already forwarded below
+                    let first_frame = main
+                        .as_ref()
+                        .and_then(|main| main.first_frame.lock().unwrap().take());
+                    if let Some(first_frame) = first_frame {
+                        first_frame(now);
                     }
                 }
 
+                // Only send frames when in Live state
+                if *state.lock().unwrap() != SourceState::Live {
+                    return Ok(gstreamer::FlowSuccess::Ok);
+                }
+
+                // A client just started playing: cut the GOP short for it
+                if force_keyframes && frame_tx.take_keyframe_request() && !force_keyframe(sink) {
+                    debug!("Encoder did not accept a keyframe request");
+                }
+
+                let sample = sink.pull_sample().map_err(|_| gstreamer::FlowError::Eos)?;
+                let buffer = sample.buffer_owned().ok_or(gstreamer::FlowError::Error)?;
+
+                // Check if this is a keyframe (no DELTA_UNIT flag)
+                let is_keyframe = !buffer.flags().contains(gstreamer::BufferFlags::DELTA_UNIT);
+
+                // Forwarded by reference; the payload is never copied
+                let frame = FrameData::new(buffer, is_keyframe, kind);
+
+                // Keep the latest keyframe around for snapshots
+                if let Some(main) = &main {
+                    if kind == StreamKind::Video && is_keyframe {
+                        *main.last_keyframe.lock().unwrap() = Some(frame.buffer.clone());
+                    }
+                }
+
+                // Send frame to every attached output
+                frame_tx.send(frame);
+
                 Ok(gstreamer::FlowSuccess::Ok)
             })
             .build(),
     );
+}
 
-    Ok(())
+/// Ask the encoder upstream of `sink` for a keyframe with fresh SPS/PPS.
+/// The event travels up through the parser; returns whether it was handled.
+pub fn force_keyframe(sink: &impl IsA<gstreamer::Element>) -> bool {
+    sink.send_event(force_keyframe_event())
+}
+
+/// Upstream GstForceKeyUnit event, as GstVideoEncoder subclasses
+/// (x264enc, the VAAPI encoders, mpph265enc) understand it
+fn force_keyframe_event() -> gstreamer::Event {
+    let structure = gstreamer::Structure::builder("GstForceKeyUnit")
+        .field("all-headers", true)
+        .build();
+    gstreamer::event::CustomUpstream::new(structure)
 }
 
 /// Build encoder pipeline string
 pub fn build_encoder_string(encode: &EncodeConfig) -> String {
-    format!(
+    // x264enc's `bitrate` is also its VBV peak, so quality-targeted vbr takes
+    // `max_bitrate` there; the other modes average at, and peak at, `bitrate`
+    let bitrate = match encode.rate_control {
+        Some(RateControl::Vbr) => encode.max_bitrate.unwrap_or(encode.bitrate),
+        _ => encode.bitrate,
+    };
+    let mut encoder = format!(
         "videoconvert ! x264enc bitrate={} key-int-max={} speed-preset={} tune={}",
-        encode.bitrate, // bitrate is in kbps
+        bitrate, // config is kbps, x264enc wants kbps
         encode.keyframe_interval,
         encode.preset,
         encode.tune
+    );
+
+    let quantizer = encode.quantizer.unwrap_or(DEFAULT_QUANTIZER);
+    match encode.rate_control {
+        // Strict CBR: bound the VBV buffer to one second of video
+        Some(RateControl::Cbr) => encoder.push_str(&format!(
+            " pass=cbr vbv-buf-capacity={}",
+            encode.vbv_buffer.unwrap_or(1000)
+        )),
+        // Quality-targeted; x264enc caps it at `bitrate`
+        Some(RateControl::Vbr) => encoder.push_str(&format!(" pass=qual quantizer={}", quantizer)),
+        Some(RateControl::Cqp) => encoder.push_str(&format!(" pass=quant quantizer={}", quantizer)),
+        None => {}
+    }
+    // Fixed QP has no VBV
+    if let (Some(ms), Some(RateControl::Vbr) | None) = (encode.vbv_buffer, encode.rate_control) {
+        encoder.push_str(&format!(" vbv-buf-capacity={}", ms));
+    }
+    if let Some(threads) = encode.threads {
+        encoder.push_str(&format!(" threads={}", threads));
+    }
+    if encode.sliced_threads {
+        encoder.push_str(" sliced-threads=true");
+    }
+
+    encoder
+}
+
+/// Build a VAAPI H.264 encoder pipeline string
+pub fn build_vaapi_encoder_string(encode: &EncodeConfig, element: VaapiElement) -> String {
+    // Both elements take kbps; they differ in the GOP and QP property names
+    let (name, gop, qp) = match element {
+        VaapiElement::Va => ("vah264enc", "key-int-max", "qpi"),
+        VaapiElement::Vaapi => ("vaapih264enc", "keyframe-period", "init-qp"),
+    };
+
+    let mut encoder = format!(
+        "videoconvert ! {} bitrate={} {}={}",
+        name, encode.bitrate, gop, encode.keyframe_interval
+    );
+
+    let quantizer = encode.quantizer.unwrap_or(DEFAULT_QUANTIZER);
+    match encode.rate_control {
+        Some(RateControl::Cbr) => encoder.push_str(" rate-control=cbr"),
+        Some(RateControl::Vbr) => encoder.push_str(" rate-control=vbr"),
+        Some(RateControl::Cqp) => {
+            encoder.push_str(&format!(" rate-control=cqp {}={}", qp, quantizer))
+        }
+        None => {}
+    }
+
+    encoder
+}
+
+/// Build the H.264 encoder string for the VAAPI and x264 backends
+pub fn build_h264_encoder_string(encode: &EncodeConfig, backend: EncoderBackend) -> String {
+    match backend {
+        EncoderBackend::Vaapi(element) => build_vaapi_encoder_string(encode, element),
+        _ => build_encoder_string(encode),
+    }
+}
+
+/// Overlay elements to insert before the encoder, including the trailing " ! ".
+/// Empty when no overlay is configured.
+pub fn overlay_prefix(config: &SourceConfig) -> String {
+    let Some(overlay) = config.overlay.as_ref().filter(|o| o.enabled) else {
+        return String::new();
+    };
+
+    let (valign, halign) = match overlay.position {
+        OverlayPosition::TopLeft => ("top", "left"),
+        OverlayPosition::TopRight => ("top", "right"),
+        OverlayPosition::BottomLeft => ("bottom", "left"),
+        OverlayPosition::BottomRight => ("bottom", "right"),
+    };
+
+    // clockoverlay draws its `text` ahead of the time, so one element covers both
+    let element = if overlay.show_timestamp {
+        "clockoverlay time-format=\"%Y-%m-%d %H:%M:%S\""
+    } else {
+        "textoverlay"
+    };
+
+    let mut prefix = format!(
+        "{} valignment={} halignment={} shaded-background=true",
+        element, valign, halign
+    );
+    if let Some(text) = &overlay.text {
+        prefix.push_str(&format!(" text=\"{}\"", text.replace('"', "\\\"")));
+    }
+    prefix.push_str(" ! ");
+    prefix
+}
+
+/// `deinterlace` element (with trailing ` ! `) for interlaced input, placed
+/// straight after capture/decode so scaling sees whole frames
+pub fn deinterlace_prefix(config: &SourceConfig) -> String {
+    if !config.deinterlace {
+        return String::new();
+    }
+    format!(
+        "deinterlace method={} ! ",
+        config.deinterlace_method.method()
     )
 }
 
+/// Launch string for a source's capture/decode/encode pipeline
+pub fn pipeline_string(config: &SourceConfig, backend: EncoderBackend) -> Result<String> {
+    match config.source_type {
+        SourceType::V4l2 => v4l2::pipeline_string(config, backend),
+        SourceType::Rtsp => rtsp::pipeline_string(config, backend),
+        SourceType::Srt => srt::pipeline_string(config, backend),
+        SourceType::Test => Ok(test::pipeline_string(config, backend)),
+    }
+}
+
+/// Check once whether a source is reachable, waiting up to `timeout`.
+/// `None` for source types with no cheap probe (SRT).
+pub fn probe(config: &SourceConfig, timeout: Duration) -> Option<bool> {
+    match config.source_type {
+        SourceType::Rtsp => Some(probe_rtsp(config, timeout)),
+        SourceType::V4l2 => Some(probe_v4l2(config, timeout)),
+        // No cheap handshake-only probe for SRT; the pipeline attempt is the probe
+        SourceType::Srt => None,
+        // videotestsrc is always available
+        SourceType::Test => Some(true),
+    }
+}
+
+/// Bus polls of `PROBE_POLL_MS` that fit in `timeout`
+fn probe_polls(timeout: Duration) -> u128 {
+    (timeout.as_millis() / u128::from(PROBE_POLL_MS)).max(1)
+}
+
+/// Probe RTSP source by attempting a quick connection
+fn probe_rtsp(config: &SourceConfig, timeout: Duration) -> bool {
+    let Some(pipeline_str) = rtsp::probe_string(config, timeout) else {
+        return false;
+    };
+    // A camera given longer to connect gets as long to answer the probe
+    let timeout = config
+        .connect_timeout_secs
+        .map_or(timeout, |secs| timeout.max(Duration::from_secs(secs)));
+
+    let pipeline = match gstreamer::parse::launch(&pipeline_str) {
+        Ok(p) => p,
+        Err(_) => return false,
+    };
+
+    // Try to set to PAUSED (will attempt connection)
+    let result = pipeline.set_state(gstreamer::State::Paused);
+
+    // Wait briefly for state change
+    if result.is_ok() {
+        let bus = pipeline.bus();
+        if let Some(bus) = bus {
+            // Wait up to `timeout` for state change or error
+            for _ in 0..probe_polls(timeout) {
+                if let Some(msg) = bus.timed_pop(gstreamer::ClockTime::from_mseconds(PROBE_POLL_MS))
+                {
+                    match msg.view() {
+                        gstreamer::MessageView::Error(_) => {
+                            pipeline.set_state(gstreamer::State::Null).ok();
+                            return false;
+                        }
+                        // Children reach Paused before the pipeline has prerolled
+                        gstreamer::MessageView::StateChanged(state)
+                            if msg.src() == Some(pipeline.upcast_ref())
+                                && state.current() == gstreamer::State::Paused =>
+                        {
+                            pipeline.set_state(gstreamer::State::Null).ok();
+                            return true;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    pipeline.set_state(gstreamer::State::Null).ok();
+    false
+}
+
+/// Probe V4L2 device by trying to negotiate caps
+fn probe_v4l2(config: &SourceConfig, timeout: Duration) -> bool {
+    let device = match &config.device {
+        Some(d) => d,
+        None => return false,
+    };
+
+    // First check if device exists
+    if !std::path::Path::new(device).exists() {
+        return false;
+    }
+
+    // Try to create a minimal pipeline to test if we can negotiate caps
+    // This will fail if there's no signal (for capture cards like TC358743)
+    let caps = v4l2::capture_caps(config).unwrap_or_default();
+
+    let pipeline_str = format!("v4l2src device={}{} ! fakesink", device, caps);
+
+    let pipeline = match gstreamer::parse::launch(&pipeline_str) {
+        Ok(p) => p,
+        Err(_) => return false,
+    };
+
+    // Try to set to PAUSED - this will attempt to negotiate caps
+    let result = pipeline.set_state(gstreamer::State::Paused);
+    if result.is_err() {
+        pipeline.set_state(gstreamer::State::Null).ok();
+        return false;
+    }
+
+    // Wait up to `timeout` for state change or error
+    if let Some(bus) = pipeline.bus() {
+        for _ in 0..probe_polls(timeout) {
+            if let Some(msg) = bus.timed_pop(gstreamer::ClockTime::from_mseconds(PROBE_POLL_MS)) {
+                match msg.view() {
+                    gstreamer::MessageView::Error(err) => {
+                        // Probes repeat while waiting, so the reason stays at debug
+                        let debug = err.debug().map(|d| d.to_string());
+                        let error = err.error().to_string();
+                        if let Some(hint) =
+                            DartError::from_bus_error(config, &error, debug.as_deref()).hint()
+                        {
+                            debug!("Source '{}' probe failed: {}", config.name, hint);
+                        }
+                        pipeline.set_state(gstreamer::State::Null).ok();
+                        return false;
+                    }
+                    gstreamer::MessageView::StateChanged(state)
+                        if msg.src() == Some(pipeline.upcast_ref())
+                            && state.current() == gstreamer::State::Paused =>
+                    {
+                        pipeline.set_state(gstreamer::State::Null).ok();
+                        return true;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    pipeline.set_state(gstreamer::State::Null).ok();
+    false
+}
+
+/// Take up to `jitter` of `delay` off, scaled by `random` in [0, 1)
+fn with_jitter(delay: Duration, jitter: f64, random: f64) -> Duration {
+    delay.mul_f64(1.0 - jitter * random)
+}
+
+/// A random number in [0, 1), from the std hasher's per-instance random keys
+fn random_fraction() -> f64 {
+    use std::hash::{BuildHasher, Hasher};
+    let bits = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Where `--dump-dot` writes pipeline graphs, if it was given
+static DOT_DUMP_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Write each pipeline's GraphViz graph to `dir` once it is playing, and
+/// again if it fails
+pub fn set_dot_dump_dir(dir: PathBuf) {
+    DOT_DUMP_DIR.set(dir).ok();
+}
+
+/// Dump `pipeline` to `{file_name}.dot` in the `--dump-dot` directory, if set
+fn dump_dot(pipeline: &gstreamer::Pipeline, file_name: &str) -> Option<PathBuf> {
+    let dir = DOT_DUMP_DIR.get()?;
+    match dump_dot_to(pipeline, dir, file_name) {
+        Ok(path) => {
+            info!("Wrote pipeline graph to {}", path.display());
+            Some(path)
+        }
+        Err(e) => {
+            warn!("Failed to write pipeline graph to {}: {}", dir.display(), e);
+            None
+        }
+    }
+}
+
+/// Write `pipeline`'s graph to `{dir}/{file_name}.dot`. Element properties
+/// are left out so credentials never reach the file; caps and states are
+/// what negotiation problems need.
+fn dump_dot_to(
+    pipeline: &gstreamer::Pipeline,
+    dir: &Path,
+    file_name: &str,
+) -> std::io::Result<PathBuf> {
+    let details = gstreamer::DebugGraphDetails::MEDIA_TYPE
+        | gstreamer::DebugGraphDetails::CAPS_DETAILS
+        | gstreamer::DebugGraphDetails::STATES;
+    let dot = pipeline.debug_to_dot_data(details);
+    let path = dir.join(format!("{}.dot", file_name));
+    std::fs::write(&path, dot.as_str())?;
+    Ok(path)
+}
+
+/// Parse a launch string built by [`pipeline_string`] into a pipeline
+fn launch_pipeline(pipeline_str: &str) -> Result<gstreamer::Pipeline> {
+    gstreamer::parse::launch(pipeline_str)?
+        .downcast::<gstreamer::Pipeline>()
+        .map_err(|_| anyhow::anyhow!("Failed to create pipeline"))
+}
+
 /// Common appsink configuration
 pub fn appsink_config() -> &'static str {
     "appsink name=sink emit-signals=true sync=false"
 }
 
+/// Name of the main output's encoder element
+pub const ENCODER_NAME: &str = "encoder";
+
+/// Name of the appsink carrying a rendition's frames
+pub fn rendition_sink_name(rendition: &str) -> String {
+    format!("sink-{}", rendition)
+}
+
+/// Raw video to appsink(s): overlay, encoder and parser for the main output.
+/// With renditions, a tee after the overlay also feeds one scaled encoder per
+/// rendition, each ending in its own `sink-{name}` appsink.
+pub fn encode_branches(config: &SourceConfig, backend: EncoderBackend) -> String {
+    let overlay = overlay_prefix(config);
+    let main = encoded_output(
+        &config.encode_config(),
+        backend,
+        ENCODER_NAME,
+        appsink_config(),
+    );
+    if config.renditions.is_empty() {
+        return format!("{}{}", overlay, main);
+    }
+
+    // MPP encodes NV12 directly, so scaled frames have to stay NV12
+    let format = (backend == EncoderBackend::Mpp).then_some("NV12");
+    let mut branches = format!("{}tee name=raw raw. ! queue ! {}", overlay, main);
+    for rendition in &config.renditions {
+        let appsink = format!(
+            "appsink name={} emit-signals=true sync=false",
+            rendition_sink_name(&rendition.name)
+        );
+        branches.push_str(&format!(
+            " raw. ! queue ! {}{}",
+            scale_prefix(config, rendition.width, rendition.height, format, false),
+            encoded_output(
+                &config.rendition_encode(rendition),
+                backend,
+                &format!("{}-{}", ENCODER_NAME, rendition.name),
+                &appsink
+            )
+        ));
+    }
+    branches
+}
+
+/// Encoder (named `encoder_name`), parser and appsink for one output
+fn encoded_output(
+    encode: &EncodeConfig,
+    backend: EncoderBackend,
+    encoder_name: &str,
+    appsink: &str,
+) -> String {
+    let (encoder, encoder_caps, parse, caps) = if backend == EncoderBackend::Mpp {
+        (
+            build_mpp_h265_encoder_string(encode),
+            h265_caps().to_string(),
+            "h265parse",
+            h265_caps(),
+        )
+    } else {
+        (
+            build_h264_encoder_string(encode, backend),
+            h264_encoder_caps(encode),
+            "h264parse",
+            h264_caps(),
+        )
+    };
+    format!(
+        "{encoder} name={name} ! {encoder_caps} ! {parse} ! {caps} ! {appsink}",
+        encoder = encoder,
+        name = encoder_name,
+        encoder_caps = encoder_caps,
+        parse = parse,
+        caps = caps,
+        appsink = appsink,
+    )
+}
+
+/// Scale raw video to `width`/`height`, including the trailing " ! ". Empty
+/// when neither is set. With only one dimension set, videoscale keeps the
+/// aspect ratio. Sizes are clamped to the source's `max_width`/`max_height`;
+/// with `bound`, see [`size_fields`].
+pub fn scale_prefix(
+    config: &SourceConfig,
+    width: Option<u32>,
+    height: Option<u32>,
+    format: Option<&str>,
+    bound: bool,
+) -> String {
+    let size = size_fields(config, width, height, bound);
+    if size.is_empty() {
+        return String::new();
+    }
+
+    let mut caps = vec!["video/x-raw".to_string()];
+    if let Some(format) = format {
+        caps.push(format!("format={}", format));
+    }
+    caps.extend(size);
+    format!(
+        "{} ! {} ! ",
+        scaler(config, width, height, bound),
+        caps.join(",")
+    )
+}
+
+/// Name of the videoscale bounding a negotiated size, watched to log clamping
+pub const CLAMP_NAME: &str = "clamp";
+
+/// Size fields for raw video caps. A configured size larger than the source's
+/// `max_width`/`max_height` is clamped to fit. With `bound`, a dimension left
+/// unset gets a range instead, so videoscale only shrinks video that
+/// negotiates larger than the limit.
+pub fn size_fields(
+    config: &SourceConfig,
+    width: Option<u32>,
+    height: Option<u32>,
+    bound: bool,
+) -> Vec<String> {
+    let clamped = match (width, height) {
+        (Some(w), Some(h)) => {
+            let (w, h) = config.clamp_size(w, h);
+            (Some(w), Some(h))
+        }
+        _ => (
+            width.map(|w| config.max_width.map_or(w, |max| w.min(max))),
+            height.map(|h| config.max_height.map_or(h, |max| h.min(max))),
+        ),
+    };
+
+    let mut fields = Vec::new();
+    for (key, value, max) in [
+        ("width", clamped.0, config.max_width),
+        ("height", clamped.1, config.max_height),
+    ] {
+        match (value, max) {
+            (Some(value), _) => fields.push(format!("{}={}", key, value)),
+            (None, Some(max)) if bound => fields.push(format!("{}=(int)[1,{}]", key, max)),
+            _ => {}
+        }
+    }
+
+    if clamped != (width, height) {
+        warn!(
+            "Source '{}' size exceeds max_width/max_height; scaling down to {}",
+            config.name,
+            fields.join(",")
+        );
+    }
+    fields
+}
+
+/// The videoscale for [`scale_prefix`], named [`CLAMP_NAME`] when it bounds
+/// a negotiated size
+fn scaler(config: &SourceConfig, width: Option<u32>, height: Option<u32>, bound: bool) -> String {
+    let ranged = (width.is_none() && config.max_width.is_some())
+        || (height.is_none() && config.max_height.is_some());
+    if bound && ranged {
+        format!("videoscale name={}", CLAMP_NAME)
+    } else {
+        "videoscale".to_string()
+    }
+}
+
+/// Log when the [`CLAMP_NAME`] videoscale shrinks the negotiated video
+fn watch_clamp(name: &str, pipeline: &gstreamer::Pipeline) {
+    let Some(clamp) = pipeline.by_name(CLAMP_NAME) else {
+        return;
+    };
+    let (Some(sink), Some(src)) = (clamp.static_pad("sink"), clamp.static_pad("src")) else {
+        return;
+    };
+
+    let name = name.to_string();
+    src.connect_notify(Some("caps"), move |src, _| {
+        let size = |pad: &gstreamer::Pad| {
+            let caps = pad.current_caps()?;
+            let s = caps.structure(0)?;
+            Some((s.get::<i32>("width").ok()?, s.get::<i32>("height").ok()?))
+        };
+        if let (Some((in_w, in_h)), Some((out_w, out_h))) = (size(&sink), size(src)) {
+            if (in_w, in_h) != (out_w, out_h) {
+                warn!(
+                    "Source '{}' negotiated {}x{}, above max_width/max_height; scaling down to {}x{}",
+                    name, in_w, in_h, out_w, out_h
+                );
+            }
+        }
+    });
+}
+
 /// H.264 output caps
 pub fn h264_caps() -> &'static str {
     "video/x-h264,stream-format=byte-stream,alignment=au"
 }
 
+/// Caps right after an H.264 encoder: the output caps, constrained to the
+/// configured profile and level, which x264enc and the VAAPI encoders
+/// negotiate to
+pub fn h264_encoder_caps(encode: &EncodeConfig) -> String {
+    let mut caps = h264_caps().to_string();
+    if let Some(profile) = encode.profile {
+        caps.push_str(&format!(",profile={}", profile.caps_name()));
+    }
+    // A bare 4.1 would parse as a number
+    if let Some(level) = &encode.level {
+        caps.push_str(&format!(",level=(string){}", level));
+    }
+    caps
+}
+
 /// H.265 output caps
 pub fn h265_caps() -> &'static str {
     "video/x-h265,stream-format=byte-stream,alignment=au"
@@ -476,9 +1749,896 @@ pub fn h265_caps() -> &'static str {
 
 /// Build MPP H.265 encoder pipeline string
 pub fn build_mpp_h265_encoder_string(encode: &EncodeConfig) -> String {
-    format!(
+    let mut encoder = format!(
         "mpph265enc bps={} gop={}",
         encode.bitrate * 1000, // config is kbps, MPP wants bps
         encode.keyframe_interval,
-    )
+    );
+
+    match encode.rate_control {
+        Some(RateControl::Cbr) => encoder.push_str(" rc-mode=cbr"),
+        Some(RateControl::Vbr) => {
+            encoder.push_str(" rc-mode=vbr");
+            if let Some(q) = encode.quantizer {
+                encoder.push_str(&format!(" qp-init={}", q));
+            }
+        }
+        Some(RateControl::Cqp) => encoder.push_str(&format!(
+            " rc-mode=fixqp qp-init={}",
+            encode.quantizer.unwrap_or(DEFAULT_QUANTIZER)
+        )),
+        None => {}
+    }
+    if let Some(max) = encode.max_bitrate {
+        encoder.push_str(&format!(" bps-max={}", max * 1000));
+    }
+
+    encoder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fallback::FallbackFrame;
+
+    fn rtsp_source(reconnect_interval: u64) -> Source {
+        let config: SourceConfig = toml::from_str(&format!(
+            r#"
+            name = "cam1"
+            type = "rtsp"
+            url = "rtsp://camera.local/stream"
+            reconnect_interval = {}
+        "#,
+            reconnect_interval
+        ))
+        .unwrap();
+        Source::new(
+            config,
+            Arc::new(FrameBus::default()),
+            None,
+            EncoderBackend::X264,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_encoder_bitrate_units() {
+        let encode = EncodeConfig {
+            bitrate: 2500,
+            ..EncodeConfig::default()
+        };
+
+        assert!(build_encoder_string(&encode).contains("x264enc bitrate=2500 "));
+        assert!(build_mpp_h265_encoder_string(&encode).contains("mpph265enc bps=2500000 "));
+    }
+
+    #[test]
+    fn test_rate_control_x264() {
+        let encode = |rate_control, quantizer| EncodeConfig {
+            rate_control,
+            quantizer,
+            ..EncodeConfig::default()
+        };
+
+        let default = build_encoder_string(&encode(None, None));
+        assert!(!default.contains("pass="));
+
+        assert!(build_encoder_string(&encode(Some(RateControl::Cbr), None))
+            .ends_with(" pass=cbr vbv-buf-capacity=1000"));
+        assert!(build_encoder_string(&encode(Some(RateControl::Vbr), None))
+            .ends_with(" pass=qual quantizer=23"));
+        assert!(
+            build_encoder_string(&encode(Some(RateControl::Cqp), Some(30)))
+                .ends_with(" pass=quant quantizer=30")
+        );
+    }
+
+    #[test]
+    fn test_x264_threading() {
+        let encode = |threads, sliced_threads| EncodeConfig {
+            threads,
+            sliced_threads,
+            ..EncodeConfig::default()
+        };
+
+        let default = build_encoder_string(&encode(None, false));
+        assert!(!default.contains("threads="));
+        assert!(build_encoder_string(&encode(Some(4), false)).ends_with(" threads=4"));
+        assert!(build_encoder_string(&encode(None, true)).ends_with(" sliced-threads=true"));
+        assert!(build_encoder_string(&encode(Some(0), true))
+            .ends_with(" threads=0 sliced-threads=true"));
+
+        // Other encoders don't take them
+        let hw = encode(Some(4), true);
+        assert!(!build_mpp_h265_encoder_string(&hw).contains("threads"));
+        assert!(!build_vaapi_encoder_string(&hw, VaapiElement::Va).contains("threads"));
+    }
+
+    #[test]
+    fn test_rate_control_mpp() {
+        let encode = |rate_control, quantizer| EncodeConfig {
+            rate_control,
+            quantizer,
+            ..EncodeConfig::default()
+        };
+
+        let default = build_mpp_h265_encoder_string(&encode(None, None));
+        assert!(!default.contains("rc-mode"));
+
+        assert!(
+            build_mpp_h265_encoder_string(&encode(Some(RateControl::Cbr), None))
+                .ends_with(" rc-mode=cbr")
+        );
+        assert!(
+            build_mpp_h265_encoder_string(&encode(Some(RateControl::Vbr), Some(28)))
+                .ends_with(" rc-mode=vbr qp-init=28")
+        );
+        assert!(
+            build_mpp_h265_encoder_string(&encode(Some(RateControl::Cqp), None))
+                .ends_with(" rc-mode=fixqp qp-init=23")
+        );
+    }
+
+    #[test]
+    fn test_vbv() {
+        let encode = |rate_control, max_bitrate, vbv_buffer| EncodeConfig {
+            bitrate: 2000,
+            rate_control,
+            max_bitrate,
+            vbv_buffer,
+            ..EncodeConfig::default()
+        };
+
+        // CBR takes the configured buffer instead of one second
+        let cbr = build_encoder_string(&encode(Some(RateControl::Cbr), Some(3000), Some(500)));
+        assert!(cbr.contains("x264enc bitrate=2000 "));
+        assert!(cbr.ends_with(" pass=cbr vbv-buf-capacity=500"));
+
+        // VBR peaks at max_bitrate
+        let vbr = build_encoder_string(&encode(Some(RateControl::Vbr), Some(3000), Some(500)));
+        assert!(vbr.contains("x264enc bitrate=3000 "));
+        assert!(vbr.ends_with(" pass=qual quantizer=23 vbv-buf-capacity=500"));
+
+        let default = build_encoder_string(&encode(None, None, Some(250)));
+        assert!(default.contains("x264enc bitrate=2000 "));
+        assert!(default.ends_with(" tune=zerolatency vbv-buf-capacity=250"));
+        assert!(
+            !build_encoder_string(&encode(Some(RateControl::Cqp), None, Some(250))).contains("vbv")
+        );
+
+        assert!(
+            build_mpp_h265_encoder_string(&encode(Some(RateControl::Vbr), Some(3000), None))
+                .ends_with(" rc-mode=vbr bps-max=3000000")
+        );
+        assert!(
+            build_mpp_h265_encoder_string(&encode(Some(RateControl::Cbr), None, Some(500)))
+                .ends_with(" rc-mode=cbr")
+        );
+    }
+
+    #[test]
+    fn test_h264_profile_caps() {
+        use crate::config::H264Profile;
+        gstreamer::init().unwrap();
+        let encode = |profile, level: Option<&str>| EncodeConfig {
+            profile,
+            level: level.map(str::to_string),
+            ..EncodeConfig::default()
+        };
+
+        // x264 negotiates to the profile in the caps after it
+        let baseline = encoded_output(
+            &encode(Some(H264Profile::Baseline), Some("3.1")),
+            EncoderBackend::X264,
+            ENCODER_NAME,
+            appsink_config(),
+        );
+        assert!(baseline.contains(&format!(
+            " name=encoder ! {},profile=baseline,level=(string)3.1 ! h264parse ! {} ! ",
+            h264_caps(),
+            h264_caps()
+        )));
+
+        assert_eq!(
+            h264_encoder_caps(&encode(Some(H264Profile::ConstrainedBaseline), None)),
+            format!("{},profile=constrained-baseline", h264_caps())
+        );
+        assert_eq!(
+            h264_encoder_caps(&encode(Some(H264Profile::Main), None)),
+            format!("{},profile=main", h264_caps())
+        );
+        assert_eq!(
+            h264_encoder_caps(&encode(Some(H264Profile::High), Some("4.1"))),
+            format!("{},profile=high,level=(string)4.1", h264_caps())
+        );
+        assert_eq!(h264_encoder_caps(&encode(None, None)), h264_caps());
+
+        // VAAPI takes the same constraint; MPP's H.265 doesn't
+        let vaapi = encoded_output(
+            &encode(Some(H264Profile::Main), None),
+            EncoderBackend::Vaapi(VaapiElement::Va),
+            ENCODER_NAME,
+            appsink_config(),
+        );
+        assert!(vaapi.contains("vah264enc bitrate=2000 key-int-max=60 name=encoder ! video/x-h264,stream-format=byte-stream,alignment=au,profile=main ! "));
+        let mpp = encoded_output(
+            &encode(Some(H264Profile::Main), None),
+            EncoderBackend::Mpp,
+            ENCODER_NAME,
+            appsink_config(),
+        );
+        assert!(!mpp.contains("profile"));
+
+        // The caps parse, with the level kept a string
+        let caps: gstreamer::Caps =
+            h264_encoder_caps(&encode(Some(H264Profile::High), Some("4.1")))
+                .parse()
+                .unwrap();
+        let s = caps.structure(0).unwrap();
+        assert_eq!(s.get::<&str>("profile").unwrap(), "high");
+        assert_eq!(s.get::<&str>("level").unwrap(), "4.1");
+    }
+
+    #[test]
+    fn test_vaapi_encoder_string() {
+        let encode = EncodeConfig {
+            bitrate: 4000,
+            keyframe_interval: 30,
+            ..EncodeConfig::default()
+        };
+
+        assert_eq!(
+            build_vaapi_encoder_string(&encode, VaapiElement::Va),
+            "videoconvert ! vah264enc bitrate=4000 key-int-max=30"
+        );
+        assert_eq!(
+            build_vaapi_encoder_string(&encode, VaapiElement::Vaapi),
+            "videoconvert ! vaapih264enc bitrate=4000 keyframe-period=30"
+        );
+
+        let cqp = EncodeConfig {
+            rate_control: Some(RateControl::Cqp),
+            quantizer: Some(26),
+            ..encode
+        };
+        assert!(build_vaapi_encoder_string(&cqp, VaapiElement::Va)
+            .ends_with(" rate-control=cqp qpi=26"));
+        assert!(build_vaapi_encoder_string(&cqp, VaapiElement::Vaapi)
+            .ends_with(" rate-control=cqp init-qp=26"));
+    }
+
+    #[test]
+    fn test_backend_precedence() {
+        assert_eq!(
+            EncoderBackend::select(true, Some(VaapiElement::Va)),
+            EncoderBackend::Mpp
+        );
+        assert_eq!(
+            EncoderBackend::select(false, Some(VaapiElement::Vaapi)),
+            EncoderBackend::Vaapi(VaapiElement::Vaapi)
+        );
+        assert_eq!(EncoderBackend::select(false, None), EncoderBackend::X264);
+
+        assert_eq!(EncoderBackend::Mpp.output_codec(), OutputCodec::H265);
+        assert_eq!(
+            EncoderBackend::Vaapi(VaapiElement::Va).output_codec(),
+            OutputCodec::H264
+        );
+    }
+
+    #[test]
+    fn test_mpp_needs_every_element() {
+        let installed = |names: &'static [&'static str]| move |name: &str| names.contains(&name);
+
+        assert!(mpp_usable(installed(&["mpph265enc", "mppvideodec"])));
+        // Encoder without the decoder falls back like no MPP at all
+        assert!(!mpp_usable(installed(&["mpph265enc"])));
+        assert!(!mpp_usable(installed(&["mppvideodec"])));
+        assert!(!mpp_usable(installed(&[])));
+
+        assert_eq!(
+            missing_elements(MPP_ELEMENTS, installed(&["mpph265enc"])),
+            vec!["mppvideodec"]
+        );
+        assert_eq!(missing_elements(MPP_ELEMENTS, installed(&[])), MPP_ELEMENTS);
+    }
+
+    #[test]
+    fn test_encoder_override() {
+        let va = Some(VaapiElement::Va);
+
+        // Forcing x264 wins even when hardware is present
+        assert_eq!(
+            EncoderBackend::resolve(EncoderChoice::X264, true, va).unwrap(),
+            EncoderBackend::X264
+        );
+        assert_eq!(
+            EncoderBackend::resolve(EncoderChoice::Vaapi, true, va).unwrap(),
+            EncoderBackend::Vaapi(VaapiElement::Va)
+        );
+        assert_eq!(
+            EncoderBackend::resolve(EncoderChoice::Auto, true, va).unwrap(),
+            EncoderBackend::Mpp
+        );
+
+        // Forcing hardware that isn't there is an error
+        assert!(EncoderBackend::resolve(EncoderChoice::Mpp, false, va).is_err());
+        assert!(EncoderBackend::resolve(EncoderChoice::Vaapi, true, None).is_err());
+    }
+
+    #[test]
+    fn test_rendition_branches() {
+        let config: SourceConfig = toml::from_str(
+            r#"
+            name = "bars"
+            type = "test"
+            [encode]
+            bitrate = 4000
+            [[rendition]]
+            name = "med"
+            bitrate = 1500
+            width = 1280
+            height = 720
+            [[rendition]]
+            name = "low"
+            bitrate = 500
+            height = 360
+        "#,
+        )
+        .unwrap();
+
+        let x264 = encode_branches(&config, EncoderBackend::X264);
+        assert!(
+            x264.starts_with("tee name=raw raw. ! queue ! videoconvert ! x264enc bitrate=4000 ")
+        );
+        assert!(x264.contains(&format!("{} raw. ! queue ", appsink_config())));
+        assert!(x264.contains(
+            " raw. ! queue ! videoscale ! video/x-raw,width=1280,height=720 ! videoconvert ! x264enc bitrate=1500 "
+        ));
+        assert!(x264.contains(
+            " raw. ! queue ! videoscale ! video/x-raw,height=360 ! videoconvert ! x264enc bitrate=500 "
+        ));
+        assert_eq!(x264.matches("appsink name=").count(), 3);
+        assert!(x264.contains(" name=encoder ! ") && x264.contains(" name=encoder-low ! "));
+        assert!(x264.contains("appsink name=sink-med emit-signals=true sync=false raw."));
+        assert!(x264.ends_with("appsink name=sink-low emit-signals=true sync=false"));
+
+        // MPP keeps scaled frames in NV12 and encodes each rendition to H.265
+        let mpp = encode_branches(&config, EncoderBackend::Mpp);
+        assert!(mpp.contains(
+            "videoscale ! video/x-raw,format=NV12,width=1280,height=720 ! mpph265enc bps=1500000 "
+        ));
+        assert_eq!(mpp.matches("! h265parse !").count(), 3);
+
+        // No renditions, no tee
+        let single: SourceConfig =
+            toml::from_str("name = \"bars\"\ntype = \"test\"\n[encode]").unwrap();
+        let single = encode_branches(&single, EncoderBackend::X264);
+        assert!(!single.contains("tee"));
+        assert!(single.ends_with(appsink_config()));
+    }
+
+    #[test]
+    fn test_first_frame_goes_live() {
+        gstreamer::init().unwrap();
+        let fallback = FallbackSource::Image(FallbackFrame {
+            buffer: gstreamer::Buffer::from_slice([0, 0, 0, 1]),
+            is_keyframe: true,
+        });
+        let config = toml::from_str(
+            r#"
+            name = "cam1"
+            type = "rtsp"
+            url = "rtsp://camera.local/stream"
+        "#,
+        )
+        .unwrap();
+        let source = Source::new(
+            config,
+            Arc::new(FrameBus::default()),
+            Some(fallback),
+            EncoderBackend::X264,
+        )
+        .unwrap();
+        *source.running.lock().unwrap() = Arc::new(AtomicBool::new(true));
+        assert!(source.enter_fallback());
+
+        // Nothing counts as connected until a frame arrives
+        assert_eq!(source.started_at(), None);
+        let now = Instant::now();
+        source.first_frame(now);
+        assert_eq!(source.state(), SourceState::Live);
+        assert_eq!(source.connected_since(), Some(now));
+        assert_eq!(source.started_at(), Some(now));
+        assert_eq!(source.fallback_duration(), None);
+
+        // A frame landing after a stop doesn't bring the source back
+        source.stop();
+        source.first_frame(Instant::now());
+        assert_eq!(source.state(), SourceState::Stopped);
+        assert_eq!(source.connected_since(), Some(now));
+    }
+
+    #[test]
+    fn test_v4l2_enters_fallback() {
+        let config = || -> SourceConfig {
+            toml::from_str(
+                r#"
+                name = "hdmi"
+                type = "v4l2"
+                device = "/dev/video0"
+                [encode]
+            "#,
+            )
+            .unwrap()
+        };
+        gstreamer::init().unwrap();
+        let fallback = FallbackSource::Image(FallbackFrame {
+            buffer: gstreamer::Buffer::from_slice([0, 0, 0, 1]),
+            is_keyframe: true,
+        });
+
+        let source = Source::new(
+            config(),
+            Arc::new(FrameBus::default()),
+            Some(fallback),
+            EncoderBackend::X264,
+        )
+        .unwrap();
+        assert!(source.enter_fallback());
+        assert_eq!(source.state(), SourceState::Fallback);
+        assert!(source.fallback_duration().is_some());
+
+        // Going live from fallback is a recovery, once
+        assert!(source.go_live());
+        assert_eq!(source.state(), SourceState::Live);
+        assert!(!source.go_live());
+
+        // Without a fallback image the device just retries
+        let source = Source::new(
+            config(),
+            Arc::new(FrameBus::default()),
+            None,
+            EncoderBackend::X264,
+        )
+        .unwrap();
+        assert!(!source.enter_fallback());
+        assert_eq!(source.state(), SourceState::Stopped);
+    }
+
+    #[test]
+    fn test_reconnect_delay_uses_configured_interval() {
+        let source = rtsp_source(5);
+        assert_eq!(source.reconnect_delay(0), FAST_POLL_INTERVAL);
+        assert_eq!(source.reconnect_delay(1), Duration::from_secs(5));
+        assert_eq!(source.reconnect_delay(7), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_reconnect_delay_first_probe_capped_by_interval() {
+        let source = rtsp_source(1);
+        assert_eq!(source.reconnect_delay(0), Duration::from_secs(1));
+        assert_eq!(source.reconnect_delay(1), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_reconnect_backoff() {
+        let source = |extra: &str| -> Source {
+            let config: SourceConfig = toml::from_str(&format!(
+                "name = \"cam1\"\ntype = \"rtsp\"\nurl = \"rtsp://camera.local/stream\"\nreconnect_interval = 5\n{}",
+                extra
+            ))
+            .unwrap();
+            Source::new(
+                config,
+                Arc::new(FrameBus::default()),
+                None,
+                EncoderBackend::X264,
+            )
+            .unwrap()
+        };
+
+        // Quick first probe, then doubling from the interval up to the cap
+        let backoff = source("reconnect_max_secs = 60");
+        let delays: Vec<_> = (0..8)
+            .map(|n| backoff.reconnect_delay(n).as_secs())
+            .collect();
+        assert_eq!(delays, [2, 5, 10, 20, 40, 60, 60, 60]);
+        assert_eq!(backoff.reconnect_delay(u32::MAX), Duration::from_secs(60));
+
+        // Jitter only ever shortens the wait, by at most its share
+        assert_eq!(
+            with_jitter(Duration::from_secs(10), 0.5, 0.0),
+            Duration::from_secs(10)
+        );
+        assert_eq!(
+            with_jitter(Duration::from_secs(10), 0.5, 0.5),
+            Duration::from_millis(7500)
+        );
+        let jittered = source("reconnect_max_secs = 60\nreconnect_jitter = 0.5");
+        for _ in 0..50 {
+            let delay = jittered.reconnect_delay(5);
+            assert!(delay > Duration::from_secs(30) && delay <= Duration::from_secs(60));
+        }
+    }
+
+    #[test]
+    fn test_idle_timer() {
+        let secs = Duration::from_secs;
+        let start = Instant::now();
+        let mut idle = IdleTimer::new(Some(secs(30)));
+
+        // The countdown starts with the first unwatched check
+        assert!(!idle.is_idle(false, start));
+        assert!(!idle.is_idle(false, start + secs(29)));
+        assert!(idle.is_idle(false, start + secs(30)));
+
+        // A client resets it; leaving again starts over
+        assert!(!idle.is_idle(true, start + secs(31)));
+        assert!(!idle.is_idle(false, start + secs(40)));
+        assert!(!idle.is_idle(false, start + secs(69)));
+        assert!(idle.is_idle(false, start + secs(70)));
+
+        // Disabled never releases
+        let mut disabled = IdleTimer::new(None);
+        assert!(!disabled.is_idle(false, start));
+        assert!(!disabled.is_idle(false, start + secs(3600)));
+    }
+
+    #[test]
+    fn test_idle_pause_transitions() {
+        let mut pause = IdlePause::new(true);
+        assert_eq!(pause.transition(true), None);
+        // Last client left: pause once
+        assert_eq!(pause.transition(false), Some(gstreamer::State::Paused));
+        assert!(pause.is_paused());
+        assert_eq!(pause.transition(false), None);
+        // First client: resume once
+        assert_eq!(pause.transition(true), Some(gstreamer::State::Playing));
+        assert!(!pause.is_paused());
+        assert_eq!(pause.transition(true), None);
+        assert_eq!(pause.transition(false), Some(gstreamer::State::Paused));
+
+        let mut disabled = IdlePause::new(false);
+        assert_eq!(disabled.transition(false), None);
+        assert_eq!(disabled.transition(true), None);
+        assert!(!disabled.is_paused());
+    }
+
+    #[test]
+    fn test_idle_release_needs_no_continuous_outputs() {
+        let source = |extra: &str| {
+            let config: SourceConfig = toml::from_str(&format!(
+                "name = \"bars\"\ntype = \"test\"\n{}\n[encode]",
+                extra
+            ))
+            .unwrap();
+            Source::new(
+                config,
+                Arc::new(FrameBus::default()),
+                None,
+                EncoderBackend::X264,
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            source("").with_idle_release(30).idle_release,
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(source("").with_idle_release(0).idle_release, None);
+        // HLS needs frames whether or not anyone is on RTSP
+        assert_eq!(
+            source("hls = true").with_idle_release(30).idle_release,
+            None
+        );
+        assert!(source("").with_idle_pause(true).idle_pause);
+        assert!(!source("hls = true").with_idle_pause(true).idle_pause);
+
+        // Nobody on the mount yet
+        assert!(!source("").is_watched());
+    }
+
+    #[test]
+    fn test_freeze_watchdog() {
+        let secs = |n| Duration::from_secs(n);
+        let earlier = Instant::now();
+        let started = earlier + secs(60);
+        let watchdog = FreezeWatchdog::new(10, started);
+
+        // No frame yet: the grace period runs from pipeline start
+        assert!(!watchdog.is_frozen(None, started + secs(9)));
+        assert!(watchdog.is_frozen(None, started + secs(10)));
+
+        // Each frame resets the timer
+        let last = Some(started + secs(8));
+        assert!(!watchdog.is_frozen(last, started + secs(17)));
+        assert!(watchdog.is_frozen(last, started + secs(18)));
+
+        // A frame from a previous pipeline doesn't count against this one
+        let stale = Some(earlier);
+        assert!(!watchdog.is_frozen(stale, started + secs(1)));
+
+        // Zero disables it
+        let disabled = FreezeWatchdog::new(0, started);
+        assert!(!disabled.is_frozen(None, started + secs(3600)));
+    }
+
+    fn launch(pipeline_str: &str) -> gstreamer::Pipeline {
+        gstreamer::init().unwrap();
+        let pipeline = gstreamer::parse::launch(pipeline_str)
+            .unwrap()
+            .downcast::<gstreamer::Pipeline>()
+            .unwrap();
+        pipeline.set_state(gstreamer::State::Playing).unwrap();
+        let _ = pipeline.state(gstreamer::ClockTime::from_seconds(1));
+        pipeline
+    }
+
+    #[test]
+    fn test_uptime_across_reconnect() {
+        // Connected at `start`, reconnected 59 minutes later, asked an hour in
+        let start = Instant::now();
+        let reconnect = start + Duration::from_secs(3540);
+        let now = start + Duration::from_secs(3600);
+
+        // By default a reconnect starts the count again
+        let source = rtsp_source(5);
+        assert_eq!(source.uptime(), None);
+        source.mark_connected(start);
+        assert_eq!(source.uptime_at(now), Some(Duration::from_secs(3600)));
+        assert!(source.uptime().is_some());
+
+        source.record_disconnect(Err(anyhow::anyhow!("Connection refused").into()));
+        assert_eq!(source.uptime(), None);
+        source.mark_connected(reconnect);
+        assert_eq!(source.uptime_at(now), Some(Duration::from_secs(60)));
+        assert_eq!(source.started_at(), Some(start));
+
+        // With the flag it counts from the first time the source went live
+        let config: SourceConfig = toml::from_str("name = \"cam1\"\ntype = \"rtsp\"\nurl = \"rtsp://camera.local/stream\"\nuptime_across_reconnects = true").unwrap();
+        let source = Source::new(
+            config,
+            Arc::new(FrameBus::default()),
+            None,
+            EncoderBackend::X264,
+        )
+        .unwrap();
+        source.mark_connected(start);
+        source.record_disconnect(Err(anyhow::anyhow!("Connection refused").into()));
+        assert_eq!(source.uptime(), None);
+        source.mark_connected(reconnect);
+        assert_eq!(source.uptime_at(now), Some(Duration::from_secs(3600)));
+        assert_eq!(source.connected_since(), Some(reconnect));
+    }
+
+    #[test]
+    fn test_panicking_run_is_restarted() {
+        let running = AtomicBool::new(true);
+        let restarts = AtomicU64::new(0);
+        let mut runs = 0;
+        supervise("cam1", &running, &restarts, Duration::ZERO, || {
+            runs += 1;
+            if runs < 3 {
+                panic!("run {} failed", runs);
+            }
+        });
+        assert_eq!(runs, 3);
+        assert_eq!(restarts.load(Ordering::SeqCst), 2);
+
+        // A stopped source isn't brought back
+        running.store(false, Ordering::SeqCst);
+        let mut runs = 0;
+        supervise("cam1", &running, &restarts, Duration::ZERO, || {
+            runs += 1;
+            panic!("stopping");
+        });
+        assert_eq!(runs, 1);
+        assert_eq!(restarts.load(Ordering::SeqCst), 2);
+
+        // Sources start with none
+        assert_eq!(rtsp_source(5).restart_count(), 0);
+    }
+
+    #[test]
+    fn test_failed_connects_are_counted() {
+        let source = rtsp_source(5);
+        assert_eq!(source.reconnect_count(), 0);
+        assert_eq!(source.last_error(), None);
+
+        // Failing to connect before ever going live isn't a reconnect
+        source.record_disconnect(Err(anyhow::anyhow!("No route to host").into()));
+        assert_eq!(source.reconnect_count(), 0);
+        assert_eq!(source.last_error().as_deref(), Some("No route to host"));
+
+        source.mark_connected(Instant::now());
+        assert!(source.uptime().is_some());
+
+        source.record_disconnect(Err(anyhow::anyhow!("Connection refused").into()));
+        source.record_disconnect(Err(anyhow::anyhow!("Could not open resource").into()));
+        assert_eq!(source.reconnect_count(), 2);
+        assert_eq!(
+            source.last_error().as_deref(),
+            Some("Could not open resource")
+        );
+        assert_eq!(source.connected_since(), None);
+
+        // A clean end of stream still counts, but keeps the last error
+        source.record_disconnect(Ok(()));
+        assert_eq!(source.reconnect_count(), 3);
+        assert!(source.last_error().is_some());
+
+        // The last failure keeps its kind for embedders to match on
+        source.record_disconnect(Err(DartError::Timeout { secs: 5 }));
+        assert!(matches!(
+            source.last_failure().as_deref(),
+            Some(DartError::Timeout { secs: 5 })
+        ));
+        assert_eq!(
+            source.last_error().as_deref(),
+            Some("No frames for 5s, source appears frozen")
+        );
+    }
+
+    #[test]
+    fn test_set_bitrate_checks_source() {
+        // Passthrough has no encoder to retune
+        assert!(rtsp_source(5).set_bitrate(2000).is_err());
+
+        let config: SourceConfig =
+            toml::from_str("name = \"bars\"\ntype = \"test\"\n[encode]").unwrap();
+        let source = Source::new(
+            config,
+            Arc::new(FrameBus::default()),
+            None,
+            EncoderBackend::X264,
+        )
+        .unwrap();
+        assert!(source.set_bitrate(0).is_err());
+        assert!(source.set_bitrate(2_000_000).is_err());
+
+        // Not running yet: kept for when the pipeline starts
+        source.set_bitrate(2000).unwrap();
+        assert_eq!(*source.bitrate_override.lock().unwrap(), Some(2000));
+    }
+
+    #[test]
+    #[ignore = "needs a GStreamer runtime with videotestsrc and x264enc"]
+    fn test_set_encoder_bitrate_live() {
+        let config: SourceConfig = toml::from_str(
+            r#"
+            name = "bars"
+            type = "test"
+            [encode]
+            bitrate = 4000
+        "#,
+        )
+        .unwrap();
+        let pipeline = launch(&test::pipeline_string(&config, EncoderBackend::X264));
+        let encoder = pipeline.by_name(ENCODER_NAME).unwrap();
+        assert_eq!(encoder.property::<u32>("bitrate"), 4000);
+
+        set_encoder_bitrate(&pipeline, EncoderBackend::X264, 1500).unwrap();
+        assert_eq!(encoder.property::<u32>("bitrate"), 1500);
+        assert_eq!(pipeline.current_state(), gstreamer::State::Playing);
+
+        // The MPP property doesn't exist on x264enc
+        assert!(set_encoder_bitrate(&pipeline, EncoderBackend::Mpp, 1500).is_err());
+        pipeline.set_state(gstreamer::State::Null).unwrap();
+    }
+
+    /// Record the GstForceKeyUnit events reaching `element`'s src pad from
+    /// downstream, by their all-headers field
+    fn watch_keyframe_requests(element: &gstreamer::Element) -> Arc<Mutex<Vec<bool>>> {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&seen);
+        element.static_pad("src").unwrap().add_probe(
+            gstreamer::PadProbeType::EVENT_UPSTREAM,
+            move |_, info| {
+                if let Some(event) = info.event() {
+                    if let Some(s) = event.structure().filter(|s| s.name() == "GstForceKeyUnit") {
+                        recorded
+                            .lock()
+                            .unwrap()
+                            .push(s.get::<bool>("all-headers").unwrap());
+                    }
+                }
+                gstreamer::PadProbeReturn::Ok
+            },
+        );
+        seen
+    }
+
+    #[test]
+    fn test_force_keyframe_travels_upstream() {
+        gstreamer::init().unwrap();
+        let event = force_keyframe_event();
+        assert_eq!(event.type_(), gstreamer::EventType::CustomUpstream);
+        assert!(event.is_upstream());
+
+        // identity stands in for the encoder between source and sink
+        let pipeline = launch("fakesrc is-live=true ! identity name=encoder ! fakesink name=sink");
+        let seen = watch_keyframe_requests(&pipeline.by_name("encoder").unwrap());
+
+        force_keyframe(&pipeline.by_name("sink").unwrap());
+        assert_eq!(*seen.lock().unwrap(), [true]);
+        pipeline.set_state(gstreamer::State::Null).unwrap();
+    }
+
+    #[test]
+    fn test_dump_dot() {
+        let pipeline = launch("fakesrc num-buffers=1 ! identity name=encoder ! fakesink");
+
+        // Nothing is written without --dump-dot
+        assert_eq!(dump_dot(&pipeline, "cam1"), None);
+
+        let dir = std::env::temp_dir().join(format!("dart-dot-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dump_dot_to(&pipeline, &dir, "cam1-error").unwrap();
+        let dot = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        assert_eq!(path, dir.join("cam1-error.dot"));
+        assert!(dot.starts_with("digraph"));
+        assert!(dot.contains("encoder"));
+        pipeline.set_state(gstreamer::State::Null).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs a GStreamer runtime with videotestsrc, x264enc and appsink"]
+    fn test_new_client_forces_keyframe_on_transcode() {
+        let config: SourceConfig = toml::from_str(
+            r#"
+            name = "bars"
+            type = "test"
+            [encode]
+            keyframe_interval = 300
+        "#,
+        )
+        .unwrap();
+        let pipeline = launch(&test::pipeline_string(&config, EncoderBackend::X264));
+        let seen = watch_keyframe_requests(&pipeline.by_name(ENCODER_NAME).unwrap());
+
+        let bus = Arc::new(FrameBus::default());
+        let frames = bus.subscribe();
+        let state = Arc::new(Mutex::new(SourceState::Live));
+        setup_appsink_callbacks(
+            &pipeline,
+            Arc::clone(&bus),
+            state,
+            Default::default(),
+            Default::default(),
+            true,
+            Box::new(|_| {}),
+        )
+        .unwrap();
+
+        // Let the first GOP start, then ask as a joining client would
+        frames.recv().unwrap();
+        bus.request_keyframe();
+        let keyframe = std::iter::from_fn(|| frames.recv().ok())
+            .take(30)
+            .skip(1)
+            .find(|f| f.is_keyframe);
+        assert!(
+            keyframe.is_some(),
+            "no keyframe within 30 frames of the request"
+        );
+        assert_eq!(*seen.lock().unwrap(), [true]);
+        pipeline.set_state(gstreamer::State::Null).unwrap();
+    }
+
+    #[test]
+    fn test_shutdown_waits_for_eos() {
+        let pipeline = launch("fakesrc is-live=true ! fakesink");
+        assert!(shutdown(&pipeline, EOS_TIMEOUT));
+        assert_eq!(pipeline.current_state(), gstreamer::State::Null);
+
+        // A closed valve swallows the EOS, so the pipeline is forced down
+        let stuck = launch("fakesrc is-live=true ! valve drop=true ! fakesink");
+        let started = Instant::now();
+        assert!(!shutdown(&stuck, Duration::from_millis(200)));
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!(stuck.current_state(), gstreamer::State::Null);
+    }
 }
@@ -0,0 +1,141 @@
+//! SRT source - receives MPEG-TS contribution feeds over SRT
+//!
+//! Passthrough:       srtsrc -> tsdemux -> h26Xparse -> appsink
+//! Transcode (x264):  srtsrc -> tsdemux -> h26Xparse -> avdec_h26X -> x264enc -> h264parse -> appsink
+//! Transcode (VAAPI): srtsrc -> tsdemux -> h26Xparse -> avdec_h26X -> vah264enc -> h264parse -> appsink
+//! Transcode (MPP):   srtsrc -> tsdemux -> h26Xparse -> mppvideodec -> mpph265enc -> h265parse -> appsink
+//!
+//! When transcoding, `max_width`/`max_height` add a videoscale after the decoder,
+//! and `deinterlace = true` a `deinterlace` ahead of it.
+
+use crate::config::{SourceCodec, SourceConfig};
+use anyhow::Result;
+
+use super::{
+    appsink_config, deinterlace_prefix, encode_branches, h264_caps, h265_caps, scale_prefix,
+    EncoderBackend,
+};
+
+/// Build the SRT source launch string
+pub(super) fn pipeline_string(config: &SourceConfig, backend: EncoderBackend) -> Result<String> {
+    let url = config
+        .url
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("SRT source requires 'url'"))?;
+
+    // srtsrc's own default receive latency is 125 ms
+    let latency = config.latency.unwrap_or(125);
+
+    // Build srtsrc with optional encryption and stream id
+    let mut srtsrc = format!("srtsrc uri=\"{}\" latency={}", url, latency);
+    if let Some(passphrase) = &config.passphrase {
+        srtsrc.push_str(&format!(" passphrase=\"{}\"", passphrase));
+    }
+    if let Some(streamid) = &config.streamid {
+        srtsrc.push_str(&format!(" streamid=\"{}\"", streamid));
+    }
+
+    let (parse, sw_decoder, source_caps) = match config.source_codec {
+        SourceCodec::H264 => ("h264parse", "avdec_h264", h264_caps()),
+        SourceCodec::H265 => ("h265parse", "avdec_h265", h265_caps()),
+    };
+
+    let pipeline_str = if config.transcode {
+        // MPP transcode: hardware decode + hardware H.265 encode
+        let (decoder, format) = if backend == EncoderBackend::Mpp {
+            ("mppvideodec", Some("NV12"))
+        } else {
+            (sw_decoder, None)
+        };
+
+        format!(
+            "{srtsrc} \
+             ! tsdemux \
+             ! {parse} \
+             ! {decoder} \
+             ! {deinterlace}{clamp}{encode}",
+            srtsrc = srtsrc,
+            parse = parse,
+            decoder = decoder,
+            deinterlace = deinterlace_prefix(config),
+            clamp = scale_prefix(config, None, None, format, true),
+            encode = encode_branches(config, backend),
+        )
+    } else {
+        // Passthrough - output codec matches the input codec
+        format!(
+            "{srtsrc} \
+             ! tsdemux \
+             ! {parse} \
+             ! {caps} \
+             ! {appsink}",
+            srtsrc = srtsrc,
+            parse = parse,
+            caps = source_caps,
+            appsink = appsink_config(),
+        )
+    };
+
+    Ok(pipeline_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(extra: &str) -> SourceConfig {
+        toml::from_str(&format!(
+            r#"
+            name = "feed1"
+            type = "srt"
+            url = "srt://ingest.local:9000"
+            {}
+        "#,
+            extra
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_passthrough_pipeline() {
+        let pipeline = pipeline_string(
+            &source(
+                r#"
+                latency = 300
+                passphrase = "0123456789"
+                streamid = "feed1"
+                "#,
+            ),
+            EncoderBackend::X264,
+        )
+        .unwrap();
+
+        assert!(pipeline.starts_with(
+            "srtsrc uri=\"srt://ingest.local:9000\" latency=300 passphrase=\"0123456789\" streamid=\"feed1\""
+        ));
+        assert!(pipeline.contains("! tsdemux ! h264parse ! video/x-h264"));
+        assert!(pipeline.ends_with(appsink_config()));
+    }
+
+    #[test]
+    fn test_transcode_pipeline() {
+        let config = source(
+            r#"
+            source_codec = "h265"
+            transcode = true
+            [encode]
+            "#,
+        );
+
+        let x264 = pipeline_string(&config, EncoderBackend::X264).unwrap();
+        assert!(x264.contains("tsdemux ! h265parse ! avdec_h265 ! videoconvert ! x264enc"));
+
+        let mpp = pipeline_string(&config, EncoderBackend::Mpp).unwrap();
+        assert!(mpp.contains("tsdemux ! h265parse ! mppvideodec ! mpph265enc"));
+
+        let config =
+            source("source_codec = \"h265\"\ntranscode = true\ndeinterlace = true\n[encode]");
+        let x264 = pipeline_string(&config, EncoderBackend::X264).unwrap();
+        assert!(x264.contains("avdec_h265 ! deinterlace method=greedyh ! videoconvert ! x264enc"));
+    }
+}
@@ -0,0 +1,118 @@
+//! Test source - synthetic video from videotestsrc for bring-up without hardware
+//!
+//! Pipeline (x264):  videotestsrc -> videoconvert -> x264enc -> h264parse -> appsink
+//! Pipeline (VAAPI): videotestsrc -> videoconvert -> vah264enc -> h264parse -> appsink
+//! Pipeline (MPP):   videotestsrc -> mpph265enc -> h265parse -> appsink
+
+use crate::config::SourceConfig;
+
+use super::{encode_branches, EncoderBackend};
+
+/// Patterns accepted by videotestsrc's `pattern` property
+pub const PATTERNS: &[&str] = &[
+    "smpte",
+    "snow",
+    "black",
+    "white",
+    "red",
+    "green",
+    "blue",
+    "checkers-1",
+    "checkers-2",
+    "checkers-4",
+    "checkers-8",
+    "circular",
+    "blink",
+    "smpte75",
+    "zone-plate",
+    "gamut",
+    "chroma-zone-plate",
+    "solid-color",
+    "ball",
+    "smpte100",
+    "bar",
+    "pinwheel",
+    "spokes",
+    "gradient",
+    "colors",
+];
+
+/// Build the test pattern launch string
+pub(super) fn pipeline_string(config: &SourceConfig, backend: EncoderBackend) -> String {
+    let pattern = config.pattern.as_deref().unwrap_or("smpte");
+
+    // MPP takes NV12 straight from videotestsrc, x264/VAAPI go through videoconvert
+    let mut caps_parts = Vec::new();
+    if backend == EncoderBackend::Mpp {
+        caps_parts.push("format=NV12".to_string());
+    }
+    if let Some(w) = config.width {
+        caps_parts.push(format!("width={}", w));
+    }
+    if let Some(h) = config.height {
+        caps_parts.push(format!("height={}", h));
+    }
+    if let Some(f) = config.framerate {
+        caps_parts.push(format!("framerate={}", f.fraction()));
+    }
+    let source_caps = if caps_parts.is_empty() {
+        String::new()
+    } else {
+        format!(" ! video/x-raw,{}", caps_parts.join(","))
+    };
+
+    format!(
+        "videotestsrc is-live=true pattern={pattern}{source_caps} \
+         ! {encode}",
+        pattern = pattern,
+        source_caps = source_caps,
+        encode = encode_branches(config, backend),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::{appsink_config, h264_caps, h265_caps};
+
+    fn source(extra: &str) -> SourceConfig {
+        toml::from_str(&format!(
+            r#"
+            name = "bars"
+            type = "test"
+            width = 1280
+            height = 720
+            framerate = 30
+            {}
+        "#,
+            extra
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_x264_pipeline() {
+        let pipeline = pipeline_string(&source(r#"pattern = "ball""#), EncoderBackend::X264);
+        assert!(pipeline.starts_with(
+            "videotestsrc is-live=true pattern=ball ! video/x-raw,width=1280,height=720,framerate=30/1"
+        ));
+        assert!(pipeline.contains("videoconvert ! x264enc"));
+        assert!(pipeline.ends_with(&format!(
+            "h264parse ! {} ! {}",
+            h264_caps(),
+            appsink_config()
+        )));
+    }
+
+    #[test]
+    fn test_mpp_pipeline_defaults_to_smpte() {
+        let pipeline = pipeline_string(&source(""), EncoderBackend::Mpp);
+        assert!(pipeline.contains("pattern=smpte ! video/x-raw,format=NV12,width=1280"));
+        assert!(pipeline.contains("mpph265enc"));
+        assert!(pipeline.ends_with(&format!(
+            "h265parse ! {} ! {}",
+            h265_caps(),
+            appsink_config()
+        )));
+    }
+}
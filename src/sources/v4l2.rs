@@ -1,28 +1,40 @@
 //! V4L2 source - captures from Video4Linux2 devices (webcams, capture cards)
 //!
 //! Pipeline (x264):  v4l2src -> videoconvert -> x264enc -> h264parse -> appsink
+//! Pipeline (VAAPI): v4l2src -> videoconvert -> vah264enc -> h264parse -> appsink
 //! Pipeline (MPP):   v4l2src -> mpph265enc -> h265parse -> appsink
+//!
+//! `format` pins what the device delivers. A raw format (`UYVY`, `YUY2`,
+//! ...) is requested as-is; `MJPG` requests the webcam's compressed mode
+//! and adds a `jpegdec`, which is usually the only way to get full size at
+//! full rate over USB 2.
+//!
+//! `max_width`/`max_height` clamp the encoded size; on MPP they add a
+//! videoscale, which the path otherwise avoids.
+//!
+//! `deinterlace = true` adds a `deinterlace` right after capture, for
+//! interlaced (1080i) signals from SDI converters and capture cards.
+//!
+//! The device is opened once, by the source; every client of the mount is
+//! fed from the appsink's frames, so single-opener capture cards still serve
+//! any number of viewers.
 
 use crate::config::SourceConfig;
 use anyhow::Result;
-use gstreamer::prelude::*;
-use tracing::debug;
 
-use super::{appsink_config, build_encoder_string, build_mpp_h265_encoder_string, h264_caps, h265_caps};
+use super::{
+    deinterlace_prefix, encode_branches, scale_prefix, scaler, size_fields, EncoderBackend,
+};
 
-/// Create V4L2 capture pipeline
-pub fn create_pipeline(config: &SourceConfig, mpp: bool) -> Result<gstreamer::Pipeline> {
+/// Build the V4L2 capture launch string
+pub(super) fn pipeline_string(config: &SourceConfig, backend: EncoderBackend) -> Result<String> {
     let device = config
         .device
         .as_ref()
         .ok_or_else(|| anyhow::anyhow!("V4L2 source requires 'device'"))?;
 
-    let encode = config.encode_config();
-
-    let pipeline_str = if mpp {
+    let pipeline_str = if backend == EncoderBackend::Mpp {
         // MPP path: NV12 caps, no videoconvert/videoscale, mpph265enc
-        let encoder = build_mpp_h265_encoder_string(&encode);
-
         let mut caps_parts = vec!["format=NV12".to_string()];
         if let Some(w) = config.width {
             caps_parts.push(format!("width={}", w));
@@ -31,76 +43,308 @@ pub fn create_pipeline(config: &SourceConfig, mpp: bool) -> Result<gstreamer::Pi
             caps_parts.push(format!("height={}", h));
         }
         if let Some(f) = config.framerate {
-            caps_parts.push(format!("framerate={}/1", f));
+            caps_parts.push(format!("framerate={}", f.fraction()));
         }
-        let source_caps = format!(" ! video/x-raw,{}", caps_parts.join(","));
+        // MJPEG is decoded and converted to the NV12 the encoder takes
+        let source_caps = match capture_caps(config) {
+            Some(caps) if is_jpeg(config) => format!(
+                "{} ! jpegdec ! videoconvert ! video/x-raw,{}",
+                caps,
+                caps_parts.join(",")
+            ),
+            _ => format!(" ! video/x-raw,{}", caps_parts.join(",")),
+        };
+
+        let clamp = if config.max_width.is_some() || config.max_height.is_some() {
+            let (w, h) = both_dimensions(config);
+            scale_prefix(config, w, h, Some("NV12"), true)
+        } else {
+            String::new()
+        };
 
         format!(
             "v4l2src device={device}{source_caps} \
-             ! {encoder} \
-             ! {h265_caps} \
-             ! h265parse \
-             ! {h265_caps} \
-             ! {appsink}",
+             ! {deinterlace}{clamp}{encode}",
             device = device,
             source_caps = source_caps,
-            encoder = encoder,
-            h265_caps = h265_caps(),
-            appsink = appsink_config(),
+            deinterlace = deinterlace_prefix(config),
+            clamp = clamp,
+            encode = encode_branches(config, backend),
         )
     } else {
-        // x264 path (existing behavior)
-        let encoder = build_encoder_string(&encode);
-
-        // Build source caps - if format is specified (for capture cards like TC358743),
-        // use it with bt601 colorimetry. Otherwise let device negotiate freely.
-        let source_caps = if let Some(format) = &config.format {
-            let mut caps_parts = vec![format!("format={}", format)];
-            if let Some(w) = config.width {
-                caps_parts.push(format!("width={}", w));
-            }
-            if let Some(h) = config.height {
-                caps_parts.push(format!("height={}", h));
-            }
-            caps_parts.push("colorimetry=bt601".to_string());
-            format!(" ! video/x-raw,{}", caps_parts.join(","))
-        } else {
-            String::new()
-        };
+        // x264 or VAAPI path. Without a format the device negotiates freely.
+        let mut source_caps = capture_caps(config).unwrap_or_default();
+        if is_jpeg(config) {
+            source_caps.push_str(" ! jpegdec");
+        }
 
-        // Build output caps for after conversion
-        let output_caps = match (config.width, config.height, config.framerate) {
-            (Some(w), Some(h), Some(f)) => {
-                format!("video/x-raw,width={},height={},framerate={}/1", w, h, f)
-            }
-            (Some(w), Some(h), None) => format!("video/x-raw,width={},height={}", w, h),
-            _ => String::from("video/x-raw"),
-        };
+        // Build output caps for after conversion. The framerate applies even
+        // without a size, since the mount advertises it.
+        let (w, h) = both_dimensions(config);
+        let mut output_caps = String::from("video/x-raw");
+        for field in size_fields(config, w, h, true) {
+            output_caps.push_str(&format!(",{}", field));
+        }
+        if let Some(f) = config.framerate {
+            output_caps.push_str(&format!(",framerate={}", f.fraction()));
+        }
 
         format!(
             "v4l2src device={device}{source_caps} \
-             ! videoconvert \
-             ! videoscale \
+             ! {deinterlace}videoconvert \
+             ! {scaler} \
              ! {output_caps} \
-             ! {encoder} \
-             ! {h264_caps} \
-             ! h264parse \
-             ! {h264_caps} \
-             ! {appsink}",
+             ! {encode}",
             device = device,
             source_caps = source_caps,
+            deinterlace = deinterlace_prefix(config),
+            scaler = scaler(config, w, h, true),
             output_caps = output_caps,
-            encoder = encoder,
-            h264_caps = h264_caps(),
-            appsink = appsink_config(),
+            encode = encode_branches(config, backend),
         )
     };
 
-    debug!("V4L2 pipeline: {}", pipeline_str);
+    Ok(pipeline_str)
+}
+
+/// Whether `format` selects the device's MJPEG mode
+fn is_jpeg(config: &SourceConfig) -> bool {
+    config.pixel_format() == Some("MJPG")
+}
+
+/// Caps pinning what the device delivers, from `format` (None lets it
+/// negotiate). Raw formats get bt601 colorimetry, which capture cards like
+/// the TC358743 need; MJPEG also pins the framerate, since webcams only
+/// reach full rate in that mode.
+pub(super) fn capture_caps(config: &SourceConfig) -> Option<String> {
+    let format = config.pixel_format()?;
+    let jpeg = is_jpeg(config);
+
+    let mut caps_parts = if jpeg {
+        vec!["image/jpeg".to_string()]
+    } else {
+        vec!["video/x-raw".to_string(), format!("format={}", format)]
+    };
+    if let Some(w) = config.width {
+        caps_parts.push(format!("width={}", w));
+    }
+    if let Some(h) = config.height {
+        caps_parts.push(format!("height={}", h));
+    }
+    if jpeg {
+        if let Some(f) = config.framerate {
+            caps_parts.push(format!("framerate={}", f.fraction()));
+        }
+    } else {
+        caps_parts.push("colorimetry=bt601".to_string());
+    }
+    Some(format!(" ! {}", caps_parts.join(",")))
+}
+
+/// The configured output size, only when both dimensions are set
+fn both_dimensions(config: &SourceConfig) -> (Option<u32>, Option<u32>) {
+    match (config.width, config.height) {
+        (Some(w), Some(h)) => (Some(w), Some(h)),
+        _ => (None, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::{appsink_config, h264_caps, h265_caps};
+
+    fn source(extra: &str) -> SourceConfig {
+        toml::from_str(&format!(
+            r#"
+            name = "cam1"
+            type = "v4l2"
+            device = "/dev/video0"
+            {}
+        "#,
+            extra
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_requires_device() {
+        let mut config = source("[encode]");
+        config.device = None;
+        assert!(pipeline_string(&config, EncoderBackend::X264).is_err());
+    }
+
+    #[test]
+    fn test_x264_pipeline() {
+        // Without a format the device negotiates freely
+        let config = source("width = 1280\nheight = 720\nframerate = 30\n[encode]");
+        let pipeline = pipeline_string(&config, EncoderBackend::X264).unwrap();
+        assert!(pipeline.starts_with(
+            "v4l2src device=/dev/video0 ! videoconvert ! videoscale ! video/x-raw,width=1280,height=720,framerate=30/1 ! "
+        ));
+        assert!(pipeline.ends_with(&format!(
+            "h264parse ! {} ! {}",
+            h264_caps(),
+            appsink_config()
+        )));
+
+        // Capture cards pin the format with bt601 colorimetry
+        let config = source("format = \"UYVY\"\nwidth = 1920\nheight = 1080\n[encode]");
+        let pipeline = pipeline_string(&config, EncoderBackend::X264).unwrap();
+        assert!(pipeline.starts_with(
+            "v4l2src device=/dev/video0 ! video/x-raw,format=UYVY,width=1920,height=1080,colorimetry=bt601 ! videoconvert"
+        ));
+        assert!(pipeline.contains("! video/x-raw,width=1920,height=1080 ! "));
+
+        // NTSC rates keep their exact fraction
+        let config = source("framerate = \"30000/1001\"\n[encode]");
+        let pipeline = pipeline_string(&config, EncoderBackend::X264).unwrap();
+        assert!(pipeline.contains("! video/x-raw,framerate=30000/1001 ! "));
+        let pipeline = pipeline_string(&config, EncoderBackend::Mpp).unwrap();
+        assert!(pipeline.starts_with(
+            "v4l2src device=/dev/video0 ! video/x-raw,format=NV12,framerate=30000/1001 ! "
+        ));
+    }
+
+    #[test]
+    fn test_webcam_mjpeg_input() {
+        let config =
+            source("format = \"MJPG\"\nwidth = 1920\nheight = 1080\nframerate = 30\n[encode]");
+        let pipeline = pipeline_string(&config, EncoderBackend::X264).unwrap();
+        assert!(pipeline.starts_with(
+            "v4l2src device=/dev/video0 ! image/jpeg,width=1920,height=1080,framerate=30/1 ! jpegdec ! videoconvert ! videoscale ! video/x-raw,width=1920,height=1080,framerate=30/1 ! "
+        ));
+        assert!(!pipeline.contains("colorimetry"));
+
+        // Spelled any way, without a size the webcam picks one
+        let config = source("format = \"mjpeg\"\n[encode]");
+        let pipeline = pipeline_string(
+            &config,
+            EncoderBackend::Vaapi(crate::sources::VaapiElement::Va),
+        )
+        .unwrap();
+        assert!(pipeline
+            .starts_with("v4l2src device=/dev/video0 ! image/jpeg ! jpegdec ! videoconvert"));
+
+        // MPP decodes and converts to the NV12 its encoder takes
+        let config = source("format = \"MJPG\"\nwidth = 1280\nheight = 720\n[encode]");
+        let pipeline = pipeline_string(&config, EncoderBackend::Mpp).unwrap();
+        assert!(pipeline.starts_with(
+            "v4l2src device=/dev/video0 ! image/jpeg,width=1280,height=720 ! jpegdec ! videoconvert ! video/x-raw,format=NV12,width=1280,height=720 ! mpph265enc"
+        ));
+    }
+
+    #[test]
+    fn test_webcam_raw_input() {
+        let config =
+            source("format = \"YUY2\"\nwidth = 640\nheight = 480\nframerate = 30\n[encode]");
+        let pipeline = pipeline_string(&config, EncoderBackend::X264).unwrap();
+        assert!(pipeline.starts_with(
+            "v4l2src device=/dev/video0 ! video/x-raw,format=YUY2,width=640,height=480,colorimetry=bt601 ! videoconvert ! videoscale ! video/x-raw,width=640,height=480,framerate=30/1 ! "
+        ));
+        assert!(!pipeline.contains("jpegdec"));
+        assert_eq!(
+            capture_caps(&config).unwrap(),
+            " ! video/x-raw,format=YUY2,width=640,height=480,colorimetry=bt601"
+        );
+        assert_eq!(capture_caps(&source("[encode]")), None);
+
+        // The V4L2 name reaches the caps in GStreamer's spelling
+        assert_eq!(
+            capture_caps(&source("format = \"yuyv\"\n[encode]")).unwrap(),
+            " ! video/x-raw,format=YUY2,colorimetry=bt601"
+        );
+    }
+
+    #[test]
+    fn test_mpp_pipeline() {
+        let config = source("width = 1280\nheight = 720\nframerate = 30\n[encode]");
+        let pipeline = pipeline_string(&config, EncoderBackend::Mpp).unwrap();
+        assert!(pipeline.starts_with(
+            "v4l2src device=/dev/video0 ! video/x-raw,format=NV12,width=1280,height=720,framerate=30/1 ! mpph265enc"
+        ));
+        assert!(!pipeline.contains("videoconvert"));
+        assert!(pipeline.ends_with(&format!(
+            "h265parse ! {} ! {}",
+            h265_caps(),
+            appsink_config()
+        )));
+    }
+
+    #[test]
+    fn test_overlay_before_encoder() {
+        let config = source(
+            r#"
+            [encode]
+            [overlay]
+            text = "Lobby"
+            show_timestamp = true
+            position = "bottom-right"
+        "#,
+        );
+
+        let x264 = pipeline_string(&config, EncoderBackend::X264).unwrap();
+        assert!(x264.contains(
+            "! video/x-raw ! clockoverlay time-format=\"%Y-%m-%d %H:%M:%S\" valignment=bottom halignment=right"
+        ));
+        assert!(x264.contains("text=\"Lobby\" ! videoconvert ! x264enc"));
+
+        // Hardware encoders get the overlay on raw NV12 frames too
+        let mpp = pipeline_string(&config, EncoderBackend::Mpp).unwrap();
+        assert!(mpp.contains("format=NV12 ! clockoverlay"));
+        assert!(mpp.contains("text=\"Lobby\" ! mpph265enc"));
+    }
+
+    #[test]
+    fn test_deinterlace() {
+        let config =
+            source("format = \"UYVY\"\nwidth = 1920\nheight = 1080\ndeinterlace = true\n[encode]");
+        let x264 = pipeline_string(&config, EncoderBackend::X264).unwrap();
+        assert!(x264.contains(
+            "colorimetry=bt601 ! deinterlace method=greedyh ! videoconvert ! videoscale ! "
+        ));
+
+        let config = source("deinterlace = true\ndeinterlace_method = \"yadif\"\n[encode]");
+        let mpp = pipeline_string(&config, EncoderBackend::Mpp).unwrap();
+        assert!(mpp.starts_with("v4l2src device=/dev/video0 ! video/x-raw,format=NV12 ! deinterlace method=yadif ! mpph265enc"));
+
+        // Off unless asked for
+        let x264 = pipeline_string(&source("[encode]"), EncoderBackend::X264).unwrap();
+        assert!(!x264.contains("deinterlace"));
+    }
+
+    #[test]
+    fn test_max_size_clamp() {
+        gstreamer::init().unwrap();
+        // A configured size above the limit is scaled down, keeping 16:9;
+        // the card still captures at its native size
+        let config = source("format = \"NV12\"\nwidth = 7680\nheight = 4320\nmax_width = 1920\nmax_height = 1080\n[encode]");
+        let pipeline = pipeline_string(&config, EncoderBackend::X264).unwrap();
+        assert!(pipeline.contains("format=NV12,width=7680,height=4320,colorimetry=bt601 ! videoconvert ! videoscale ! video/x-raw,width=1920,height=1080 ! "));
+
+        // A negotiated size is bounded by ranges on a named scaler
+        let config = source("framerate = 30\nmax_width = 1920\nmax_height = 1080\n[encode]");
+        let pipeline = pipeline_string(&config, EncoderBackend::X264).unwrap();
+        assert!(pipeline.contains("! videoscale name=clamp ! video/x-raw,width=(int)[1,1920],height=(int)[1,1080],framerate=30/1 ! "));
 
-    let pipeline = gstreamer::parse::launch(&pipeline_str)?
-        .downcast::<gstreamer::Pipeline>()
-        .map_err(|_| anyhow::anyhow!("Failed to create pipeline"))?;
+        let caps: gstreamer::Caps = "video/x-raw,width=(int)[1,1920],height=(int)[1,1080]"
+            .parse()
+            .unwrap();
+        let s = caps.structure(0).unwrap();
+        assert_eq!(
+            s.get::<gstreamer::IntRange<i32>>("width").unwrap().max(),
+            1920
+        );
+        assert_eq!(
+            s.get::<gstreamer::IntRange<i32>>("height").unwrap().max(),
+            1080
+        );
 
-    Ok(pipeline)
+        // MPP only gets a videoscale when a limit is set
+        let pipeline = pipeline_string(&config, EncoderBackend::Mpp).unwrap();
+        assert!(pipeline.contains("framerate=30/1 ! videoscale name=clamp ! video/x-raw,format=NV12,width=(int)[1,1920],height=(int)[1,1080] ! mpph265enc"));
+        let config = source("width = 3840\nheight = 2160\nmax_width = 1280\n[encode]");
+        let pipeline = pipeline_string(&config, EncoderBackend::Mpp).unwrap();
+        assert!(pipeline.contains("width=3840,height=2160 ! videoscale ! video/x-raw,format=NV12,width=1280,height=720 ! mpph265enc"));
+    }
 }
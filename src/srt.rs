@@ -0,0 +1,186 @@
+//! SRT output - publishes a source's encoded frames as MPEG-TS over SRT
+//!
+//! Pipeline: appsrc -> h26Xparse -> mpegtsmux -> srtsink
+//!
+//! `srt_listen = PORT` runs srtsink as a listener that clients connect to;
+//! `srt_url = "srt://host:port"` runs it as a caller that pushes to an ingest.
+
+use crate::config::{OutputCodec, SourceConfig};
+use crate::rtsp::{FrameReceiver, StreamKind};
+use anyhow::{Context, Result};
+use gstreamer::prelude::*;
+use gstreamer_app::AppSrc;
+use tracing::{debug, info, warn};
+
+/// Where a source's SRT output goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SrtTarget {
+    /// Accept connections on a local port
+    Listener { port: u16 },
+    /// Connect out to a remote listener
+    Caller { url: String },
+}
+
+impl SrtTarget {
+    /// SRT target configured for a source, if any
+    pub fn from_config(config: &SourceConfig) -> Option<Self> {
+        if let Some(port) = config.srt_listen {
+            Some(Self::Listener { port })
+        } else {
+            config.srt_url.clone().map(|url| Self::Caller { url })
+        }
+    }
+
+    /// URL to print in the stream list
+    pub fn display_url(&self, host: &str) -> String {
+        match self {
+            Self::Listener { port } => format!("srt://{}:{}", host, port),
+            Self::Caller { url } => url.clone(),
+        }
+    }
+}
+
+/// Start an SRT output for a source, fed from `frames`. A listener binds to
+/// `host`, the server's bind address as a URL host (IPv6 in brackets).
+pub fn start(
+    name: &str,
+    codec: OutputCodec,
+    target: &SrtTarget,
+    host: &str,
+    mut frames: FrameReceiver,
+) -> Result<()> {
+    let pipeline_str = pipeline_string(codec, target, host);
+    debug!(
+        "SRT pipeline for '{}': {}",
+        name,
+        crate::logging::redact_pipeline(&pipeline_str)
+    );
+
+    let pipeline = gstreamer::parse::launch(&pipeline_str)
+        .context("Failed to create SRT pipeline")?
+        .downcast::<gstreamer::Pipeline>()
+        .map_err(|_| anyhow::anyhow!("Failed to downcast to Pipeline"))?;
+
+    let appsrc = pipeline
+        .by_name("videosrc")
+        .ok_or_else(|| anyhow::anyhow!("SRT pipeline missing 'videosrc' element"))?
+        .dynamic_cast::<AppSrc>()
+        .map_err(|_| anyhow::anyhow!("Failed to cast to AppSrc"))?;
+
+    pipeline
+        .set_state(gstreamer::State::Playing)
+        .map_err(|e| anyhow::anyhow!("Failed to start SRT pipeline: {:?}", e))?;
+
+    info!(
+        "Started SRT output for '{}': {}",
+        name,
+        target.display_url(host)
+    );
+
+    let name = name.to_string();
+    std::thread::spawn(move || {
+        // Receivers can only start decoding at a keyframe
+        while let Ok(frame) = frames.recv_from_keyframe() {
+            // Video only; forwarded audio is not muxed into the transport stream
+            if frame.kind != StreamKind::Video {
+                continue;
+            }
+
+            if let Err(e) = appsrc.push_buffer(frame.output_buffer()) {
+                warn!("SRT output for '{}' stopped: {:?}", name, e);
+                break;
+            }
+        }
+
+        pipeline.set_state(gstreamer::State::Null).ok();
+        debug!("SRT output for '{}' ended", name);
+    });
+
+    Ok(())
+}
+
+/// Build the SRT output launch string
+fn pipeline_string(codec: OutputCodec, target: &SrtTarget, host: &str) -> String {
+    let (caps, parse) = match codec {
+        OutputCodec::H264 => (crate::sources::h264_caps(), "h264parse"),
+        OutputCodec::H265 => (crate::sources::h265_caps(), "h265parse"),
+    };
+
+    // wait-for-connection=false keeps a listener without clients (or a caller
+    // whose peer is down) from blocking the pusher thread
+    let sink = match target {
+        SrtTarget::Listener { port } => format!(
+            "srtsink uri=\"srt://{}:{}\" mode=listener wait-for-connection=false sync=false",
+            host, port
+        ),
+        SrtTarget::Caller { url } => format!(
+            "srtsink uri=\"{}\" mode=caller wait-for-connection=false sync=false",
+            url
+        ),
+    };
+
+    // config-interval=-1 repeats SPS/PPS so late joiners can decode
+    format!(
+        "appsrc name=videosrc is-live=true format=time do-timestamp=true caps={caps} \
+         ! {parse} config-interval=-1 \
+         ! mpegtsmux alignment=7 \
+         ! {sink}",
+        caps = caps,
+        parse = parse,
+        sink = sink,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(extra: &str) -> SourceConfig {
+        toml::from_str(&format!(
+            r#"
+            name = "cam1"
+            type = "rtsp"
+            url = "rtsp://camera.local/stream"
+            {}
+        "#,
+            extra
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_target_from_config() {
+        assert_eq!(SrtTarget::from_config(&source("")), None);
+        assert_eq!(
+            SrtTarget::from_config(&source("srt_listen = 9000")),
+            Some(SrtTarget::Listener { port: 9000 })
+        );
+        assert_eq!(
+            SrtTarget::from_config(&source(r#"srt_url = "srt://cdn.example.com:9000""#)),
+            Some(SrtTarget::Caller {
+                url: "srt://cdn.example.com:9000".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_pipeline_string() {
+        let listener = pipeline_string(
+            OutputCodec::H264,
+            &SrtTarget::Listener { port: 9000 },
+            "0.0.0.0",
+        );
+        assert!(listener.contains("! h264parse config-interval=-1 ! mpegtsmux"));
+        assert!(listener.contains("srtsink uri=\"srt://0.0.0.0:9000\" mode=listener"));
+
+        let caller = pipeline_string(
+            OutputCodec::H265,
+            &SrtTarget::Caller {
+                url: "srt://cdn.example.com:9000".to_string(),
+            },
+            "0.0.0.0",
+        );
+        assert!(caller.contains("caps=video/x-h265"));
+        assert!(caller.contains("srtsink uri=\"srt://cdn.example.com:9000\" mode=caller"));
+    }
+}
@@ -0,0 +1,246 @@
+//! JSON status endpoint for supervisors and load balancers
+//!
+//! `GET /status` returns the RTSP port and every source with its state, uptime and start time,
+//! last error, reconnect and restart counts and number of RTSP viewers. The response is HTTP 503 when any source
+//! has been stuck in fallback longer than `[server] unhealthy_after` seconds.
+
+use crate::config::SourceType;
+use crate::http::{Request, Response};
+use crate::sources::{Source, SourceState};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Status of one source
+#[derive(Debug, Serialize)]
+pub struct SourceStatus {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub source_type: SourceType,
+    pub state: SourceState,
+    /// Seconds the source has been up (see `uptime_across_reconnects`)
+    pub uptime_secs: Option<u64>,
+    /// Unix time `uptime_secs` counts from
+    pub started_at: Option<u64>,
+    pub last_error: Option<String>,
+    pub reconnect_count: u64,
+    /// Times the source's thread panicked and was restarted
+    pub restart_count: u64,
+    /// RTSP clients playing the source's mounts
+    pub viewers: usize,
+    #[serde(skip)]
+    pub fallback_duration: Option<Duration>,
+}
+
+impl SourceStatus {
+    /// Snapshot a running source
+    pub fn from_source(source: &Source) -> Self {
+        let uptime = source.uptime();
+        Self {
+            name: source.name().to_string(),
+            source_type: source.source_type(),
+            state: source.state(),
+            uptime_secs: uptime.map(|d| d.as_secs()),
+            started_at: uptime
+                .and_then(|d| SystemTime::now().checked_sub(d))
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+            last_error: source.last_error(),
+            reconnect_count: source.reconnect_count(),
+            restart_count: source.restart_count(),
+            viewers: source.viewers(),
+            fallback_duration: source.fallback_duration(),
+        }
+    }
+}
+
+/// Full status report
+#[derive(Debug, Serialize)]
+struct StatusReport {
+    healthy: bool,
+    /// Port the RTSP server listens on, also when the OS picked it
+    rtsp_port: u16,
+    sources: Vec<SourceStatus>,
+}
+
+/// Handle a request to the status endpoint
+pub fn handle_request(
+    sources: &[Arc<Source>],
+    rtsp_port: u16,
+    unhealthy_after: Duration,
+    request: &Request,
+) -> Response {
+    if request.path != "/status" {
+        return Response::not_found();
+    }
+    if request.method != "GET" {
+        return Response::text(405, "Method Not Allowed");
+    }
+
+    let statuses = sources
+        .iter()
+        .map(|s| SourceStatus::from_source(s))
+        .collect();
+    report(statuses, rtsp_port, unhealthy_after)
+}
+
+/// Build the JSON response, 503 if any source has been in fallback too long
+fn report(sources: Vec<SourceStatus>, rtsp_port: u16, unhealthy_after: Duration) -> Response {
+    let healthy = !sources
+        .iter()
+        .any(|s| s.fallback_duration.is_some_and(|d| d >= unhealthy_after));
+
+    let report = StatusReport {
+        healthy,
+        rtsp_port,
+        sources,
+    };
+    let body = serde_json::to_vec(&report).unwrap_or_default();
+
+    let mut response = Response::ok("application/json", body);
+    if !healthy {
+        response.status = 503;
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rtsp::FrameBus;
+    use crate::sources::EncoderBackend;
+
+    fn status(name: &str, state: SourceState, fallback_secs: Option<u64>) -> SourceStatus {
+        SourceStatus {
+            name: name.to_string(),
+            source_type: SourceType::Rtsp,
+            state,
+            uptime_secs: None,
+            started_at: None,
+            last_error: None,
+            reconnect_count: 0,
+            restart_count: 0,
+            viewers: 0,
+            fallback_duration: fallback_secs.map(Duration::from_secs),
+        }
+    }
+
+    #[test]
+    fn test_status_json_shape() {
+        let config = toml::from_str(
+            r#"
+            name = "cam1"
+            type = "rtsp"
+            url = "rtsp://camera.local/stream"
+        "#,
+        )
+        .unwrap();
+        let source = Arc::new(
+            Source::new(
+                config,
+                Arc::new(FrameBus::default()),
+                None,
+                EncoderBackend::X264,
+            )
+            .unwrap(),
+        );
+        let request = Request {
+            method: "GET".to_string(),
+            path: "/status".to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        };
+
+        let response = handle_request(&[source], 8554, Duration::from_secs(60), &request);
+        assert_eq!(response.status, 200);
+
+        let json: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(json["healthy"], true);
+        assert_eq!(json["rtsp_port"], 8554);
+        let source = &json["sources"][0];
+        assert_eq!(source["name"], "cam1");
+        assert_eq!(source["type"], "rtsp");
+        assert_eq!(source["state"], "stopped");
+        assert_eq!(source["uptime_secs"], serde_json::Value::Null);
+        assert_eq!(source["started_at"], serde_json::Value::Null);
+        assert_eq!(source["last_error"], serde_json::Value::Null);
+        assert_eq!(source["reconnect_count"], 0);
+        assert_eq!(source["restart_count"], 0);
+        assert_eq!(source["viewers"], 0);
+    }
+
+    #[test]
+    fn test_status_served_over_http() {
+        use crate::http::{serve, Bind};
+        use std::io::{Read, Write};
+
+        let config = toml::from_str(
+            r#"
+            name = "cam1"
+            type = "rtsp"
+            url = "rtsp://camera.local/stream"
+        "#,
+        )
+        .unwrap();
+        let sources = vec![Arc::new(
+            Source::new(
+                config,
+                Arc::new(FrameBus::default()),
+                None,
+                EncoderBackend::X264,
+            )
+            .unwrap(),
+        )];
+
+        let path = std::env::temp_dir().join(format!("dart-status-{}.sock", std::process::id()));
+        serve(
+            &Bind::Unix(path.clone()),
+            Arc::new(move |request| {
+                handle_request(&sources, 8554, Duration::from_secs(60), request)
+            }),
+        )
+        .unwrap();
+
+        let get = |target: &str| {
+            let mut stream = std::os::unix::net::UnixStream::connect(&path).unwrap();
+            write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+
+        let response = get("/status");
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+        assert!(head.contains("Content-Type: application/json"), "{}", head);
+        let json: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(json["healthy"], true);
+        assert_eq!(json["sources"][0]["name"], "cam1");
+        assert_eq!(json["sources"][0]["state"], "stopped");
+
+        assert!(get("/nope").starts_with("HTTP/1.1 404"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_long_fallback_is_unhealthy() {
+        let threshold = Duration::from_secs(60);
+
+        let response = report(
+            vec![status("cam1", SourceState::Fallback, Some(10))],
+            8554,
+            threshold,
+        );
+        assert_eq!(response.status, 200);
+
+        let response = report(
+            vec![
+                status("cam1", SourceState::Live, None),
+                status("cam2", SourceState::Fallback, Some(90)),
+            ],
+            8554,
+            threshold,
+        );
+        assert_eq!(response.status, 503);
+    }
+}
@@ -0,0 +1,487 @@
+//! WebRTC output for browsers, signalled with WHEP on the HTTP API
+//!
+//! `POST /whep/{name}` with an SDP offer starts a viewer and answers
+//! `201 Created` with the SDP answer and the viewer's `Location`;
+//! `DELETE` on that location stops it. ICE candidates are gathered before
+//! answering, so clients don't need trickle ICE.
+//!
+//! Pipeline per viewer: appsrc -> h26Xparse -> rtph26Xpay -> webrtcbin
+//!
+//! The source's encoded stream is sent as is, so the browser must accept
+//! the source's output codec.
+//!
+//! A source with `[sources.auth]` enabled requires the same credentials as
+//! HTTP Basic auth on both the offer and the `DELETE`.
+
+use crate::config::OutputCodec;
+use crate::http::{source_authorized, Request, Response};
+use crate::rtsp::{FrameBus, StreamKind};
+use crate::sources::Source;
+use anyhow::{Context, Result};
+use gstreamer::prelude::*;
+use gstreamer_app::AppSrc;
+use gstreamer_webrtc::{
+    WebRTCICEGatheringState, WebRTCPeerConnectionState, WebRTCSDPType, WebRTCSessionDescription,
+};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// Path prefix routed to this module
+pub const PREFIX: &str = "/whep";
+
+/// How long to wait for ICE candidates before answering with what we have
+const GATHER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Interval between ICE gathering state checks
+const GATHER_POLL: Duration = Duration::from_millis(50);
+
+/// How often a viewer thread checks whether it was stopped
+const STOP_POLL: Duration = Duration::from_millis(500);
+
+/// Whether a request path belongs to this module
+pub fn handles(path: &str) -> bool {
+    path.strip_prefix(PREFIX)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Running WebRTC viewers, shared by the HTTP API threads
+#[derive(Default)]
+pub struct Sessions {
+    next_id: AtomicU64,
+    /// Source name and stop flag of each viewer, by id
+    running: Mutex<HashMap<u64, (String, Arc<AtomicBool>)>>,
+}
+
+impl Sessions {
+    /// Stop a viewer of source `name`. Returns whether it existed.
+    fn stop(&self, name: &str, id: u64) -> bool {
+        let mut running = self.running.lock().unwrap();
+        if running.get(&id).is_none_or(|(source, _)| source != name) {
+            return false;
+        }
+        if let Some((_, running)) = running.remove(&id) {
+            running.store(false, Ordering::SeqCst);
+        }
+        true
+    }
+}
+
+/// What a WHEP path addresses
+#[derive(Debug, PartialEq, Eq)]
+enum Route<'a> {
+    /// `/whep/{name}`: offers for a source are posted here
+    Source(&'a str),
+    /// `/whep/{name}/{id}`: one running viewer
+    Session(&'a str, u64),
+}
+
+fn route(path: &str) -> Option<Route<'_>> {
+    let rest = path.strip_prefix(PREFIX)?.strip_prefix('/')?;
+    match rest.split_once('/') {
+        None if !rest.is_empty() => Some(Route::Source(rest)),
+        Some((name, id)) if !name.is_empty() => Some(Route::Session(name, id.parse().ok()?)),
+        _ => None,
+    }
+}
+
+/// Handle a request under `/whep`
+pub fn handle_request(
+    sessions: &Arc<Sessions>,
+    sources: &[Arc<Source>],
+    request: &Request,
+) -> Response {
+    let response = match (request.method.as_str(), route(&request.path)) {
+        (_, None) => Response::not_found(),
+        // CORS preflight, so dashboards served from elsewhere can post offers
+        ("OPTIONS", Some(_)) => Response::text(204, "")
+            .with_header("Access-Control-Allow-Methods", "POST, DELETE, OPTIONS")
+            .with_header(
+                "Access-Control-Allow-Headers",
+                "Content-Type, Authorization",
+            ),
+        ("POST", Some(Route::Source(name))) => match find(sources, name) {
+            Some(source) if !source_authorized(source.config().auth.as_ref(), request) => {
+                Response::unauthorized()
+            }
+            Some(source) => offer(sessions, source, request),
+            None => Response::not_found(),
+        },
+        ("DELETE", Some(Route::Session(name, id))) => match find(sources, name) {
+            Some(source) if !source_authorized(source.config().auth.as_ref(), request) => {
+                Response::unauthorized()
+            }
+            Some(_) if sessions.stop(name, id) => {
+                info!("WebRTC viewer {} of '{}' stopped by client", id, name);
+                Response::text(200, "OK")
+            }
+            _ => Response::not_found(),
+        },
+        _ => Response::text(405, "Method Not Allowed"),
+    };
+    response
+        .with_header("Access-Control-Allow-Origin", "*")
+        .with_header("Access-Control-Expose-Headers", "Location")
+}
+
+/// The source served at `/whep/{name}`, if it has WebRTC on
+fn find<'a>(sources: &'a [Arc<Source>], name: &str) -> Option<&'a Arc<Source>> {
+    sources
+        .iter()
+        .find(|s| s.name() == name && s.config().webrtc)
+}
+
+/// Answer an SDP offer and start streaming to the new viewer
+fn offer(sessions: &Arc<Sessions>, source: &Source, request: &Request) -> Response {
+    let is_sdp = request
+        .header("content-type")
+        .is_some_and(|t| t.starts_with("application/sdp"));
+    if !is_sdp {
+        return Response::text(415, "Offer must be application/sdp");
+    }
+    let Ok(offer) = std::str::from_utf8(&request.body) else {
+        return Response::text(400, "Offer is not UTF-8");
+    };
+
+    let codec = source.output_codec();
+    let payload = match offered_payload(offer, codec) {
+        Ok(payload) => payload,
+        Err(e) => return Response::text(400, &e.to_string()),
+    };
+
+    match start_viewer(
+        sessions,
+        source.name(),
+        source.frame_bus(),
+        codec,
+        payload,
+        offer,
+    ) {
+        Ok((id, answer)) => {
+            let location = format!("{}/{}/{}", PREFIX, source.name(), id);
+            let mut response = Response::ok("application/sdp", answer.into_bytes());
+            response.status = 201;
+            response.with_header("Location", &location)
+        }
+        Err(e) => {
+            warn!("WebRTC offer for '{}' failed: {:#}", source.name(), e);
+            Response::text(500, "Failed to answer offer")
+        }
+    }
+}
+
+/// RTP encoding name the offer must accept for `codec`
+fn encoding_name(codec: OutputCodec) -> &'static str {
+    match codec {
+        OutputCodec::H264 => "H264",
+        OutputCodec::H265 => "H265",
+    }
+}
+
+/// Pick the payload type to send `codec` with from the offer's video section.
+/// For H.264 a payload with `packetization-mode=1` is preferred, since the
+/// payloader aggregates NAL units that mode 0 can't carry.
+fn offered_payload(offer: &str, codec: OutputCodec) -> Result<u8> {
+    let encoding = encoding_name(codec);
+    let mut in_video = false;
+    let mut seen_video = false;
+    let mut payloads = Vec::new();
+    let mut mode1 = Vec::new();
+
+    for line in offer.lines().map(str::trim) {
+        if let Some(media) = line.strip_prefix("m=") {
+            // Only the first video section is answered
+            in_video = !seen_video && media.starts_with("video ");
+            seen_video |= in_video;
+            continue;
+        }
+        if !in_video {
+            continue;
+        }
+        if let Some((payload, format)) = line
+            .strip_prefix("a=rtpmap:")
+            .and_then(|a| a.split_once(' '))
+        {
+            let name = format.split('/').next().unwrap_or_default();
+            if name.eq_ignore_ascii_case(encoding) {
+                payloads.extend(payload.parse::<u8>().ok());
+            }
+        } else if let Some((payload, params)) =
+            line.strip_prefix("a=fmtp:").and_then(|a| a.split_once(' '))
+        {
+            if params
+                .split(';')
+                .any(|p| p.trim() == "packetization-mode=1")
+            {
+                mode1.extend(payload.parse::<u8>().ok());
+            }
+        }
+    }
+
+    if !seen_video {
+        anyhow::bail!("Offer has no video section");
+    }
+    payloads
+        .iter()
+        .find(|payload| codec != OutputCodec::H264 || mode1.contains(payload))
+        .or(payloads.first())
+        .copied()
+        .ok_or_else(|| anyhow::anyhow!("Offer does not accept {} video", encoding))
+}
+
+/// Build the launch string for one viewer, sending with `payload`
+fn pipeline_string(codec: OutputCodec, payload: u8) -> String {
+    let (caps, parse, pay) = match codec {
+        OutputCodec::H264 => (crate::sources::h264_caps(), "h264parse", "rtph264pay"),
+        OutputCodec::H265 => (crate::sources::h265_caps(), "h265parse", "rtph265pay"),
+    };
+
+    format!(
+        "appsrc name=videosrc is-live=true format=time do-timestamp=true caps={caps} \
+         ! {parse} \
+         ! {pay} config-interval=-1 aggregate-mode=zero-latency \
+         ! application/x-rtp,media=video,encoding-name={encoding},payload={payload},clock-rate=90000 \
+         ! webrtcbin name=webrtc bundle-policy=max-bundle",
+        caps = caps,
+        parse = parse,
+        pay = pay,
+        encoding = encoding_name(codec),
+        payload = payload,
+    )
+}
+
+/// Negotiate with the offer and feed the viewer from `bus` in a background
+/// thread. Returns the viewer's id and the SDP answer.
+fn start_viewer(
+    sessions: &Arc<Sessions>,
+    name: &str,
+    bus: Arc<FrameBus>,
+    codec: OutputCodec,
+    payload: u8,
+    offer: &str,
+) -> Result<(u64, String)> {
+    let pipeline_str = pipeline_string(codec, payload);
+    debug!("WebRTC pipeline for '{}': {}", name, pipeline_str);
+
+    let pipeline = gstreamer::parse::launch(&pipeline_str)
+        .context("Failed to create WebRTC pipeline")?
+        .downcast::<gstreamer::Pipeline>()
+        .map_err(|_| anyhow::anyhow!("Failed to downcast to Pipeline"))?;
+
+    let answer = match negotiate(&pipeline, offer) {
+        Ok(answer) => answer,
+        Err(e) => {
+            pipeline.set_state(gstreamer::State::Null).ok();
+            return Err(e);
+        }
+    };
+
+    let appsrc = pipeline
+        .by_name("videosrc")
+        .ok_or_else(|| anyhow::anyhow!("WebRTC pipeline missing 'videosrc' element"))?
+        .dynamic_cast::<AppSrc>()
+        .map_err(|_| anyhow::anyhow!("Failed to cast to AppSrc"))?;
+
+    let id = sessions.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+    let running = Arc::new(AtomicBool::new(true));
+    sessions
+        .running
+        .lock()
+        .unwrap()
+        .insert(id, (name.to_string(), Arc::clone(&running)));
+
+    // A peer that goes away without a DELETE is noticed by ICE
+    if let Some(webrtc) = pipeline.by_name("webrtc") {
+        let running = Arc::clone(&running);
+        webrtc.connect_notify(Some("connection-state"), move |webrtc, _| {
+            let state = webrtc.property::<WebRTCPeerConnectionState>("connection-state");
+            if matches!(
+                state,
+                WebRTCPeerConnectionState::Failed | WebRTCPeerConnectionState::Closed
+            ) {
+                running.store(false, Ordering::SeqCst);
+            }
+        });
+    }
+
+    info!("WebRTC viewer {} of '{}' connected", id, name);
+
+    let name = name.to_string();
+    let sessions = Arc::clone(sessions);
+    let mut frames = bus.subscribe();
+    bus.viewer_joined();
+    bus.request_keyframe();
+    std::thread::spawn(move || {
+        while running.load(Ordering::SeqCst) {
+            // The browser can't decode until the first keyframe
+            let frame = match frames.recv_from_keyframe_timeout(STOP_POLL) {
+                Ok(frame) => frame,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            // Video only; forwarded audio is not sent
+            if frame.kind != StreamKind::Video {
+                continue;
+            }
+
+            if let Err(e) = appsrc.push_buffer(frame.output_buffer()) {
+                debug!("WebRTC viewer {} of '{}' stopped: {:?}", id, name, e);
+                break;
+            }
+        }
+
+        bus.viewer_left();
+        sessions.stop(&name, id);
+        pipeline.set_state(gstreamer::State::Null).ok();
+        info!("WebRTC viewer {} of '{}' left", id, name);
+    });
+
+    Ok((id, answer))
+}
+
+/// Start the pipeline, apply the offer and return webrtcbin's answer once
+/// ICE gathering completes (or times out)
+fn negotiate(pipeline: &gstreamer::Pipeline, offer: &str) -> Result<String> {
+    let webrtc = pipeline
+        .by_name("webrtc")
+        .ok_or_else(|| anyhow::anyhow!("WebRTC pipeline missing 'webrtc' element"))?;
+
+    pipeline
+        .set_state(gstreamer::State::Playing)
+        .map_err(|e| anyhow::anyhow!("Failed to start WebRTC pipeline: {:?}", e))?;
+
+    let offer = gstreamer_sdp::SDPMessage::parse_buffer(offer.as_bytes())
+        .map_err(|_| anyhow::anyhow!("Failed to parse SDP offer"))?;
+    let offer = WebRTCSessionDescription::new(WebRTCSDPType::Offer, offer);
+    webrtc.emit_by_name::<()>(
+        "set-remote-description",
+        &[&offer, &None::<gstreamer::Promise>],
+    );
+
+    let promise = gstreamer::Promise::new();
+    webrtc.emit_by_name::<()>("create-answer", &[&None::<gstreamer::Structure>, &promise]);
+    promise.wait();
+    let answer = promise
+        .get_reply()
+        .and_then(|reply| reply.get::<WebRTCSessionDescription>("answer").ok())
+        .ok_or_else(|| anyhow::anyhow!("webrtcbin did not create an answer"))?;
+    webrtc.emit_by_name::<()>(
+        "set-local-description",
+        &[&answer, &None::<gstreamer::Promise>],
+    );
+
+    let polls = GATHER_TIMEOUT.as_millis() / GATHER_POLL.as_millis();
+    for _ in 0..polls {
+        let state = webrtc.property::<WebRTCICEGatheringState>("ice-gathering-state");
+        if state == WebRTCICEGatheringState::Complete {
+            break;
+        }
+        std::thread::sleep(GATHER_POLL);
+    }
+
+    // The local description now lists the gathered candidates
+    let local = webrtc.property::<WebRTCSessionDescription>("local-description");
+    local
+        .sdp()
+        .as_text()
+        .map_err(|_| anyhow::anyhow!("Failed to serialize SDP answer"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Video section of a typical browser offer: VP8 first, then H.264 in
+    /// packetization modes 0 and 1, then H.265
+    const OFFER: &str = "v=0\r\n\
+        o=- 4611731400430051336 2 IN IP4 127.0.0.1\r\n\
+        s=-\r\n\
+        t=0 0\r\n\
+        a=group:BUNDLE 0 1\r\n\
+        m=audio 9 UDP/TLS/RTP/SAVPF 111\r\n\
+        a=mid:0\r\n\
+        a=recvonly\r\n\
+        a=rtpmap:111 opus/48000/2\r\n\
+        m=video 9 UDP/TLS/RTP/SAVPF 96 102 106 45\r\n\
+        a=mid:1\r\n\
+        a=recvonly\r\n\
+        a=rtpmap:96 VP8/90000\r\n\
+        a=rtpmap:102 H264/90000\r\n\
+        a=fmtp:102 level-asymmetry-allowed=1;packetization-mode=0;profile-level-id=42001f\r\n\
+        a=rtpmap:106 H264/90000\r\n\
+        a=fmtp:106 level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f\r\n\
+        a=rtpmap:45 H265/90000\r\n";
+
+    #[test]
+    fn test_offered_payload() {
+        assert_eq!(offered_payload(OFFER, OutputCodec::H264).unwrap(), 106);
+        assert_eq!(offered_payload(OFFER, OutputCodec::H265).unwrap(), 45);
+
+        // Mode 0 is still used when it's all the browser offers
+        let mode0 = OFFER.replace("a=rtpmap:106 H264/90000\r\n", "");
+        assert_eq!(offered_payload(&mode0, OutputCodec::H264).unwrap(), 102);
+
+        // Codecs from other sections don't count
+        let vp8_only = "m=audio 9 UDP/TLS/RTP/SAVPF 100\r\n\
+            a=rtpmap:100 H264/90000\r\n\
+            m=video 9 UDP/TLS/RTP/SAVPF 96\r\n\
+            a=rtpmap:96 VP8/90000\r\n";
+        let err = offered_payload(vp8_only, OutputCodec::H264)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "Offer does not accept H264 video");
+
+        let audio_only = OFFER.split("m=video").next().unwrap();
+        let err = offered_payload(audio_only, OutputCodec::H264)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(err, "Offer has no video section");
+    }
+
+    #[test]
+    fn test_pipeline_string_matches_payload() {
+        let h264 = pipeline_string(OutputCodec::H264, 106);
+        assert!(h264.contains("caps=video/x-h264"));
+        assert!(h264.contains("! h264parse ! rtph264pay config-interval=-1"));
+        assert!(h264
+            .contains("encoding-name=H264,payload=106,clock-rate=90000 ! webrtcbin name=webrtc"));
+
+        let h265 = pipeline_string(OutputCodec::H265, 45);
+        assert!(h265.contains("! h265parse ! rtph265pay"));
+        assert!(h265.contains("encoding-name=H265,payload=45,"));
+    }
+
+    #[test]
+    fn test_route() {
+        assert!(handles("/whep/cam1"));
+        assert!(!handles("/whepcam1"));
+
+        assert_eq!(route("/whep/cam1"), Some(Route::Source("cam1")));
+        assert_eq!(route("/whep/cam1/7"), Some(Route::Session("cam1", 7)));
+        assert_eq!(route("/whep/cam1/abc"), None);
+        assert_eq!(route("/whep/"), None);
+        assert_eq!(route("/whep"), None);
+    }
+
+    #[test]
+    fn test_stop_session() {
+        let sessions = Sessions::default();
+        let running = Arc::new(AtomicBool::new(true));
+        sessions
+            .running
+            .lock()
+            .unwrap()
+            .insert(3, ("cam1".to_string(), Arc::clone(&running)));
+
+        // Another source's path can't stop the viewer
+        assert!(!sessions.stop("cam2", 3));
+        assert!(running.load(Ordering::SeqCst));
+
+        assert!(sessions.stop("cam1", 3));
+        assert!(!running.load(Ordering::SeqCst));
+        assert!(!sessions.stop("cam1", 3));
+    }
+}